@@ -0,0 +1,205 @@
+use crate::error::Error;
+use crate::node_type::Offset;
+use crate::page_layout::{
+    HEADER_OFFSET, HEADER_ROOT_OFFSET, INTERNAL_NODE_HEADER_SIZE,
+    INTERNAL_NODE_NUM_CHILDREN_OFFSET, KEY_SIZE, LEAF_NODE_HEADER_SIZE, LEAF_NODE_NUM_PAIRS_OFFSET,
+    MAGIC, MAGIC_OFFSET, MAGIC_SIZE, NODE_TYPE_OFFSET, PAGE_SIZE, PTR_SIZE, VALUE_SIZE,
+};
+use memmap::{Mmap, MmapOptions};
+use std::convert::TryInto;
+use std::fs::File;
+use std::path::Path;
+use std::str;
+
+const INTERNAL_NODE_TYPE: u8 = 0x01;
+const LEAF_NODE_TYPE: u8 = 0x02;
+
+/// AnalyticsReader maps a tree file read-only and iterates its leaves in key order
+/// straight from the mapping, without deserializing nodes or going through a pager.
+/// It is meant for one-shot full scans; the file must not be modified while it is mapped.
+pub struct AnalyticsReader {
+    mmap: Mmap,
+}
+
+impl AnalyticsReader {
+    pub fn open(path: &Path) -> Result<AnalyticsReader, Error> {
+        let file = File::open(path)?;
+        // Safety: the mapping is read-only and callers must not modify the file
+        // while the reader is alive.
+        let mmap = unsafe { MmapOptions::new().map(&file)? };
+        if mmap.len() < PAGE_SIZE || mmap[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC_SIZE] != MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        Ok(AnalyticsReader { mmap })
+    }
+
+    /// leaves returns the leaves of the tree from left to right.
+    pub fn leaves(&self) -> Leaves<'_> {
+        let root = read_value(&self.mmap, HEADER_OFFSET + HEADER_ROOT_OFFSET);
+        Leaves {
+            data: &self.mmap,
+            stack: vec![root],
+            visited: 0,
+        }
+    }
+}
+
+/// Leaves iterates over the leaves of a mapped tree in left to right order.
+pub struct Leaves<'a> {
+    data: &'a [u8],
+    /// Offsets of the nodes left to visit, the next one last.
+    stack: Vec<usize>,
+    visited: usize,
+}
+
+impl<'a> Leaves<'a> {
+    fn page(&self, offset: usize) -> Result<&'a [u8], Error> {
+        let data = self.data;
+        if !offset.is_multiple_of(PAGE_SIZE) || offset == HEADER_OFFSET {
+            return Err(Error::Corrupted { offset });
+        }
+        data.get(offset..offset + PAGE_SIZE)
+            .ok_or(Error::Corrupted { offset })
+    }
+
+    fn next_leaf(&mut self) -> Result<Option<Leaf<'a>>, Error> {
+        while let Some(offset) = self.stack.pop() {
+            // A well formed tree never has more nodes than pages in the file.
+            self.visited += 1;
+            if self.visited > self.data.len() / PAGE_SIZE {
+                return Err(Error::Corrupted { offset });
+            }
+            let page = self.page(offset)?;
+            match page[NODE_TYPE_OFFSET] {
+                INTERNAL_NODE_TYPE => {
+                    let num_children = read_value(page, INTERNAL_NODE_NUM_CHILDREN_OFFSET);
+                    if INTERNAL_NODE_HEADER_SIZE + num_children * PTR_SIZE > PAGE_SIZE {
+                        return Err(Error::Corrupted { offset });
+                    }
+                    for idx in (0..num_children).rev() {
+                        self.stack
+                            .push(read_value(page, INTERNAL_NODE_HEADER_SIZE + idx * PTR_SIZE));
+                    }
+                }
+                LEAF_NODE_TYPE => {
+                    let len = read_value(page, LEAF_NODE_NUM_PAIRS_OFFSET);
+                    if LEAF_NODE_HEADER_SIZE + len * (KEY_SIZE + VALUE_SIZE) > PAGE_SIZE {
+                        return Err(Error::Corrupted { offset });
+                    }
+                    return Ok(Some(Leaf { offset, page, len }));
+                }
+                _ => return Err(Error::Corrupted { offset }),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = Result<Leaf<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_leaf() {
+            Ok(leaf) => leaf.map(Ok),
+            Err(e) => {
+                // Stop after the first error.
+                self.stack.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Leaf is a view of a leaf page borrowed from the mapping.
+pub struct Leaf<'a> {
+    offset: usize,
+    page: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Leaf<'a> {
+    pub fn offset(&self) -> Offset {
+        Offset(self.offset)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// pair returns the key and value at idx, borrowed from the mapping.
+    pub fn pair(&self, idx: usize) -> Result<(&'a str, &'a str), Error> {
+        if idx >= self.len {
+            return Err(Error::KeyNotFound);
+        }
+        let key_offset = LEAF_NODE_HEADER_SIZE + idx * (KEY_SIZE + VALUE_SIZE);
+        let value_offset = key_offset + KEY_SIZE;
+        Ok((
+            self.read_str(key_offset, KEY_SIZE)?,
+            self.read_str(value_offset, VALUE_SIZE)?,
+        ))
+    }
+
+    /// pairs returns the pairs of the leaf in key order.
+    pub fn pairs(&self) -> impl Iterator<Item = Result<(&'a str, &'a str), Error>> + '_ {
+        (0..self.len).map(move |idx| self.pair(idx))
+    }
+
+    fn read_str(&self, offset: usize, size: usize) -> Result<&'a str, Error> {
+        let page: &'a [u8] = self.page;
+        let raw = &page[offset..offset + size];
+        match str::from_utf8(raw) {
+            // Trim leading or trailing zeros.
+            Ok(s) => Ok(s.trim_matches(char::from(0))),
+            Err(e) => Err(Error::InvalidUtf8 {
+                offset: self.offset + offset + e.valid_up_to(),
+            }),
+        }
+    }
+}
+
+/// read_value reads a BigEndian usize at offset.
+fn read_value(data: &[u8], offset: usize) -> usize {
+    let bytes: [u8; PTR_SIZE] = data[offset..offset + PTR_SIZE]
+        .try_into()
+        .unwrap_or([0x00; PTR_SIZE]);
+    usize::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn analytics_reader_iterates_leaves_in_order() -> Result<(), Error> {
+        use crate::analytics::AnalyticsReader;
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let path = Path::new("/tmp/db_analytics");
+        let mut btree = BTreeBuilder::new().path(path).b_parameter(2).build()?;
+        for i in (0..30).rev() {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), format!("v{}", i)))?;
+        }
+
+        let reader = AnalyticsReader::open(path)?;
+        let mut pairs = Vec::new();
+        for leaf in reader.leaves() {
+            let leaf = leaf?;
+            assert!(!leaf.is_empty());
+            for pair in leaf.pairs() {
+                let (key, value) = pair?;
+                pairs.push((key.to_string(), value.to_string()));
+            }
+        }
+        let expected: Vec<(String, String)> = (0..30)
+            .map(|i| (format!("k{:02}", i), format!("v{}", i)))
+            .collect();
+        assert_eq!(pairs, expected);
+        Ok(())
+    }
+}
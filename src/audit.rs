@@ -0,0 +1,177 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::record_log::{self, RecordLog};
+use std::ops::{Bound, RangeBounds};
+
+/// Mutation is the kind of change recorded by an AuditEntry.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mutation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Mutation {
+    fn code(&self) -> char {
+        match self {
+            Mutation::Insert => 'I',
+            Mutation::Update => 'U',
+            Mutation::Delete => 'D',
+        }
+    }
+
+    fn from_code(code: &str) -> Result<Mutation, Error> {
+        match code {
+            "I" => Ok(Mutation::Insert),
+            "U" => Ok(Mutation::Update),
+            "D" => Ok(Mutation::Delete),
+            _ => Err(Error::UnexpectedError),
+        }
+    }
+}
+
+/// AuditEntry records a single mutation of an audited tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AuditEntry {
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub mutation: Mutation,
+    pub key: String,
+    /// Caller supplied context current at the time of the mutation, see AuditLog::set_context.
+    pub context: String,
+}
+
+/// AuditLog appends an entry for every mutation of a tree to a separate audit tree,
+/// see BTree::set_audit_log. Entries are written after the mutation succeeds and are
+/// never modified.
+pub struct AuditLog {
+    log: RecordLog,
+    context: String,
+}
+
+impl AuditLog {
+    /// new opens an audit log stored in tree, resuming the sequence numbers stored in it.
+    pub fn new(tree: BTree) -> Result<AuditLog, Error> {
+        Ok(AuditLog {
+            log: RecordLog::new(tree)?,
+            context: String::new(),
+        })
+    }
+
+    /// set_context sets the context, e.g. the acting user, recorded with the following mutations.
+    pub fn set_context(&mut self, context: &str) {
+        self.context = context.to_string();
+    }
+
+    pub(crate) fn record(&mut self, mutation: Mutation, key: &str) -> Result<(), Error> {
+        let timestamp = record_log::now_millis();
+        let encoded = format!(
+            "{:x}|{}|{}|{}{}",
+            timestamp,
+            mutation.code(),
+            key.len(),
+            key,
+            self.context
+        );
+        self.log.append(&encoded)?;
+        Ok(())
+    }
+
+    /// query returns the entries made within time_range (in milliseconds since the Unix epoch)
+    /// for keys starting with prefix, oldest first. Entries are appended in time order,
+    /// so reading stops at the first entry made after time_range.
+    pub fn query<R: RangeBounds<u64>>(
+        &mut self,
+        time_range: R,
+        prefix: &str,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        let mut entries = Vec::new();
+        for record in self.log.iter() {
+            let (seq, encoded) = record?;
+            let entry = decode(seq, &encoded)?;
+            let past_end = match time_range.end_bound() {
+                Bound::Included(end) => entry.timestamp > *end,
+                Bound::Excluded(end) => entry.timestamp >= *end,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                break;
+            }
+            if time_range.contains(&entry.timestamp) && entry.key.starts_with(prefix) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn into_inner(self) -> BTree {
+        self.log.into_inner()
+    }
+}
+
+fn decode(seq: u64, encoded: &str) -> Result<AuditEntry, Error> {
+    let mut fields = encoded.splitn(4, '|');
+    let mut next = || fields.next().ok_or(Error::UnexpectedError);
+    let timestamp = u64::from_str_radix(next()?, 16).map_err(|_| Error::UnexpectedError)?;
+    let mutation = Mutation::from_code(next()?)?;
+    let key_len: usize = next()?.parse().map_err(|_| Error::UnexpectedError)?;
+    let rest = next()?;
+    let key = rest.get(..key_len).ok_or(Error::UnexpectedError)?;
+    let context = &rest[key_len..];
+    Ok(AuditEntry {
+        seq,
+        timestamp,
+        mutation,
+        key: key.to_string(),
+        context: context.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn audit_log_records_mutations() -> Result<(), Error> {
+        use crate::audit::{AuditLog, Mutation};
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_audited"))
+            .b_parameter(2)
+            .build()?;
+        let audit_tree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_audit_log"))
+            .b_parameter(2)
+            .build()?;
+        btree.set_audit_log(AuditLog::new(audit_tree)?);
+
+        btree.audit_log().unwrap().set_context("alice@ops");
+        btree.insert(KeyValuePair::new("user:1".to_string(), "a".to_string()))?;
+        btree.insert(KeyValuePair::new("cfg:x".to_string(), "b".to_string()))?;
+        btree.audit_log().unwrap().set_context("bob");
+        btree.update_range(.., |_, value| Some(value.to_uppercase()))?;
+        btree.delete(Key("user:1".to_string()))?;
+
+        let log = btree.audit_log().unwrap();
+        let entries = log.query(.., "user:")?;
+        let summary: Vec<(u64, Mutation, &str, &str)> = entries
+            .iter()
+            .map(|e| (e.seq, e.mutation, e.key.as_str(), e.context.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0, Mutation::Insert, "user:1", "alice@ops"),
+                (3, Mutation::Update, "user:1", "bob"),
+                (4, Mutation::Delete, "user:1", "bob"),
+            ]
+        );
+        assert_eq!(log.query(..1, "")?, vec![]);
+        assert_eq!(log.query(.., "")?.len(), 5);
+        Ok(())
+    }
+}
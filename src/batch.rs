@@ -0,0 +1,46 @@
+use crate::node_type::{Key, KeyValuePair};
+
+/// BatchOp is a single mutation of a WriteBatch.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BatchOp {
+    Put(KeyValuePair),
+    Delete(Key),
+}
+
+/// WriteBatch collects puts and deletes to be applied together by BTree::apply_batch.
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    pub fn put(&mut self, kv: KeyValuePair) -> &mut WriteBatch {
+        self.ops.push(BatchOp::Put(kv));
+        self
+    }
+
+    pub fn delete(&mut self, key: Key) -> &mut WriteBatch {
+        self.ops.push(BatchOp::Delete(key));
+        self
+    }
+
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+}
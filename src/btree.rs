@@ -1,43 +1,157 @@
+use crate::audit::{AuditLog, Mutation};
+use crate::batch::{BatchOp, WriteBatch};
+use crate::check::{self, CheckLevel, Health};
+use crate::cursor::Cursor;
+use crate::entry::Entry;
 use crate::error::Error;
+use crate::expiry::Expiry;
+use crate::export::{self, ExportFilter};
+use crate::header::Header;
+use crate::hot_keys::HotKeyDetector;
+use crate::key_policy::{KeyPolicy, KeyValidator};
+use crate::merge::MergeOperator;
+use crate::migrations::Migrations;
 use crate::node::Node;
-use crate::node_type::{Key, KeyValuePair, NodeType, Offset};
+use crate::node_type::{self, Key, KeyValuePair, NodeType, Offset};
 use crate::page::Page;
-use crate::pager::Pager;
+use crate::page_layout::{
+    HEADER_OFFSET, KEY_SIZE, LEAF_NODE_HEADER_SIZE, LEAF_NODE_NUM_PAIRS_OFFSET, NODE_TYPE_OFFSET,
+    PAGE_SIZE, VALUE_SIZE,
+};
+use crate::pager::{self, Pager};
+use crate::quota::{Quota, QuotaUsage, Quotas};
+use crate::scan::{self, Join, KeyRange, Keys, Leaves, Overlap, Scan, ScanChunks, Values};
+use crate::scoped::Scoped;
+use crate::stats::{
+    AccessSampler, IoStats, LeafHeat, LifetimeStats, Pages, PrefixRate, WriteRates,
+};
+use crate::trash::Trash;
+use crate::workload::Workload;
+use crate::write_mode::WriteMode;
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::path::Path;
+use std::fs;
+use std::io::Write;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{Duration, Instant};
 
 /// B+Tree properties.
+#[cfg(not(feature = "small-pages"))]
 pub const MAX_BRANCHING_FACTOR: usize = 200;
+/// A 512 byte page holds at most 2 * 12 - 1 pairs.
+#[cfg(feature = "small-pages")]
+pub const MAX_BRANCHING_FACTOR: usize = 24;
+
+/// b parameter of BTreeBuilder::default, the largest b whose nodes fit in a page.
+pub const DEFAULT_B_PARAMETER: usize = MAX_BRANCHING_FACTOR / 2;
+/// Name of the tree file BTree::open_default_in keeps in its directory.
+pub const DEFAULT_FILE_NAME: &str = "b_tree.db";
 pub const NODE_KEYS_LIMIT: usize = MAX_BRANCHING_FACTOR - 1;
 
+/// Number of pairs read at a time by BTree::copy_range.
+const COPY_CHUNK_SIZE: usize = 256;
+
 /// BTree struct represents an on-disk B+tree.
 /// Each node is persisted in the table file, the leaf nodes contain the values.
 pub struct BTree {
     pager: Pager,
     b: usize,
     root_offset: Offset,
+    /// Format version of the stored values, see BTree::migrate.
+    value_version: usize,
+    key_policy: KeyPolicy,
+    key_validator: KeyValidator,
+    write_mode: WriteMode,
+    quotas: Quotas,
+    sampler: AccessSampler,
+    hot_keys: Option<HotKeyDetector>,
+    write_rates: Option<WriteRates>,
+    audit_log: Option<Box<AuditLog>>,
+    trash: Option<Box<Trash>>,
+    expiry: Option<Box<Expiry>>,
+    /// Bytes of keys and values written by the user.
+    logical_bytes: usize,
+    /// Lifetime counters, with the byte counts as of opening the file.
+    lifetime: LifetimeStats,
+    /// Number of levels of the tree, None until computed after the root changes.
+    height: Option<usize>,
+    /// Number of pairs in the tree.
+    len: usize,
+    /// Whether the tree was modified since the header last recorded len.
+    /// The header marks the count as stale while this is set.
+    len_dirty: bool,
+    /// Generation of the file this handle reads, see BTree::replace_with.
+    generation: usize,
+    stale_checks: bool,
+    revalidate_every: Option<Duration>,
+    /// When the header was last read, see BTreeBuilder::revalidate_every.
+    validated_at: Instant,
+    merge_operator: Option<MergeOperator>,
+    /// Leave underflowing nodes in place on delete, see BTreeBuilder::deferred_merges.
+    deferred_merges: bool,
 }
 
 /// BtreeBuilder is a Builder for the BTree struct.
 pub struct BTreeBuilder {
-    /// Path to the tree file.
-    path: &'static Path,
+    /// Path to the tree file, required to build or open a tree.
+    path: Option<PathBuf>,
     /// The BTree parameter, an inner node contains no more than 2*b-1 keys and no less than b-1 keys
     /// and no more than 2*b children and no less than b children.
     b: usize,
+    /// What to do with keys longer than KEY_SIZE.
+    key_policy: KeyPolicy,
+    /// Naming rules enforced on inserted keys.
+    key_validator: KeyValidator,
+    /// Whether stored pairs may be overwritten or deleted.
+    write_mode: WriteMode,
+    /// Quotas enforced per key prefix.
+    quotas: Quotas,
+    /// Sample one of every access_sampling leaf accesses, 0 disables sampling.
+    access_sampling: usize,
+    /// Detects keys receiving pathological write rates.
+    hot_keys: Option<HotKeyDetector>,
+    /// Tracks write rates per key prefix.
+    write_rates: Option<WriteRates>,
+    /// Zero pages as they are freed.
+    secure_wipe: bool,
+    /// Cap on the size of the file in bytes.
+    max_size: Option<usize>,
+    /// Verify the generation of the file before every operation.
+    stale_checks: bool,
+    /// Reread the header before lookups once it is older than this.
+    revalidate_every: Option<Duration>,
+    /// Combines merge operands with stored values.
+    merge_operator: Option<MergeOperator>,
+    /// Leave underflowing nodes in place on delete.
+    deferred_merges: bool,
 }
 
 impl BTreeBuilder {
     pub fn new() -> BTreeBuilder {
         BTreeBuilder {
-            path: Path::new(""),
+            path: None,
             b: 0,
+            key_policy: KeyPolicy::default(),
+            key_validator: KeyValidator::default(),
+            write_mode: WriteMode::default(),
+            quotas: Quotas::default(),
+            access_sampling: 0,
+            hot_keys: None,
+            write_rates: None,
+            secure_wipe: false,
+            max_size: None,
+            stale_checks: false,
+            revalidate_every: None,
+            merge_operator: None,
+            deferred_merges: false,
         }
     }
 
     pub fn path(mut self, path: &'static Path) -> BTreeBuilder {
-        self.path = path;
+        self.path = Some(path.to_path_buf());
         self
     }
 
@@ -46,37 +160,208 @@ impl BTreeBuilder {
         self
     }
 
+    /// tuned_for sets the b parameter suited to workload, see Workload.
+    pub fn tuned_for(self, workload: Workload) -> BTreeBuilder {
+        self.b_parameter(workload.b_parameter())
+    }
+
+    pub fn key_policy(mut self, key_policy: KeyPolicy) -> BTreeBuilder {
+        self.key_policy = key_policy;
+        self
+    }
+
+    /// key_validator enforces naming rules on every inserted key,
+    /// inserts breaking them fail with Error::InvalidKey.
+    pub fn key_validator(mut self, key_validator: KeyValidator) -> BTreeBuilder {
+        self.key_validator = key_validator;
+        self
+    }
+
+    /// merge_operator sets the function BTree::merge combines stored values with.
+    pub fn merge_operator(mut self, merge_operator: MergeOperator) -> BTreeBuilder {
+        self.merge_operator = Some(merge_operator);
+        self
+    }
+
+    /// deferred_merges makes delete and delete_range leave nodes that fall below b - 1 keys
+    /// in place instead of borrowing from or merging with a sibling, so a delete only writes
+    /// the leaf it removes a pair from. Lookups and scans work the same over sparse nodes.
+    /// The space they waste is reclaimed by calling BTree::merge_sparse_nodes periodically.
+    pub fn deferred_merges(mut self, deferred_merges: bool) -> BTreeBuilder {
+        self.deferred_merges = deferred_merges;
+        self
+    }
+
+    /// write_mode restricts changes to pairs already stored in the tree.
+    pub fn write_mode(mut self, write_mode: WriteMode) -> BTreeBuilder {
+        self.write_mode = write_mode;
+        self
+    }
+
+    /// quota limits the entries or bytes stored under keys starting with prefix,
+    /// inserts exceeding it fail with Error::QuotaExceeded.
+    pub fn quota(mut self, prefix: &str, quota: Quota) -> BTreeBuilder {
+        self.quotas.add(prefix.to_string(), quota);
+        self
+    }
+
+    /// access_sampling enables sampling one of every rate leaf accesses,
+    /// see BTree::heatmap.
+    pub fn access_sampling(mut self, rate: usize) -> BTreeBuilder {
+        self.access_sampling = rate;
+        self
+    }
+
+    /// hot_key_detector installs a detector notified of every write to the tree.
+    pub fn hot_key_detector(mut self, detector: HotKeyDetector) -> BTreeBuilder {
+        self.hot_keys = Some(detector);
+        self
+    }
+
+    /// write_rates installs a tracker of write rates per key prefix, see BTree::write_rates.
+    pub fn write_rates(mut self, write_rates: WriteRates) -> BTreeBuilder {
+        self.write_rates = Some(write_rates);
+        self
+    }
+
+    /// secure_wipe zeroes pages on disk as they are freed,
+    /// so that removed data does not linger in the file.
+    pub fn secure_wipe(mut self, secure_wipe: bool) -> BTreeBuilder {
+        self.secure_wipe = secure_wipe;
+        self
+    }
+
+    /// max_size caps the size of the tree file in bytes. Writes that would grow
+    /// the file past it fail with Error::CapacityExceeded, leaving the tree unchanged.
+    pub fn max_size(mut self, max_size: usize) -> BTreeBuilder {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// stale_checks makes search, insert and delete verify first that the file at path
+    /// is still the one this handle opened, failing with Error::StaleHandle once another
+    /// handle replaced it. Each check reads the header of the file at path.
+    pub fn stale_checks(mut self, stale_checks: bool) -> BTreeBuilder {
+        self.stale_checks = stale_checks;
+        self
+    }
+
+    /// revalidate_every is meant for handles reading a file written through another handle.
+    /// Lookups reread the header first whenever it was last read more than every ago,
+    /// picking up the writer's current root, so their view of the tree lags the writer by at
+    /// most every plus the time of a lookup. Between rereads a lookup may miss pairs the writer
+    /// moved by splitting or merging nodes, since nodes are rewritten in place.
+    pub fn revalidate_every(mut self, every: Duration) -> BTreeBuilder {
+        self.revalidate_every = Some(every);
+        self
+    }
+
     pub fn build(&self) -> Result<BTree, Error> {
-        if self.path.to_string_lossy() == "" {
-            return Err(Error::UnexpectedError);
-        }
+        let path = self.require_path()?;
         if self.b == 0 {
             return Err(Error::UnexpectedError);
         }
 
-        let mut pager = Pager::new(&self.path)?;
+        let mut pager = Pager::new(path)?;
+        // Reserve the first page for the header.
+        pager.write_page(Page::new([0x00; PAGE_SIZE]))?;
         let root = Node::new(NodeType::Leaf(vec![]), true, None);
         let root_offset = pager.write_page(Page::try_from(&root)?)?;
-        Ok(BTree {
-            pager,
-            b: self.b,
+        let header = Header {
             root_offset,
-        })
+            b: self.b,
+            value_version: 0,
+            lifetime: LifetimeStats::default(),
+            len: Some(0),
+            generation: 0,
+        };
+        let mut btree = self.assemble(pager, header);
+        btree.write_header()?;
+        Ok(btree)
+    }
+
+    /// open_with_check opens the existing tree file at path after validating it according
+    /// to level. The b parameter is read from the file, the other options apply as usual.
+    /// Pages orphaned by an unclean shutdown are only reclaimed by BTree::gc.
+    pub fn open_with_check(&self, level: CheckLevel) -> Result<BTree, Error> {
+        let path = self.require_path()?;
+
+        let mut pager = Pager::open(path)?;
+        let header = Header::try_from(pager.get_page(&Offset(HEADER_OFFSET))?)?;
+        check::check_tree(&mut pager, &header, level, !self.deferred_merges)?;
+        let len = header.len;
+        let mut btree = self.assemble(pager, header);
+        btree.restore_state(len)?;
+        Ok(btree)
+    }
+
+    /// require_path returns the path to the tree file, failing with Error::PathRequired
+    /// if none was set.
+    fn require_path(&self) -> Result<&Path, Error> {
+        self.path
+            .as_deref()
+            .filter(|path| !path.as_os_str().is_empty())
+            .ok_or(Error::PathRequired)
+    }
+
+    fn assemble(&self, mut pager: Pager, header: Header) -> BTree {
+        pager.set_secure_wipe(self.secure_wipe);
+        pager.set_max_size(self.max_size);
+        BTree {
+            pager,
+            b: header.b,
+            root_offset: header.root_offset,
+            value_version: header.value_version,
+            key_policy: self.key_policy,
+            key_validator: self.key_validator.clone(),
+            write_mode: self.write_mode,
+            quotas: self.quotas.clone(),
+            sampler: AccessSampler::new(self.access_sampling),
+            hot_keys: self.hot_keys.clone(),
+            write_rates: self.write_rates.clone(),
+            audit_log: None,
+            trash: None,
+            expiry: None,
+            logical_bytes: 0,
+            lifetime: header.lifetime,
+            height: None,
+            len: header.len.unwrap_or(0),
+            len_dirty: false,
+            generation: header.generation,
+            stale_checks: self.stale_checks,
+            revalidate_every: self.revalidate_every,
+            validated_at: Instant::now(),
+            merge_operator: self.merge_operator.clone(),
+            deferred_merges: self.deferred_merges,
+        }
     }
 }
 
 impl Default for BTreeBuilder {
-    // A default BTreeBuilder provides a builder with:
-    /// - b parameter set to 200
-    /// - path set to '/tmp/db'.
+    /// A default BTreeBuilder has its b parameter set to DEFAULT_B_PARAMETER and no path,
+    /// building it fails with Error::PathRequired until one is set.
     fn default() -> Self {
-        BTreeBuilder::new()
-            .b_parameter(200)
-            .path(Path::new("/tmp/db"))
+        BTreeBuilder::new().b_parameter(DEFAULT_B_PARAMETER)
     }
 }
 
 impl BTree {
+    /// open_default_in opens the tree file named DEFAULT_FILE_NAME in dir with the
+    /// options of BTreeBuilder::default, creating it if it does not exist yet.
+    pub fn open_default_in(dir: &Path) -> Result<BTree, Error> {
+        let path = dir.join(DEFAULT_FILE_NAME);
+        let exists = path.exists();
+        let builder = BTreeBuilder {
+            path: Some(path),
+            ..BTreeBuilder::default()
+        };
+        if exists {
+            builder.open_with_check(CheckLevel::Header)
+        } else {
+            builder.build()
+        }
+    }
+
     fn is_node_full(&self, node: &Node) -> Result<bool, Error> {
         match &node.node_type {
             NodeType::Leaf(pairs) => Ok(pairs.len() == (2 * self.b - 1)),
@@ -95,17 +380,356 @@ impl BTree {
     }
 
     /// insert a key value pair possibly splitting nodes along the way.
-    pub fn insert(&mut self, kv: KeyValuePair) -> Result<(), Error> {
+    /// If the key is already present its value is replaced,
+    /// and the previously stored pair is returned.
+    pub fn insert(&mut self, kv: KeyValuePair) -> Result<Option<KeyValuePair>, Error> {
+        // Reject an oversized value before any node is split for it.
+        if kv.value.len() > VALUE_SIZE {
+            return Err(Error::ValueOverflowError);
+        }
+        let KeyValuePair { key, value } = kv;
+        self.insert_with(key, |_| Ok(value))
+    }
+
+    /// apply_batch applies the mutations of batch in order and flushes the file once at
+    /// the end; deletes of absent keys are ignored. The whole batch is checked against
+    /// the stored pairs before anything is written, so a bad key or value, a quota or a
+    /// write mode rejects it unapplied. There is no write-ahead log yet, so running past
+    /// BTreeBuilder::max_size with Error::CapacityExceeded, an I/O error or a crash
+    /// halfway leaves the earlier mutations applied.
+    pub fn apply_batch(&mut self, batch: &WriteBatch) -> Result<(), Error> {
+        self.check_batch(batch)?;
+        for op in batch.ops() {
+            self.apply_op(op)?;
+        }
+        self.flush_batch()
+    }
+
+    /// check_batch replays the mutations of batch over the pairs they touch and a copy
+    /// of the quotas without writing anything, failing with the error the first
+    /// rejected mutation would raise once applied.
+    fn check_batch(&mut self, batch: &WriteBatch) -> Result<(), Error> {
+        let mut keys = Vec::with_capacity(batch.ops().len());
+        for op in batch.ops() {
+            let key = match op {
+                BatchOp::Put(kv) => {
+                    let key = self.key_policy.apply_write(&kv.key)?;
+                    self.key_validator.validate(&key)?;
+                    if kv.value.len() > VALUE_SIZE {
+                        return Err(Error::ValueOverflowError);
+                    }
+                    key
+                }
+                BatchOp::Delete(key) => {
+                    let key = self.key_policy.apply(&key.0)?;
+                    if !self.write_mode.allows_delete() {
+                        return Err(Error::Immutable { key });
+                    }
+                    key
+                }
+            };
+            keys.push(key);
+        }
+        if self.quotas.is_empty() && self.write_mode.allows_overwrite() {
+            return Ok(());
+        }
+        // Expired pairs are looked up too, an insert replaces them like any other pair.
+        self.ensure_current()?;
+        self.revalidate()?;
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+        let mut found = vec![None; keys.len()];
+        let root_offset = self.root_offset;
+        self.get_many_in(root_offset, &keys, &order, &mut found)?;
+        let mut staged: HashMap<&str, Option<KeyValuePair>> = HashMap::new();
+        let mut quotas = self.quotas.clone();
+        for ((op, key), found) in batch.ops().iter().zip(&keys).zip(found) {
+            let current = staged.entry(key.as_str()).or_insert(found);
+            match op {
+                BatchOp::Put(kv) => {
+                    let kv = KeyValuePair::new(key.clone(), kv.value.clone());
+                    match current {
+                        Some(_) if !self.write_mode.allows_overwrite() => {
+                            return Err(Error::KeyAlreadyExists)
+                        }
+                        Some(old) => {
+                            quotas.check_replace(old, &kv)?;
+                            quotas.record_delete(old);
+                        }
+                        None => quotas.check(&kv)?,
+                    }
+                    quotas.record_insert(&kv);
+                    *current = Some(kv);
+                }
+                BatchOp::Delete(_) => {
+                    if let Some(old) = current.take() {
+                        quotas.record_delete(&old);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// apply_op applies a single mutation of a batch without flushing the file,
+    /// a delete of an absent key is ignored.
+    pub(crate) fn apply_op(&mut self, op: &BatchOp) -> Result<(), Error> {
+        match op {
+            BatchOp::Put(kv) => {
+                self.insert(kv.clone())?;
+            }
+            BatchOp::Delete(key) => match self.delete(key.clone()) {
+                Ok(()) | Err(Error::KeyNotFound) => (),
+                Err(e) => return Err(e),
+            },
+        }
+        Ok(())
+    }
+
+    /// flush_batch records the stats and flushes the file once the mutations
+    /// of a batch were applied.
+    pub(crate) fn flush_batch(&mut self) -> Result<(), Error> {
+        self.persist_stats()?;
+        self.pager.sync()
+    }
+
+    /// insert_if_absent inserts kv only if its key is not present yet,
+    /// returning whether it was inserted.
+    pub fn insert_if_absent(&mut self, kv: KeyValuePair) -> Result<bool, Error> {
+        if self.contains_key(&kv.key)? {
+            return Ok(false);
+        }
+        self.insert(kv)?;
+        Ok(true)
+    }
+
+    /// insert_all inserts every pair of pairs, replacing the values of existing keys.
+    /// The pairs are sorted first so that each descent fills a leaf with every pair
+    /// that belongs to it, writing the leaf once. When a key appears more than once the
+    /// last of its pairs wins. Every pair is validated before the tree is modified, but
+    /// pairs stored before a quota or write mode error are kept.
+    /// Returns the number of pairs stored.
+    pub fn insert_all<I: IntoIterator<Item = KeyValuePair>>(
+        &mut self,
+        pairs: I,
+    ) -> Result<usize, Error> {
+        self.ensure_current()?;
+        self.revalidate()?;
+        let mut sorted = Vec::new();
+        for kv in pairs {
+            if kv.value.len() > VALUE_SIZE {
+                return Err(Error::ValueOverflowError);
+            }
+            let key = self.key_policy.apply_write(&kv.key)?;
+            self.key_validator.validate(&key)?;
+            sorted.push(KeyValuePair::new(key, kv.value));
+        }
+        sorted.sort_by(|a, b| a.key.cmp(&b.key));
+        // The sort is stable, so the last pair of every key is the first one once reversed.
+        sorted.reverse();
+        sorted.dedup_by(|a, b| a.key == b.key);
+        sorted.reverse();
+
+        let stored = sorted.len();
+        let mut pairs = sorted.into_iter().peekable();
+        while let Some(first) = pairs.peek() {
+            let key = first.key.clone();
+            self.reserve_for_insert(&key, false)?;
+            let root = self.prepare_insert()?;
+            let root_offset = self.root_offset;
+            let (leaf_offset, mut leaf, upper) = self.descend_to_leaf(root, root_offset, &key)?;
+            let leaf_pairs = match &mut leaf.node_type {
+                NodeType::Leaf(pairs) => pairs,
+                _ => return Err(Error::UnexpectedError),
+            };
+            let capacity = 2 * self.b - 1;
+            let mut res = Ok(());
+            let mut mutations = Vec::new();
+            loop {
+                // Keep filling the leaf while the next key belongs to it and fits in it.
+                let next = pairs.next_if(|kv| {
+                    upper.as_ref().is_none_or(|upper| kv.key <= upper.0)
+                        && (leaf_pairs.len() < capacity
+                            || leaf_pairs.binary_search_by(|p| p.key.cmp(&kv.key)).is_ok())
+                });
+                let kv = match next {
+                    Some(kv) => kv,
+                    None => break,
+                };
+                let found = leaf_pairs.binary_search_by(|p| p.key.cmp(&kv.key));
+                let old = found.ok().map(|idx| &leaf_pairs[idx]);
+                if let Err(e) = self.accept_insert(old, &kv) {
+                    res = Err(e);
+                    break;
+                }
+                mutations.push(match found {
+                    Ok(_) => (Mutation::Update, kv.key.clone()),
+                    Err(_) => (Mutation::Insert, kv.key.clone()),
+                });
+                match found {
+                    Ok(idx) => leaf_pairs[idx] = kv,
+                    Err(idx) => leaf_pairs.insert(idx, kv),
+                }
+            }
+            if !mutations.is_empty() {
+                self.sampler.record_write(&leaf_offset);
+                self.pager
+                    .write_page_at_offset(Page::try_from(&leaf)?, &leaf_offset)?;
+            }
+            for (mutation, key) in mutations {
+                if let Mutation::Insert = mutation {
+                    self.len += 1;
+                }
+                self.audit(mutation, &key)?;
+            }
+            res?;
+        }
+        Ok(stored)
+    }
+
+    /// upsert stores the value computed by f from the current value of key, or from None
+    /// if the key is absent, reading and writing the leaf in a single descent.
+    /// Returns the previously stored pair.
+    pub fn upsert<F>(&mut self, key: &str, f: F) -> Result<Option<KeyValuePair>, Error>
+    where
+        F: FnOnce(Option<&str>) -> String,
+    {
+        self.insert_with(key.to_string(), |old| Ok(f(old)))
+    }
+
+    /// increment adds delta to the integer stored under key and returns the sum,
+    /// reading and writing the leaf in a single descent. A missing key is created at delta.
+    /// Fails with InvalidNumber if the stored value is not an integer or the sum overflows.
+    pub fn increment(&mut self, key: &str, delta: i64) -> Result<i64, Error> {
+        let mut sum = delta;
+        self.insert_with(key.to_string(), |old| {
+            if let Some(old) = old {
+                sum = old
+                    .parse::<i64>()
+                    .ok()
+                    .and_then(|n| n.checked_add(delta))
+                    .ok_or_else(|| Error::InvalidNumber {
+                        key: key.to_string(),
+                    })?;
+            }
+            Ok(sum.to_string())
+        })?;
+        Ok(sum)
+    }
+
+    /// compare_and_swap stores new under key, or removes key if new is None, but only if
+    /// the value currently stored under key equals expected, None meaning the key is absent.
+    /// Returns whether the swap happened; the tree is left unchanged otherwise.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&str>,
+        new: Option<&str>,
+    ) -> Result<bool, Error> {
+        let current = self.get(key)?.map(|kv| kv.value);
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => {
+                self.insert(KeyValuePair::new(key.to_string(), value.to_string()))?;
+            }
+            None if current.is_some() => self.delete(Key(key.to_string()))?,
+            None => (),
+        }
+        Ok(true)
+    }
+
+    /// insert_with descends to the leaf that holds key, splitting full nodes along the way,
+    /// and stores the value computed by f from the value currently stored under key.
+    fn insert_with<F>(&mut self, key: String, f: F) -> Result<Option<KeyValuePair>, Error>
+    where
+        F: FnOnce(Option<&str>) -> Result<String, Error>,
+    {
+        self.ensure_current()?;
+        self.revalidate()?;
+        let key = self.key_policy.apply_write(&key)?;
+        self.key_validator.validate(&key)?;
+        let (offset, node) = match self.reserve_for_insert(&key, true)? {
+            Some(leaf) => leaf,
+            None => {
+                let root = self.prepare_insert()?;
+                (self.root_offset, root)
+            }
+        };
+        let old = self.insert_non_full(node, offset, key.clone(), f)?;
+        let mutation = match old {
+            Some(_) => Mutation::Update,
+            None => {
+                self.len += 1;
+                Mutation::Insert
+            }
+        };
+        self.audit(mutation, &key)?;
+        Ok(old)
+    }
+
+    /// reserve_for_insert makes sure the splits on the way to the leaf of key cannot run out
+    /// of pages halfway. Nothing is reserved while the free pages cover the worst case of
+    /// every node on the path splitting; otherwise the file only grows by the pages the full
+    /// nodes on the path need. If in_place is set and the leaf already holds key, nothing
+    /// is reserved and the leaf is returned so the pair can be overwritten without splitting.
+    fn reserve_for_insert(
+        &mut self,
+        key: &str,
+        in_place: bool,
+    ) -> Result<Option<(Offset, Node)>, Error> {
+        let height = self.height()?;
+        if self.pager.free_pages() > height {
+            return Ok(None);
+        }
+        let mut offset = self.root_offset;
+        let mut node = self.read_node(&offset)?;
+        // Splitting a full root takes a page for the new root as well.
+        let mut needed = if self.is_node_full(&node)? { 1 } else { 0 };
+        loop {
+            if self.is_node_full(&node)? {
+                needed += 1;
+            }
+            let child_offset = match &node.node_type {
+                NodeType::Internal(children, keys) => {
+                    let idx = node_type::child_index(keys, key);
+                    *children.get(idx).ok_or(Error::UnexpectedError)?
+                }
+                NodeType::Leaf(pairs) => {
+                    if in_place
+                        && pairs
+                            .binary_search_by(|kv| kv.key.as_str().cmp(key))
+                            .is_ok()
+                    {
+                        return Ok(Some((offset, node)));
+                    }
+                    break;
+                }
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            };
+            offset = child_offset;
+            node = self.read_node(&offset)?;
+        }
+        self.pager.reserve(needed)?;
+        Ok(None)
+    }
+
+    /// prepare_insert makes room for one more pair below the root, splitting the root
+    /// if it is full, and returns the root. The pages the splits need must have been
+    /// reserved with reserve_for_insert.
+    fn prepare_insert(&mut self) -> Result<Node, Error> {
+        self.mark_len_dirty()?;
         let root_page = self.pager.get_page(&self.root_offset)?;
         let mut root = Node::try_from(root_page)?;
         if self.is_node_full(&root)? {
-            let mut old_root = &mut root;
-            let old_root_offset = self.root_offset.clone();
+            let old_root = &mut root;
+            let old_root_offset = self.root_offset;
             let mut new_root = Node::new(NodeType::Internal(vec![], vec![]), true, None);
             // write the new root to disk.
             let new_root_offset = self.pager.write_page(Page::try_from(&new_root)?)?;
             // Set the current roots parent to the new root.
-            old_root.parent_offset = Some(new_root_offset.clone());
+            old_root.parent_offset = Some(new_root_offset);
             old_root.is_root = false;
             // update the root offset.
             self.root_offset = new_root_offset;
@@ -114,96 +738,325 @@ impl BTree {
             // Write the old root with its new data to disk.
             self.pager
                 .write_page_at_offset(Page::try_from(&*old_root)?, &old_root_offset)?;
-            // Write the newly created sibling to disk.
-            let sibling_offset = self.pager.write_page(Page::try_from(&sibling)?)?;
+            // Write the newly created sibling to disk next to the old root.
+            let sibling_offset = self
+                .pager
+                .write_page_near(Page::try_from(&sibling)?, &old_root_offset)?;
             // Update the new root with its children and key.
             new_root.node_type =
                 NodeType::Internal(vec![old_root_offset, sibling_offset], vec![median]);
-            // Write the new_root to disk.
+            // Write the new_root to disk and point the header at it.
             self.pager
                 .write_page_at_offset(Page::try_from(&new_root)?, &self.root_offset)?;
+            self.write_header()?;
             // Assign the new root.
             root = new_root;
         }
-        self.insert_non_full(&mut root, self.root_offset.clone(), kv)
+        Ok(root)
     }
 
-    /// insert_non_full (recursively) finds a node rooted at a given non-full node.
-    /// to insert a given kv pair.
-    fn insert_non_full(
+    /// accept_insert validates and accounts for storing kv in place of old.
+    /// Nothing is recorded if the pair is rejected.
+    fn accept_insert(
         &mut self,
-        node: &mut Node,
-        node_offset: Offset,
-        kv: KeyValuePair,
+        old: Option<&KeyValuePair>,
+        kv: &KeyValuePair,
     ) -> Result<(), Error> {
-        match &mut node.node_type {
-            NodeType::Leaf(ref mut pairs) => {
-                let idx = pairs.binary_search(&kv).unwrap_or_else(|x| x);
-                pairs.insert(idx, kv);
-                self.pager
-                    .write_page_at_offset(Page::try_from(&*node)?, &node_offset)
-            }
-            NodeType::Internal(ref mut children, ref mut keys) => {
-                let idx = keys
-                    .binary_search(&Key(kv.key.clone()))
-                    .unwrap_or_else(|x| x);
-                let child_offset = children.get(idx).ok_or(Error::UnexpectedError)?.clone();
-                let child_page = self.pager.get_page(&child_offset)?;
-                let mut child = Node::try_from(child_page)?;
-                if self.is_node_full(&child)? {
-                    // split will split the child at b leaving the [0, b-1] keys
-                    // while moving the set of [b, 2b-1] keys to the sibling.
-                    let (median, mut sibling) = child.split(self.b)?;
-                    self.pager
-                        .write_page_at_offset(Page::try_from(&child)?, &child_offset)?;
-                    // Write the newly created sibling to disk.
-                    let sibling_offset = self.pager.write_page(Page::try_from(&sibling)?)?;
-                    // Siblings keys are larger than the splitted child thus need to be inserted
-                    // at the next index.
-                    children.insert(idx + 1, sibling_offset.clone());
-                    keys.insert(idx, median.clone());
-
-                    // Write the parent page to disk.
-                    self.pager
-                        .write_page_at_offset(Page::try_from(&*node)?, &node_offset)?;
-                    // Continue recursively.
-                    if kv.key <= median.0 {
-                        self.insert_non_full(&mut child, child_offset, kv)
+        if kv.value.len() > VALUE_SIZE {
+            return Err(Error::ValueOverflowError);
+        }
+        match old {
+            Some(_) if !self.write_mode.allows_overwrite() => return Err(Error::KeyAlreadyExists),
+            Some(old) => self.quotas.check_replace(old, kv)?,
+            None => self.quotas.check(kv)?,
+        }
+        if let Some(old) = old {
+            self.quotas.record_delete(old);
+        }
+        self.quotas.record_insert(kv);
+        self.record_key_write(&kv.key)?;
+        self.logical_bytes += kv.key.len() + kv.value.len();
+        Ok(())
+    }
+
+    /// insert_non_full finds the leaf below a given non-full node
+    /// to insert the pair computed by f under key, returning the pair it replaced if any.
+    fn insert_non_full<F>(
+        &mut self,
+        node: Node,
+        node_offset: Offset,
+        key: String,
+        f: F,
+    ) -> Result<Option<KeyValuePair>, Error>
+    where
+        F: FnOnce(Option<&str>) -> Result<String, Error>,
+    {
+        let (leaf_offset, mut leaf, _) = self.descend_to_leaf(node, node_offset, &key)?;
+        let pairs = match &mut leaf.node_type {
+            NodeType::Leaf(pairs) => pairs,
+            _ => return Err(Error::UnexpectedError),
+        };
+        let found = pairs.binary_search_by(|kv| kv.key.cmp(&key));
+        let old = found.ok().map(|idx| pairs[idx].clone());
+        let kv = KeyValuePair::new(key, f(old.as_ref().map(|kv| kv.value.as_str()))?);
+        self.accept_insert(old.as_ref(), &kv)?;
+        match found {
+            Ok(idx) => pairs[idx] = kv,
+            Err(idx) => pairs.insert(idx, kv),
+        }
+        self.sampler.record_write(&leaf_offset);
+        self.pager
+            .write_page_at_offset(Page::try_from(&leaf)?, &leaf_offset)?;
+        Ok(old)
+    }
+
+    /// descend_to_leaf walks down from a given non-full node to the leaf where key belongs,
+    /// splitting every full node on the way so that the leaf has room for another pair.
+    /// Returns the leaf along with the separator bounding its keys from above, if any.
+    fn descend_to_leaf(
+        &mut self,
+        mut node: Node,
+        mut node_offset: Offset,
+        key: &str,
+    ) -> Result<(Offset, Node, Option<Key>), Error> {
+        let mut upper = None;
+        loop {
+            let (child_offset, child) = match &mut node.node_type {
+                NodeType::Leaf(_) => return Ok((node_offset, node, upper)),
+                NodeType::Internal(ref mut children, ref mut keys) => {
+                    let idx = node_type::child_index(keys, key);
+                    let child_offset = *children.get(idx).ok_or(Error::UnexpectedError)?;
+                    let child_page = self.pager.get_page(&child_offset)?;
+                    let mut child = Node::try_from(child_page)?;
+                    if self.is_node_full(&child)? {
+                        // split will split the child at b leaving the [0, b-1] keys
+                        // while moving the set of [b, 2b-1] keys to the sibling.
+                        let (median, sibling) = child.split(self.b)?;
+                        self.pager
+                            .write_page_at_offset(Page::try_from(&child)?, &child_offset)?;
+                        // Write the newly created sibling to disk next to the child.
+                        let sibling_offset = self
+                            .pager
+                            .write_page_near(Page::try_from(&sibling)?, &child_offset)?;
+                        // Siblings keys are larger than the splitted child thus need to be inserted
+                        // at the next index.
+                        children.insert(idx + 1, sibling_offset);
+                        keys.insert(idx, median.clone());
+                        let sibling_upper = keys.get(idx + 1).cloned();
+
+                        // Write the parent page to disk.
+                        self.pager
+                            .write_page_at_offset(Page::try_from(&node)?, &node_offset)?;
+                        // Continue with the half the key belongs to.
+                        if key <= median.0.as_str() {
+                            upper = Some(median);
+                            (child_offset, child)
+                        } else {
+                            upper = sibling_upper.or(upper);
+                            (sibling_offset, sibling)
+                        }
                     } else {
-                        self.insert_non_full(&mut sibling, sibling_offset, kv)
+                        if let Some(separator) = keys.get(idx) {
+                            upper = Some(separator.clone());
+                        }
+                        (child_offset, child)
                     }
-                } else {
-                    self.insert_non_full(&mut child, child_offset, kv)
                 }
-            }
-            NodeType::Unexpected => Err(Error::UnexpectedError),
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            };
+            node = child;
+            node_offset = child_offset;
         }
     }
 
     /// search searches for a specific key in the BTree.
     pub fn search(&mut self, key: String) -> Result<KeyValuePair, Error> {
+        let key = self.lookup_key(&key)?;
         let root_page = self.pager.get_page(&self.root_offset)?;
         let root = Node::try_from(root_page)?;
-        self.search_node(root, &key)
+        let kv = self.search_node(root, &self.root_offset.clone(), &key)?;
+        if self.is_expired(&kv.key)? {
+            return Err(Error::KeyNotFound);
+        }
+        Ok(kv)
+    }
+
+    /// lookup_key prepares a point lookup of key, checking that the handle still reads
+    /// the current file and rereading the header if due, and returns key as stored.
+    fn lookup_key(&mut self, key: &str) -> Result<String, Error> {
+        self.ensure_current()?;
+        self.revalidate()?;
+        self.key_policy.apply(key)
+    }
+
+    /// get returns the pair stored under key, or None if there is none.
+    pub fn get(&mut self, key: &str) -> Result<Option<KeyValuePair>, Error> {
+        match self.search(key.to_string()) {
+            Ok(kv) => Ok(Some(kv)),
+            Err(Error::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// get_many returns the pair stored under each of keys, or None for keys with no pair,
+    /// in the order of keys. The keys are looked up together in key order, so each node
+    /// on the paths to their leaves is read once however many of the keys it covers.
+    pub fn get_many(&mut self, keys: &[String]) -> Result<Vec<Option<KeyValuePair>>, Error> {
+        self.ensure_current()?;
+        self.revalidate()?;
+        let keys = keys
+            .iter()
+            .map(|key| self.key_policy.apply(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+        let mut found = vec![None; keys.len()];
+        let root_offset = self.root_offset;
+        self.get_many_in(root_offset, &keys, &order, &mut found)?;
+        for kv in found.iter_mut() {
+            if let Some(key) = kv.as_ref().map(|kv| kv.key.clone()) {
+                if self.is_expired(&key)? {
+                    *kv = None;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// longest_prefix returns the pair whose key is the longest prefix of key, key itself
+    /// included, or None if no stored key is a prefix of it. Since keys are at most KEY_SIZE
+    /// bytes long, every candidate prefix is looked up in a single get_many traversal.
+    pub fn longest_prefix(&mut self, key: &str) -> Result<Option<KeyValuePair>, Error> {
+        let prefixes: Vec<String> = key
+            .char_indices()
+            .map(|(idx, c)| &key[..idx + c.len_utf8()])
+            .take_while(|prefix| prefix.len() <= KEY_SIZE)
+            .map(str::to_string)
+            .collect();
+        let found = self.get_many(&prefixes)?;
+        Ok(found.into_iter().rev().flatten().next())
+    }
+
+    /// get_many_in looks up the keys at the indexes in order, sorted by key,
+    /// in the subtree at offset, storing the pairs found at the same indexes of found.
+    fn get_many_in(
+        &mut self,
+        offset: Offset,
+        keys: &[String],
+        order: &[usize],
+        found: &mut [Option<KeyValuePair>],
+    ) -> Result<(), Error> {
+        match self.read_node(&offset)?.node_type {
+            NodeType::Internal(children, separators) => {
+                let mut rest = order;
+                for (idx, child) in children.into_iter().enumerate() {
+                    // A key equal to a separator lives in the child left of it.
+                    let n = match separators.get(idx) {
+                        Some(separator) => rest.partition_point(|&i| keys[i] <= separator.0),
+                        None => rest.len(),
+                    };
+                    let (here, tail) = rest.split_at(n);
+                    if !here.is_empty() {
+                        self.get_many_in(child, keys, here, found)?;
+                    }
+                    rest = tail;
+                }
+                Ok(())
+            }
+            NodeType::Leaf(pairs) => {
+                self.sampler.record_read(&offset);
+                for &i in order {
+                    if let Ok(idx) = pairs.binary_search_by(|kv| kv.key.cmp(&keys[i])) {
+                        found[i] = Some(pairs[idx].clone());
+                    }
+                }
+                Ok(())
+            }
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// contains_key returns true if a pair is stored under key.
+    pub fn contains_key(&mut self, key: &str) -> Result<bool, Error> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// merge combines operand with the value stored under key using the tree's
+    /// MergeOperator, in the same descent that reads the stored value.
+    /// Fails with Error::NoMergeOperator if the tree was built without one.
+    pub fn merge(&mut self, key: &str, operand: &str) -> Result<(), Error> {
+        let merge_operator = self.merge_operator.clone().ok_or(Error::NoMergeOperator)?;
+        self.upsert(key, |old| merge_operator(key, old, operand))?;
+        Ok(())
+    }
+
+    /// entry returns a view of the pair stored under key for in-place manipulation.
+    pub fn entry(&mut self, key: &str) -> Result<Entry<'_>, Error> {
+        Entry::new(self, key)
+    }
+
+    /// first returns the pair with the smallest key, or None if the tree is empty.
+    pub fn first(&mut self) -> Result<Option<KeyValuePair>, Error> {
+        self.scan(..).next().transpose()
+    }
+
+    /// last returns the pair with the largest key, or None if the tree is empty.
+    pub fn last(&mut self) -> Result<Option<KeyValuePair>, Error> {
+        self.scan_rev(..).next().transpose()
+    }
+
+    /// seek_floor returns the pair with the greatest key less than or equal to key,
+    /// or None if every key is greater.
+    pub fn seek_floor(&mut self, key: &str) -> Result<Option<KeyValuePair>, Error> {
+        self.scan_rev(..=key.to_string()).next().transpose()
+    }
+
+    /// seek_ceiling returns the pair with the smallest key greater than or equal to key,
+    /// or None if every key is smaller.
+    pub fn seek_ceiling(&mut self, key: &str) -> Result<Option<KeyValuePair>, Error> {
+        self.scan(key.to_string()..).next().transpose()
+    }
+
+    /// pop_first removes and returns the pair with the smallest key,
+    /// or None if the tree is empty.
+    pub fn pop_first(&mut self) -> Result<Option<KeyValuePair>, Error> {
+        let first = self.first()?;
+        self.pop(first)
+    }
+
+    /// pop_last removes and returns the pair with the largest key,
+    /// or None if the tree is empty.
+    pub fn pop_last(&mut self) -> Result<Option<KeyValuePair>, Error> {
+        let last = self.last()?;
+        self.pop(last)
+    }
+
+    fn pop(&mut self, kv: Option<KeyValuePair>) -> Result<Option<KeyValuePair>, Error> {
+        if let Some(kv) = &kv {
+            self.delete(Key(kv.key.clone()))?;
+        }
+        Ok(kv)
     }
 
     /// search_node recursively searches a sub tree rooted at node for a key.
-    fn search_node(&mut self, node: Node, search: &str) -> Result<KeyValuePair, Error> {
+    fn search_node(
+        &mut self,
+        node: Node,
+        offset: &Offset,
+        search: &str,
+    ) -> Result<KeyValuePair, Error> {
         match node.node_type {
             NodeType::Internal(children, keys) => {
-                let idx = keys
-                    .binary_search(&Key(search.to_string()))
-                    .unwrap_or_else(|x| x);
+                let idx = node_type::child_index(&keys, search);
                 // Retrieve child page from disk and deserialize.
                 let child_offset = children.get(idx).ok_or(Error::UnexpectedError)?;
                 let page = self.pager.get_page(child_offset)?;
                 let child_node = Node::try_from(page)?;
-                self.search_node(child_node, search)
+                self.search_node(child_node, child_offset, search)
             }
             NodeType::Leaf(pairs) => {
-                if let Ok(idx) =
-                    pairs.binary_search_by_key(&search.to_string(), |pair| pair.key.clone())
-                {
+                self.sampler.record_read(offset);
+                if let Ok(idx) = pairs.binary_search_by(|pair| pair.key.as_str().cmp(search)) {
                     return Ok(pairs[idx].clone());
                 }
                 Err(Error::KeyNotFound)
@@ -212,114 +1065,774 @@ impl BTree {
         }
     }
 
+    /// get_raw returns the bytes stored as the value of key without converting them
+    /// to a String, so values that are not valid UTF-8 can still be read back.
+    pub fn get_raw(&mut self, key: &str) -> Result<Vec<u8>, Error> {
+        let key = self.lookup_key(key)?;
+        let mut offset = self.root_offset;
+        loop {
+            let page = self.pager.get_page(&offset)?;
+            let node_type = page.get_ptr_from_offset(NODE_TYPE_OFFSET, 1)[0];
+            match NodeType::from(node_type) {
+                NodeType::Internal(_, _) => {
+                    if let NodeType::Internal(children, keys) = Node::try_from(page)?.node_type {
+                        let idx = node_type::child_index(&keys, &key);
+                        offset = *children.get(idx).ok_or(Error::UnexpectedError)?;
+                    }
+                }
+                NodeType::Leaf(_) => {
+                    self.sampler.record_read(&offset);
+                    // Scan the serialized pairs, only the key bytes are compared.
+                    let num_pairs = page.get_value_from_offset(LEAF_NODE_NUM_PAIRS_OFFSET)?;
+                    let mut pair_offset = LEAF_NODE_HEADER_SIZE;
+                    for _i in 0..num_pairs {
+                        let raw_key = page.get_ptr_from_offset(pair_offset, KEY_SIZE);
+                        if trim_padding(raw_key) == key.as_bytes() {
+                            if self.is_expired(&key)? {
+                                return Err(Error::KeyNotFound);
+                            }
+                            let raw_value =
+                                page.get_ptr_from_offset(pair_offset + KEY_SIZE, VALUE_SIZE);
+                            return Ok(trim_padding(raw_value).to_vec());
+                        }
+                        pair_offset += KEY_SIZE + VALUE_SIZE;
+                    }
+                    return Err(Error::KeyNotFound);
+                }
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+        }
+    }
+
     /// delete deletes a given key from the tree.
     pub fn delete(&mut self, key: Key) -> Result<(), Error> {
-        self.delete_key_from_subtree(key, &self.root_offset.clone())
-    }
-
-    /// delete key from subtree recursively traverses a tree rooted at a node in certain offset
-    /// until it finds the given key and delets.
-    fn delete_key_from_subtree(&mut self, key: Key, offset: &Offset) -> Result<(), Error> {
-        let page = self.pager.get_page(offset)?;
-        let mut node = Node::try_from(page)?;
-        match &mut node.node_type {
-            NodeType::Leaf(ref mut pairs) => {
-                let node_idx = pairs
-                    .binary_search_by_key(&key, |kv| Key(kv.key.clone()))
-                    .map_err(|_| Error::KeyNotFound)?;
-                pairs.remove(node_idx);
-                // Check for underflow - if it occures,
-                // we need to merge with a sibling.
-                // this can only occur if node is not the root (as it cannot "underflow").
-                // continue recoursively up the tree.
-                self.merge_if_needed(node, &key)?;
+        self.ensure_current()?;
+        self.revalidate()?;
+        let key = Key(self.key_policy.apply(&key.0)?);
+        if !self.write_mode.allows_delete() {
+            return Err(Error::Immutable { key: key.0 });
+        }
+        let removed = self.delete_key_from_subtree(key, &self.root_offset.clone())?;
+        // Only a delete that removed a pair counts as a write.
+        self.record_key_write(&removed.key)?;
+        self.logical_bytes += removed.key.len();
+        self.len -= 1;
+        self.quotas.record_delete(&removed);
+        if let Some(trash) = self.trash.as_mut() {
+            trash.put(&removed)?;
+        }
+        self.audit(Mutation::Delete, &removed.key)
+    }
+
+    /// delete_range removes every pair within range, returning the number of pairs removed.
+    /// Subtrees lying entirely within range are freed page by page without being rewritten,
+    /// only the leaves straddling its bounds are written back.
+    pub fn delete_range<R: RangeBounds<String>>(&mut self, range: R) -> Result<usize, Error> {
+        self.ensure_current()?;
+        self.revalidate()?;
+        let range: KeyRange = (range.start_bound().cloned(), range.end_bound().cloned());
+        if !self.write_mode.allows_delete() {
+            return match self.scan(range).next().transpose()? {
+                Some(kv) => Err(Error::Immutable { key: kv.key }),
+                None => Ok(0),
+            };
+        }
+        self.mark_len_dirty()?;
+        let root_offset = self.root_offset;
+        let (removed, emptied) = self.prune(root_offset, &range, None, None)?;
+        self.len -= removed;
+        let mut root = self.read_node(&root_offset)?;
+        if emptied && root.node_type != NodeType::Leaf(vec![]) {
+            // Every child of the root is gone, start over from an empty leaf.
+            root.node_type = NodeType::Leaf(vec![]);
+            self.pager
+                .write_page_at_offset(Page::try_from(&root)?, &root_offset)?;
+            self.height = None;
+        }
+        // Pruning may leave a chain of internal nodes with a single child at the top.
+        while let NodeType::Internal(_, keys) = &root.node_type {
+            if !keys.is_empty() {
+                break;
+            }
+            self.shrink_root(self.root_offset, root)?;
+            root = self.read_node(&self.root_offset.clone())?;
+        }
+        Ok(removed)
+    }
+
+    /// prune removes the pairs within range from the subtree at offset, whose keys lie
+    /// in (lower, upper], returning the number of pairs removed and whether the subtree
+    /// ended up empty. An emptied subtree is left for the caller to free.
+    fn prune(
+        &mut self,
+        offset: Offset,
+        range: &KeyRange,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+    ) -> Result<(usize, bool), Error> {
+        let mut node = self.read_node(&offset)?;
+        match node.node_type {
+            NodeType::Leaf(pairs) => {
+                let (gone, kept): (Vec<_>, Vec<_>) = pairs.into_iter().partition(|kv| {
+                    scan::after_start(&range.0, &kv.key) && scan::before_end(&range.1, &kv.key)
+                });
+                if gone.is_empty() {
+                    return Ok((0, kept.is_empty()));
+                }
+                let emptied = kept.is_empty();
+                node.node_type = NodeType::Leaf(kept);
+                self.pager
+                    .write_page_at_offset(Page::try_from(&node)?, &offset)?;
+                self.sampler.record_write(&offset);
+                self.discard_pairs(gone).map(|removed| (removed, emptied))
             }
             NodeType::Internal(children, keys) => {
-                let node_idx = keys.binary_search(&key).unwrap_or_else(|x| x);
-                // Retrieve child page from disk and deserialize.
-                // And continue recoursively.
-                let child_offset = children.get(node_idx).ok_or(Error::UnexpectedError)?;
-                return self.delete_key_from_subtree(key, child_offset);
-            }
-            NodeType::Unexpected => return Err(Error::UnexpectedError),
-        }
-        Ok(())
-    }
-
-    /// merge_if_needed checks the node for underflow (following a removal of a key),
-    /// if it underflows it is merged with a sibling node, and than called recoursively
-    /// up the tree.
-    fn merge_if_needed(&mut self, node: Node, key: &Key) -> Result<(), Error> {
-        if self.is_node_underflow(&node)? {
-            // Fetch the sibling from the parent -
-            // This could be quicker if we implement sibling pointers.
-            let parent_offset = node.parent_offset.clone().ok_or(Error::UnexpectedError)?;
-            let parent_page = self.pager.get_page(&parent_offset)?;
-            let mut parent_node = Node::try_from(parent_page)?;
-            // The parent has to be an "internal" node.
-            match parent_node.node_type {
-                NodeType::Internal(ref mut children, ref keys) => {
-                    let idx = keys.binary_search(&key).unwrap_or_else(|x| x);
-                    // The sibling is in idx +- 1 as the above index led
-                    // the downward search to node.
-                    let sibling_idx;
-                    if idx == keys.len() - 1 {
-                        sibling_idx = idx - 1;
-                    } else {
-                        sibling_idx = idx + 1;
+                let num_children = children.len();
+                let mut kept_children = Vec::with_capacity(num_children);
+                let mut kept_keys = Vec::with_capacity(keys.len());
+                let mut removed = 0;
+                for (idx, child) in children.into_iter().enumerate() {
+                    let lo = if idx == 0 { lower } else { keys.get(idx - 1) };
+                    let hi = keys.get(idx).or(upper);
+                    let emptied = match scan::overlap(range, lo, hi) {
+                        Overlap::Disjoint => false,
+                        Overlap::Covered => {
+                            removed += self.drop_subtree(child)?;
+                            continue;
+                        }
+                        Overlap::Partial => {
+                            let (n, emptied) = self.prune(child, range, lo, hi)?;
+                            removed += n;
+                            emptied
+                        }
+                    };
+                    if emptied {
+                        self.pager.free_page(&child)?;
+                        continue;
                     }
-
-                    let sibling_offset = children.get(sibling_idx).ok_or(Error::UnexpectedError)?;
-                    let sibling_page = self.pager.get_page(sibling_offset)?;
-                    let sibling = Node::try_from(sibling_page)?;
-                    let merged_node = self.merge(node, sibling)?;
-                    let merged_node_offset =
-                        self.pager.write_page(Page::try_from(&merged_node)?)?;
-                    // remove the old nodes.
-                    children.remove(idx);
-                    children.remove(sibling_idx);
-                    // write the new node in place.
-                    let merged_node_idx = cmp::min(idx, sibling_idx);
-                    children.insert(merged_node_idx, merged_node_offset);
-                    // write the updated parent back to disk and continue up the tree.
+                    // The separator left of a kept child still bounds it from below.
+                    if !kept_children.is_empty() {
+                        kept_keys.push(keys[idx - 1].clone());
+                    }
+                    kept_children.push(child);
+                }
+                if kept_children.is_empty() {
+                    return Ok((removed, true));
+                }
+                if kept_children.len() < num_children {
+                    node.node_type = NodeType::Internal(kept_children, kept_keys);
                     self.pager
-                        .write_page_at_offset(Page::try_from(&parent_node)?, &parent_offset)?;
-                    return self.merge_if_needed(parent_node, &key);
+                        .write_page_at_offset(Page::try_from(&node)?, &offset)?;
                 }
-                _ => return Err(Error::UnexpectedError),
+                if removed > 0 && !self.deferred_merges {
+                    self.restore_occupancy(offset)?;
+                }
+                Ok((removed, false))
             }
+            NodeType::Unexpected => Err(Error::UnexpectedError),
         }
-        Ok(())
     }
 
-    // merges two *sibling* nodes, it assumes the following:
-    // 1. the two nodes are of the same type.
-    // 2. the two nodes do not accumulate to an overflow,
-    // i.e. |first.keys| + |second.keys| <= [2*(b-1) for keys or 2*b for offsets].
-    fn merge(&self, first: Node, second: Node) -> Result<Node, Error> {
-        match first.node_type {
-            NodeType::Leaf(first_pairs) => {
-                if let NodeType::Leaf(second_pairs) = second.node_type {
-                    let merged_pairs: Vec<KeyValuePair> = first_pairs
-                        .into_iter()
-                        .chain(second_pairs.into_iter())
-                        .collect();
-                    let node_type = NodeType::Leaf(merged_pairs);
-                    Ok(Node::new(node_type, first.is_root, first.parent_offset))
-                } else {
-                    Err(Error::UnexpectedError)
-                }
+    /// restore_occupancy rebalances every underflowing child of the internal node at offset,
+    /// descending into the children that took entries from a sibling since those entries
+    /// may include underflowing nodes themselves. A node with a single child is left to
+    /// its own parent, or to shrink_root.
+    fn restore_occupancy(&mut self, offset: Offset) -> Result<(), Error> {
+        let mut node = self.read_node(&offset)?;
+        let mut idx = 0;
+        loop {
+            let (child_offset, num_children) = match &node.node_type {
+                NodeType::Internal(children, _) if children.len() > 1 => match children.get(idx) {
+                    Some(child_offset) => (*child_offset, children.len()),
+                    None => return Ok(()),
+                },
+                _ => return Ok(()),
+            };
+            let child = self.read_node(&child_offset)?;
+            if !self.is_node_underflow(&child)? {
+                idx += 1;
+                continue;
             }
-            NodeType::Internal(first_offsets, first_keys) => {
-                if let NodeType::Internal(second_offsets, second_keys) = second.node_type {
-                    let merged_keys: Vec<Key> = first_keys
-                        .into_iter()
-                        .chain(second_keys.into_iter())
-                        .collect();
-                    let merged_offsets: Vec<Offset> = first_offsets
+            node = self.rebalance(offset, node, idx, child_offset, child)?;
+            let children = match &node.node_type {
+                NodeType::Internal(children, _) => children,
+                _ => return Err(Error::UnexpectedError),
+            };
+            // A merge with the left sibling leaves the merged node at idx - 1,
+            // any other rebalance leaves the child at idx to be checked again.
+            let filled = if children.len() < num_children && idx > 0 {
+                children[idx - 1]
+            } else {
+                children[idx]
+            };
+            self.restore_occupancy(filled)?;
+        }
+    }
+
+    /// compact_range repacks the leaves holding keys within range as full as they go,
+    /// freeing the pages left over, without touching leaves outside of it.
+    /// Meant for a hot, fragmented key range where a full rewrite of the file through
+    /// replace_with would cost too much. Returns the number of pages freed.
+    pub fn compact_range<R: RangeBounds<String>>(&mut self, range: R) -> Result<usize, Error> {
+        self.ensure_current()?;
+        self.revalidate()?;
+        let range: KeyRange = (range.start_bound().cloned(), range.end_bound().cloned());
+        let root_offset = self.root_offset;
+        let freed = self.compact_subtree(root_offset, &range, None, None)?;
+        let mut root = self.read_node(&root_offset)?;
+        while let NodeType::Internal(_, keys) = &root.node_type {
+            if !keys.is_empty() {
+                break;
+            }
+            self.shrink_root(self.root_offset, root)?;
+            root = self.read_node(&self.root_offset.clone())?;
+        }
+        Ok(freed)
+    }
+
+    /// compact_subtree repacks the children overlapping range of the internal node
+    /// at offset, whose keys lie in (lower, upper], then does the same below each of them.
+    /// Returns the number of pages freed.
+    fn compact_subtree(
+        &mut self,
+        offset: Offset,
+        range: &KeyRange,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+    ) -> Result<usize, Error> {
+        let mut freed = match self.overlapping_children(offset, range, lower, upper)? {
+            Some((first, last)) => self.repack_children(offset, first, last)?,
+            None => return Ok(0),
+        };
+        let node = self.read_node(&offset)?;
+        let (children, keys) = match node.node_type {
+            NodeType::Internal(children, keys) => (children, keys),
+            _ => return Err(Error::UnexpectedError),
+        };
+        let mut freed_below = 0;
+        for (idx, child) in children.into_iter().enumerate() {
+            let lo = if idx == 0 { lower } else { keys.get(idx - 1) };
+            let hi = keys.get(idx).or(upper);
+            if scan::overlap(range, lo, hi) != Overlap::Disjoint {
+                freed_below += self.compact_subtree(child, range, lo, hi)?;
+            }
+        }
+        // Repacking the level below may leave some of the children short of entries.
+        if freed_below > 0 {
+            self.restore_occupancy(offset)?;
+        }
+        freed += freed_below;
+        Ok(freed)
+    }
+
+    /// overlapping_children returns the indexes of the first and last children of the
+    /// node at offset that hold keys within range, or None if the node is a leaf
+    /// or none of its children does.
+    fn overlapping_children(
+        &mut self,
+        offset: Offset,
+        range: &KeyRange,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+    ) -> Result<Option<(usize, usize)>, Error> {
+        let (num_children, keys) = match self.read_node(&offset)?.node_type {
+            NodeType::Internal(children, keys) => (children.len(), keys),
+            _ => return Ok(None),
+        };
+        let mut overlapping = (0..num_children).filter(|&idx| {
+            let lo = if idx == 0 { lower } else { keys.get(idx - 1) };
+            let hi = keys.get(idx).or(upper);
+            scan::overlap(range, lo, hi) != Overlap::Disjoint
+        });
+        let first = overlapping.next();
+        Ok(first.map(|first| (first, overlapping.next_back().unwrap_or(first))))
+    }
+
+    /// repack_children spreads the entries of the children first to last of the internal
+    /// node at offset evenly over as few nodes as hold them, reusing the pages of the
+    /// leftmost children and freeing the others. Returns the number of pages freed.
+    fn repack_children(
+        &mut self,
+        offset: Offset,
+        first: usize,
+        last: usize,
+    ) -> Result<usize, Error> {
+        let mut node = self.read_node(&offset)?;
+        let (children, keys) = match &mut node.node_type {
+            NodeType::Internal(children, keys) => (children, keys),
+            _ => return Err(Error::UnexpectedError),
+        };
+        let run = children[first..=last].to_vec();
+        // Gather the entries of the run, pulling the separators between internal
+        // children down between their children.
+        let mut pairs = Vec::new();
+        let mut grandchildren = Vec::new();
+        let mut separators = Vec::new();
+        for (idx, child) in run.iter().enumerate() {
+            match self.read_node(child)?.node_type {
+                NodeType::Leaf(leaf_pairs) => pairs.extend(leaf_pairs),
+                NodeType::Internal(child_children, child_keys) => {
+                    if idx > 0 {
+                        separators.push(keys[first + idx - 1].clone());
+                    }
+                    grandchildren.extend(child_children);
+                    separators.extend(child_keys);
+                }
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+        }
+        let (entries, capacity) = match grandchildren.len() {
+            0 => (pairs.len(), 2 * self.b - 1),
+            n => (n, 2 * self.b),
+        };
+        let num_nodes = entries.div_ceil(capacity).max(1);
+        if num_nodes == run.len() {
+            return Ok(0);
+        }
+
+        let mut raised = Vec::with_capacity(num_nodes - 1);
+        let mut pairs = pairs.into_iter();
+        let mut grandchildren = grandchildren.into_iter();
+        let mut separators = separators.into_iter();
+        let mut rest = entries;
+        for (i, child) in run[..num_nodes].iter().enumerate() {
+            let take = rest.div_ceil(num_nodes - i);
+            rest -= take;
+            let node_type = if pairs.len() > 0 || grandchildren.len() == 0 {
+                let leaf_pairs: Vec<KeyValuePair> = pairs.by_ref().take(take).collect();
+                if i + 1 < num_nodes {
+                    let last_pair = leaf_pairs.last().ok_or(Error::UnexpectedError)?;
+                    raised.push(Key(last_pair.key.clone()));
+                }
+                NodeType::Leaf(leaf_pairs)
+            } else {
+                let node_children: Vec<Offset> = grandchildren.by_ref().take(take).collect();
+                let node_keys: Vec<Key> = separators.by_ref().take(take - 1).collect();
+                if i + 1 < num_nodes {
+                    raised.push(separators.next().ok_or(Error::UnexpectedError)?);
+                }
+                NodeType::Internal(node_children, node_keys)
+            };
+            let repacked = Node::new(node_type, false, Some(offset));
+            self.pager
+                .write_page_at_offset(Page::try_from(&repacked)?, child)?;
+            self.sampler.record_write(child);
+        }
+        let mut freed = 0;
+        for child in children.drain(first + num_nodes..=last) {
+            self.pager.free_page(&child)?;
+            freed += 1;
+        }
+        // The separator right of the run still bounds its last node from above.
+        keys.splice(first..last, raised);
+        self.pager
+            .write_page_at_offset(Page::try_from(&node)?, &offset)?;
+        Ok(freed)
+    }
+
+    /// merge_sparse_nodes rebalances every node left below b - 1 keys by deletes
+    /// under BTreeBuilder::deferred_merges, returning the number of pages freed.
+    pub fn merge_sparse_nodes(&mut self) -> Result<usize, Error> {
+        let allocated = self.pager.allocated_pages().len();
+        let root_offset = self.root_offset;
+        self.merge_sparse_subtree(root_offset)?;
+        let mut root = self.read_node(&root_offset)?;
+        while let NodeType::Internal(_, keys) = &root.node_type {
+            if !keys.is_empty() {
+                break;
+            }
+            self.shrink_root(self.root_offset, root)?;
+            root = self.read_node(&self.root_offset.clone())?;
+        }
+        Ok(allocated - self.pager.allocated_pages().len())
+    }
+
+    /// merge_sparse_subtree restores the occupancy of the subtree at offset bottom up,
+    /// so that every child is whole by the time it is merged or borrowed from.
+    fn merge_sparse_subtree(&mut self, offset: Offset) -> Result<(), Error> {
+        let children = match self.read_node(&offset)?.node_type {
+            NodeType::Internal(children, _) => children,
+            _ => return Ok(()),
+        };
+        for child in children {
+            self.merge_sparse_subtree(child)?;
+        }
+        self.restore_occupancy(offset)
+    }
+
+    /// drop_subtree frees every page of the subtree at offset,
+    /// returning the number of pairs it held.
+    fn drop_subtree(&mut self, offset: Offset) -> Result<usize, Error> {
+        let mut removed = 0;
+        let mut stack = vec![offset];
+        while let Some(offset) = stack.pop() {
+            match self.read_node(&offset)?.node_type {
+                NodeType::Internal(children, _) => stack.extend(children),
+                NodeType::Leaf(pairs) => removed += self.discard_pairs(pairs)?,
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+            self.pager.free_page(&offset)?;
+        }
+        Ok(removed)
+    }
+
+    /// discard_pairs accounts for the removal of pairs from the tree,
+    /// moving them to the trash if one is set.
+    fn discard_pairs(&mut self, pairs: Vec<KeyValuePair>) -> Result<usize, Error> {
+        let removed = pairs.len();
+        for kv in pairs {
+            self.update_pair(&kv, None)?;
+            if let Some(trash) = self.trash.as_mut() {
+                trash.put(&kv)?;
+            }
+            self.audit(Mutation::Delete, &kv.key)?;
+        }
+        Ok(removed)
+    }
+
+    /// clear removes every pair, leaving an empty root leaf in place of the root
+    /// and returning every other page of the file to the free list.
+    /// Unlike delete, the removed pairs are neither moved to the trash nor audited.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.ensure_current()?;
+        self.revalidate()?;
+        if !self.write_mode.allows_delete() {
+            if let Some(kv) = self.first()? {
+                return Err(Error::Immutable { key: kv.key });
+            }
+        }
+        self.mark_len_dirty()?;
+        let root = Node::new(NodeType::Leaf(vec![]), true, None);
+        self.pager
+            .write_page_at_offset(Page::try_from(&root)?, &self.root_offset)?;
+        self.sampler.record_write(&self.root_offset);
+        self.len = 0;
+        self.height = Some(1);
+        self.quotas.reset();
+        if let Some(expiry) = self.expiry.as_mut() {
+            expiry.clear_all()?;
+        }
+        self.persist_stats()?;
+        for offset in self.pager.allocated_pages() {
+            if offset != Offset(HEADER_OFFSET) && offset != self.root_offset {
+                self.pager.free_page(&offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// record_key_write notifies the per key write trackers of a write to key,
+    /// and clears the expiry of key since every write replaces or removes its pair.
+    fn record_key_write(&mut self, key: &str) -> Result<(), Error> {
+        self.lifetime.writes += 1;
+        if let Some(hot_keys) = self.hot_keys.as_mut() {
+            hot_keys.record_write(key);
+        }
+        if let Some(write_rates) = self.write_rates.as_mut() {
+            write_rates.record_write(key);
+        }
+        match self.expiry.as_mut() {
+            Some(expiry) => expiry.clear(key),
+            None => Ok(()),
+        }
+    }
+
+    /// set_audit_log starts recording every insert, update and delete in audit_log.
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.audit_log = Some(Box::new(audit_log));
+    }
+
+    pub fn audit_log(&mut self) -> Option<&mut AuditLog> {
+        self.audit_log.as_deref_mut()
+    }
+
+    /// set_trash makes delete move the removed pairs to trash, from where they can be
+    /// brought back with restore.
+    pub fn set_trash(&mut self, trash: Trash) {
+        self.trash = Some(Box::new(trash));
+    }
+
+    pub fn trash(&mut self) -> Option<&mut Trash> {
+        self.trash.as_deref_mut()
+    }
+
+    /// set_expiry enables insert_with_ttl, keeping the expiry times in expiry.
+    /// Expired pairs are skipped by lookups and scans but keep their space, and still count
+    /// towards len and count_range, until purge_expired removes them.
+    pub fn set_expiry(&mut self, expiry: Expiry) {
+        self.expiry = Some(Box::new(expiry));
+    }
+
+    pub fn expiry(&mut self) -> Option<&mut Expiry> {
+        self.expiry.as_deref_mut()
+    }
+
+    /// insert_with_ttl inserts kv like insert and makes it expire ttl from now.
+    /// Writing the key again without a ttl makes it permanent.
+    /// Fails with Error::NoExpiry unless set_expiry was called first.
+    pub fn insert_with_ttl(
+        &mut self,
+        kv: KeyValuePair,
+        ttl: Duration,
+    ) -> Result<Option<KeyValuePair>, Error> {
+        if self.expiry.is_none() {
+            return Err(Error::NoExpiry);
+        }
+        let key = self.key_policy.apply(&kv.key)?;
+        let old = self.insert(kv)?;
+        match self.expiry.as_mut() {
+            Some(expiry) => expiry.set(&key, ttl)?,
+            None => return Err(Error::NoExpiry),
+        }
+        Ok(old)
+    }
+
+    /// purge_expired deletes every expired pair, returning the number of pairs deleted.
+    pub fn purge_expired(&mut self) -> Result<usize, Error> {
+        let expired = match self.expiry.as_mut() {
+            Some(expiry) => expiry.expired()?,
+            None => return Ok(0),
+        };
+        let mut purged = 0;
+        for key in expired {
+            match self.delete(Key(key.clone())) {
+                Ok(()) => purged += 1,
+                // The pair is gone already, only its expiry time was left behind.
+                Err(Error::KeyNotFound) => {
+                    if let Some(expiry) = self.expiry.as_mut() {
+                        expiry.clear(&key)?;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(purged)
+    }
+
+    /// is_expired returns true if key was inserted with a ttl that ran out.
+    pub(crate) fn is_expired(&mut self, key: &str) -> Result<bool, Error> {
+        match self.expiry.as_mut() {
+            Some(expiry) => expiry.is_expired(key),
+            None => Ok(false),
+        }
+    }
+
+    /// restore inserts back the most recently deleted pair stored under key and removes it
+    /// from the trash. Fails with Error::KeyNotFound if key is not in the trash
+    /// and with Error::KeyAlreadyExists if key was stored again since.
+    pub fn restore(&mut self, key: &str) -> Result<(), Error> {
+        match self.search(key.to_string()) {
+            Ok(_) => return Err(Error::KeyAlreadyExists),
+            Err(Error::KeyNotFound) => (),
+            Err(e) => return Err(e),
+        }
+        let trash = self.trash.as_mut().ok_or(Error::KeyNotFound)?;
+        let trashed = trash.latest(key)?;
+        self.insert(trashed.kv)?;
+        match self.trash.as_mut() {
+            Some(trash) => trash.remove(trashed.seq),
+            None => Ok(()),
+        }
+    }
+
+    /// audit records a successful mutation of key in the audit log, if any.
+    fn audit(&mut self, mutation: Mutation, key: &str) -> Result<(), Error> {
+        match self.audit_log.as_mut() {
+            Some(audit_log) => audit_log.record(mutation, key),
+            None => Ok(()),
+        }
+    }
+
+    /// quota_usage returns the entries and bytes currently stored under a prefix
+    /// configured with BTreeBuilder::quota.
+    pub fn quota_usage(&self, prefix: &str) -> Option<QuotaUsage> {
+        self.quotas.usage(prefix)
+    }
+
+    /// delete_key_from_subtree removes key from the leaf of the tree rooted at offset
+    /// that holds it, then restores the minimal occupancy of the nodes on the path back up,
+    /// returning the removed pair. The path is tracked on the way down since the parent
+    /// offsets stored in nodes are not kept up to date when children move between nodes.
+    fn delete_key_from_subtree(
+        &mut self,
+        key: Key,
+        offset: &Offset,
+    ) -> Result<KeyValuePair, Error> {
+        // Internal nodes on the path to the leaf, along with the index of the child taken.
+        let mut path: Vec<(Offset, Node, usize)> = Vec::new();
+        let mut offset = *offset;
+        let mut node = self.read_node(&offset)?;
+        while let NodeType::Internal(children, keys) = &node.node_type {
+            let idx = keys.binary_search(&key).unwrap_or_else(|x| x);
+            let child_offset = *children.get(idx).ok_or(Error::UnexpectedError)?;
+            let child = self.read_node(&child_offset)?;
+            path.push((offset, node, idx));
+            offset = child_offset;
+            node = child;
+        }
+
+        let removed = match &mut node.node_type {
+            NodeType::Leaf(pairs) => {
+                let idx = pairs
+                    .binary_search_by(|kv| kv.key.as_str().cmp(&key.0))
+                    .map_err(|_| Error::KeyNotFound)?;
+                pairs.remove(idx)
+            }
+            _ => return Err(Error::UnexpectedError),
+        };
+        self.mark_len_dirty()?;
+        self.sampler.record_write(&offset);
+        self.pager
+            .write_page_at_offset(Page::try_from(&node)?, &offset)?;
+
+        if self.deferred_merges {
+            return Ok(removed);
+        }
+        // Rebalance bottom up until a node no longer underflows.
+        while self.is_node_underflow(&node)? {
+            let (parent_offset, parent, idx) = path.pop().ok_or(Error::UnexpectedError)?;
+            node = self.rebalance(parent_offset, parent, idx, offset, node)?;
+            offset = parent_offset;
+        }
+        if node.is_root {
+            self.shrink_root(offset, node)?;
+        }
+        Ok(removed)
+    }
+
+    /// rebalance restores the occupancy of the underflowing child at idx of parent by
+    /// borrowing an entry from a sibling that can spare one, or merging it with a sibling
+    /// otherwise. Every modified node is written back and the updated parent is returned.
+    fn rebalance(
+        &mut self,
+        parent_offset: Offset,
+        mut parent: Node,
+        idx: usize,
+        offset: Offset,
+        mut node: Node,
+    ) -> Result<Node, Error> {
+        let (children, keys) = match &mut parent.node_type {
+            NodeType::Internal(children, keys) => (children, keys),
+            _ => return Err(Error::UnexpectedError),
+        };
+        let mut left = match idx.checked_sub(1) {
+            Some(left_idx) => {
+                let left_offset = children[left_idx];
+                Some((left_offset, self.read_node(&left_offset)?))
+            }
+            None => None,
+        };
+        let mut right = match children.get(idx + 1) {
+            Some(right_offset) => Some((*right_offset, self.read_node(right_offset)?)),
+            None => None,
+        };
+
+        if let Some((left_offset, left)) = left.as_mut() {
+            if self.can_lend(left)? {
+                let separator = &mut keys[idx - 1];
+                match (&mut left.node_type, &mut node.node_type) {
+                    (NodeType::Leaf(left_pairs), NodeType::Leaf(pairs)) => {
+                        let kv = left_pairs.pop().ok_or(Error::UnexpectedError)?;
+                        pairs.insert(0, kv);
+                        let last = left_pairs.last().ok_or(Error::UnexpectedError)?;
+                        *separator = Key(last.key.clone());
+                    }
+                    (
+                        NodeType::Internal(left_children, left_keys),
+                        NodeType::Internal(children, keys),
+                    ) => {
+                        let child = left_children.pop().ok_or(Error::UnexpectedError)?;
+                        let key = left_keys.pop().ok_or(Error::UnexpectedError)?;
+                        children.insert(0, child);
+                        keys.insert(0, std::mem::replace(separator, key));
+                    }
+                    _ => return Err(Error::UnexpectedError),
+                }
+                self.write_nodes(&[(left_offset, left), (&offset, &node)])?;
+                self.pager
+                    .write_page_at_offset(Page::try_from(&parent)?, &parent_offset)?;
+                return Ok(parent);
+            }
+        }
+
+        if let Some((right_offset, right)) = right.as_mut() {
+            if self.can_lend(right)? {
+                let separator = &mut keys[idx];
+                match (&mut node.node_type, &mut right.node_type) {
+                    (NodeType::Leaf(pairs), NodeType::Leaf(right_pairs)) => {
+                        let kv = right_pairs.remove(0);
+                        *separator = Key(kv.key.clone());
+                        pairs.push(kv);
+                    }
+                    (
+                        NodeType::Internal(children, keys),
+                        NodeType::Internal(right_children, right_keys),
+                    ) => {
+                        children.push(right_children.remove(0));
+                        let key = right_keys.remove(0);
+                        keys.push(std::mem::replace(separator, key));
+                    }
+                    _ => return Err(Error::UnexpectedError),
+                }
+                self.write_nodes(&[(&offset, &node), (right_offset, right)])?;
+                self.pager
+                    .write_page_at_offset(Page::try_from(&parent)?, &parent_offset)?;
+                return Ok(parent);
+            }
+        }
+
+        // Neither sibling can lend an entry, merge the node with one of them.
+        let (left_idx, left_offset, left, right_offset, right) = match (left, right) {
+            (Some((left_offset, left)), _) => (idx - 1, left_offset, left, offset, node),
+            (None, Some((right_offset, right))) => (idx, offset, node, right_offset, right),
+            (None, None) => return Err(Error::UnexpectedError),
+        };
+        let separator = keys.remove(left_idx);
+        children.remove(left_idx + 1);
+        let merged = self.merge_nodes(left, right, separator)?;
+        self.write_nodes(&[(&left_offset, &merged)])?;
+        self.pager.free_page(&right_offset)?;
+        self.pager
+            .write_page_at_offset(Page::try_from(&parent)?, &parent_offset)?;
+        Ok(parent)
+    }
+
+    /// can_lend returns true if a node can give up an entry without underflowing.
+    fn can_lend(&self, node: &Node) -> Result<bool, Error> {
+        match &node.node_type {
+            NodeType::Leaf(pairs) => Ok(pairs.len() > self.b - 1),
+            NodeType::Internal(_, keys) => Ok(keys.len() > self.b - 1),
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+
+    // merges two *sibling* nodes, it assumes the following:
+    // 1. the two nodes are of the same type.
+    // 2. the two nodes do not accumulate to an overflow,
+    // i.e. |first.keys| + |second.keys| <= [2*(b-1) for keys or 2*b for offsets].
+    // The separator between them is pulled down into merged internal nodes,
+    // and dropped for leaves since every key of a leaf is stored in its pairs.
+    fn merge_nodes(&self, first: Node, second: Node, separator: Key) -> Result<Node, Error> {
+        match first.node_type {
+            NodeType::Leaf(first_pairs) => {
+                if let NodeType::Leaf(second_pairs) = second.node_type {
+                    let merged_pairs: Vec<KeyValuePair> =
+                        first_pairs.into_iter().chain(second_pairs).collect();
+                    let node_type = NodeType::Leaf(merged_pairs);
+                    Ok(Node::new(node_type, first.is_root, first.parent_offset))
+                } else {
+                    Err(Error::UnexpectedError)
+                }
+            }
+            NodeType::Internal(first_offsets, first_keys) => {
+                if let NodeType::Internal(second_offsets, second_keys) = second.node_type {
+                    let merged_keys: Vec<Key> = first_keys
                         .into_iter()
-                        .chain(second_offsets.into_iter())
+                        .chain(Some(separator))
+                        .chain(second_keys)
                         .collect();
+                    let merged_offsets: Vec<Offset> =
+                        first_offsets.into_iter().chain(second_offsets).collect();
                     let node_type = NodeType::Internal(merged_offsets, merged_keys);
                     Ok(Node::new(node_type, first.is_root, first.parent_offset))
                 } else {
@@ -330,124 +1843,2580 @@ impl BTree {
         }
     }
 
-    /// print_sub_tree is a helper function for recursively printing the nodes rooted at a node given by its offset.
-    fn print_sub_tree(&mut self, prefix: String, offset: Offset) -> Result<(), Error> {
-        println!("{}Node at offset: {}", prefix, offset.0);
-        let curr_prefix = format!("{}|->", prefix);
-        let page = self.pager.get_page(&offset)?;
-        let node = Node::try_from(page)?;
-        match node.node_type {
-            NodeType::Internal(children, keys) => {
-                println!("{}Keys: {:?}", curr_prefix, keys);
-                println!("{}Children: {:?}", curr_prefix, children);
-                let child_prefix = format!("{}   |  ", prefix);
-                for child_offset in children {
-                    self.print_sub_tree(child_prefix.clone(), child_offset)?;
-                }
-                Ok(())
-            }
-            NodeType::Leaf(pairs) => {
-                println!("{}Key value pairs: {:?}", curr_prefix, pairs);
-                Ok(())
+    /// shrink_root makes the only child of an internal root without keys the new root,
+    /// reducing the height of the tree by one.
+    fn shrink_root(&mut self, offset: Offset, root: Node) -> Result<(), Error> {
+        let child_offset = match &root.node_type {
+            NodeType::Internal(children, keys) if keys.is_empty() => {
+                *children.first().ok_or(Error::UnexpectedError)?
+            }
+            _ => return Ok(()),
+        };
+        let mut child = self.read_node(&child_offset)?;
+        child.is_root = true;
+        child.parent_offset = None;
+        self.pager
+            .write_page_at_offset(Page::try_from(&child)?, &child_offset)?;
+        self.root_offset = child_offset;
+        self.write_header()?;
+        self.pager.free_page(&offset)
+    }
+
+    /// write_nodes writes every node to its offset.
+    fn write_nodes(&mut self, nodes: &[(&Offset, &Node)]) -> Result<(), Error> {
+        for (offset, node) in nodes {
+            self.pager
+                .write_page_at_offset(Page::try_from(*node)?, offset)?;
+        }
+        Ok(())
+    }
+
+    /// health returns a quick status of the tree without walking its nodes.
+    pub fn health(&mut self) -> Health {
+        let header_valid = self
+            .pager
+            .get_page(&Offset(HEADER_OFFSET))
+            .and_then(Header::try_from)
+            .is_ok_and(|header| header.root_offset == self.root_offset);
+        let pages = self.pager.num_pages();
+        Health {
+            header_valid,
+            pages,
+            free_pages: pages - self.pager.allocated_pages().len(),
+        }
+    }
+
+    /// gc walks every page reachable from the root and returns all other allocated pages
+    /// to the free list, reclaiming pages leaked by interrupted multi-page operations.
+    /// Returns the number of reclaimed pages.
+    pub fn gc(&mut self) -> Result<usize, Error> {
+        let mut reachable = HashSet::new();
+        reachable.insert(Offset(HEADER_OFFSET));
+        self.walk(|offset, _| {
+            reachable.insert(*offset);
+        })?;
+
+        let mut reclaimed = 0;
+        for offset in self.pager.allocated_pages() {
+            if !reachable.contains(&offset) {
+                self.pager.free_page(&offset)?;
+                reclaimed += 1;
+            }
+        }
+        if reclaimed > 0 {
+            self.lifetime.recoveries += 1;
+            self.write_header()?;
+        }
+        Ok(reclaimed)
+    }
+
+    /// join merge-joins the pairs of this tree within left_range with the pairs of right
+    /// within right_range, yielding both pairs for every key found on both sides
+    /// in ascending key order.
+    pub fn join<'a, L, R>(
+        &'a mut self,
+        left_range: L,
+        right: &'a mut BTree,
+        right_range: R,
+    ) -> Join<'a>
+    where
+        L: RangeBounds<String>,
+        R: RangeBounds<String>,
+    {
+        Join::new(self.scan(left_range), right.scan(right_range))
+    }
+
+    /// split_ranges divides the keyspace into at most n contiguous ranges of roughly
+    /// equal size using the separator keys of the internal nodes, descending one
+    /// level at a time until there are enough separators. Scanning every returned
+    /// range visits each pair exactly once.
+    pub fn split_ranges(&mut self, n: usize) -> Result<Vec<KeyRange>, Error> {
+        self.ensure_current()?;
+        self.revalidate()?;
+        let mut separators: Vec<String> = Vec::new();
+        let mut level = vec![self.root_offset];
+        while separators.len() + 1 < n && !level.is_empty() {
+            let mut next_level = Vec::new();
+            for offset in level.iter() {
+                if let NodeType::Internal(children, keys) = self.read_node(offset)?.node_type {
+                    separators.extend(keys.into_iter().map(|Key(key)| key));
+                    next_level.extend(children);
+                }
+            }
+            level = next_level;
+        }
+        separators.sort();
+        separators.dedup();
+
+        let parts = cmp::min(n, separators.len() + 1).max(1);
+        let mut ranges = Vec::with_capacity(parts);
+        let mut start = Bound::Unbounded;
+        for i in 1..parts {
+            let separator = separators[i * separators.len() / parts].clone();
+            ranges.push((start, Bound::Included(separator.clone())));
+            start = Bound::Excluded(separator);
+        }
+        ranges.push((start, Bound::Unbounded));
+        Ok(ranges)
+    }
+
+    /// par_scan calls f on every pair within range from the rayon thread pool.
+    /// The keyspace is divided with split_ranges, one share per thread of the pool,
+    /// and each share is scanned through its own snapshot of the tree, so f is called
+    /// concurrently and in no particular order.
+    #[cfg(feature = "rayon")]
+    pub fn par_scan<R, F>(&mut self, range: R, f: F) -> Result<(), Error>
+    where
+        R: RangeBounds<String>,
+        F: Fn(KeyValuePair) + Sync,
+    {
+        use rayon::prelude::*;
+
+        let range: KeyRange = (range.start_bound().cloned(), range.end_bound().cloned());
+        let mut snapshots = Vec::new();
+        for part in self.split_ranges(rayon::current_num_threads())? {
+            snapshots.push((self.snapshot()?, scan::intersect(&range, &part)));
+        }
+        snapshots.into_par_iter().try_for_each(|(mut tree, part)| {
+            for kv in tree.scan(part) {
+                f(kv?);
+            }
+            Ok(())
+        })
+    }
+
+    /// snapshot opens a read-only view of the tree through a separate handle on its file.
+    /// It reads like the tree itself, only the hooks run on writes are left out.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn snapshot(&self) -> Result<BTree, Error> {
+        Ok(BTree {
+            pager: Pager::open(self.pager.path())?,
+            b: self.b,
+            root_offset: self.root_offset,
+            value_version: self.value_version,
+            key_policy: self.key_policy,
+            key_validator: self.key_validator.clone(),
+            write_mode: self.write_mode,
+            quotas: Quotas::default(),
+            sampler: AccessSampler::new(0),
+            hot_keys: None,
+            write_rates: None,
+            audit_log: None,
+            trash: None,
+            expiry: match &self.expiry {
+                Some(expiry) => Some(Box::new(expiry.snapshot()?)),
+                None => None,
+            },
+            logical_bytes: 0,
+            lifetime: self.lifetime,
+            height: self.height,
+            len: self.len,
+            len_dirty: false,
+            generation: self.generation,
+            stale_checks: self.stale_checks,
+            revalidate_every: self.revalidate_every,
+            validated_at: self.validated_at,
+            merge_operator: self.merge_operator.clone(),
+            deferred_merges: self.deferred_merges,
+        })
+    }
+
+    /// cursor returns an unpositioned cursor over the pairs of the tree.
+    pub fn cursor(&mut self) -> Cursor<'_> {
+        Cursor::new(self)
+    }
+
+    /// scan returns the pairs within range in ascending key order, reading one leaf at a time.
+    pub fn scan<R: RangeBounds<String>>(&mut self, range: R) -> Scan<'_> {
+        Scan::new(self, range)
+    }
+
+    /// scan_prefix returns the pairs whose key starts with prefix in ascending key order,
+    /// seeking straight to the first of them and stopping at the first key past them.
+    pub fn scan_prefix(&mut self, prefix: &str) -> Scan<'_> {
+        self.scan(scan::prefix_range(prefix))
+    }
+
+    /// scan_rev returns the pairs within range in descending key order.
+    pub fn scan_rev<R: RangeBounds<String>>(&mut self, range: R) -> Scan<'_> {
+        Scan::new_rev(self, range)
+    }
+
+    /// count_range returns the number of keys within range. Only the subtrees overlapping
+    /// range are visited, and leaves are counted straight from their pages without
+    /// deserializing their pairs.
+    pub fn count_range<R: RangeBounds<String>>(&mut self, range: R) -> Result<u64, Error> {
+        self.ensure_current()?;
+        self.revalidate()?;
+        let range: KeyRange = (range.start_bound().cloned(), range.end_bound().cloned());
+        let root_offset = self.root_offset;
+        self.count_subtree(root_offset, &range, Overlap::Partial, None, None)
+    }
+
+    /// count_subtree counts the keys within range of the subtree at offset,
+    /// whose keys lie in (lower, upper] and relate to range as given by overlap.
+    fn count_subtree(
+        &mut self,
+        offset: Offset,
+        range: &KeyRange,
+        overlap: Overlap,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+    ) -> Result<u64, Error> {
+        let page = self.pager.get_page(&offset)?;
+        let node_type = page.get_ptr_from_offset(NODE_TYPE_OFFSET, 1)[0];
+        if let NodeType::Leaf(_) = NodeType::from(node_type) {
+            let num_pairs = page.get_value_from_offset(LEAF_NODE_NUM_PAIRS_OFFSET)?;
+            if overlap == Overlap::Covered {
+                return Ok(num_pairs as u64);
+            }
+            // Only the key bytes of the boundary leaves are compared.
+            let mut count = 0;
+            let mut pair_offset = LEAF_NODE_HEADER_SIZE;
+            for _i in 0..num_pairs {
+                let raw_key = trim_padding(page.get_ptr_from_offset(pair_offset, KEY_SIZE));
+                let key = str::from_utf8(raw_key).map_err(|e| Error::InvalidUtf8 {
+                    offset: pair_offset + e.valid_up_to(),
+                })?;
+                if scan::after_start(&range.0, key) && scan::before_end(&range.1, key) {
+                    count += 1;
+                }
+                pair_offset += KEY_SIZE + VALUE_SIZE;
+            }
+            return Ok(count);
+        }
+        let (children, keys) = match Node::try_from(page)?.node_type {
+            NodeType::Internal(children, keys) => (children, keys),
+            _ => return Err(Error::UnexpectedError),
+        };
+        let mut count = 0;
+        for (idx, child) in children.into_iter().enumerate() {
+            let lo = if idx == 0 { lower } else { keys.get(idx - 1) };
+            let hi = keys.get(idx).or(upper);
+            let overlap = match overlap {
+                Overlap::Covered => Overlap::Covered,
+                _ => scan::overlap(range, lo, hi),
+            };
+            if overlap != Overlap::Disjoint {
+                count += self.count_subtree(child, range, overlap, lo, hi)?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// keys returns every key of the tree in ascending order.
+    pub fn keys(&mut self) -> Keys<'_> {
+        Keys::new(self.scan(..))
+    }
+
+    /// values returns every value of the tree in ascending order of their keys.
+    pub fn values(&mut self) -> Values<'_> {
+        Values::new(self.scan(..))
+    }
+
+    /// scan_chunks returns the pairs within range in ascending key order,
+    /// batched into vectors of up to chunk_size pairs.
+    pub fn scan_chunks<R: RangeBounds<String>>(
+        &mut self,
+        range: R,
+        chunk_size: usize,
+    ) -> ScanChunks<'_> {
+        ScanChunks::new(self.scan(range), chunk_size)
+    }
+
+    /// update_range calls f with the key and value of every pair within range, replacing
+    /// the value with the returned one or deleting the pair on None. Each affected leaf
+    /// is rewritten once, then the nodes left short of pairs by deletions are rebalanced
+    /// as with delete_range. Deleted pairs are moved to the trash if one is set, like
+    /// with delete. Returns the number of pairs updated or deleted. On error, the leaves
+    /// rewritten so far keep their changes.
+    pub fn update_range<R, F>(&mut self, range: R, mut f: F) -> Result<usize, Error>
+    where
+        R: RangeBounds<String>,
+        F: FnMut(&str, &str) -> Option<String>,
+    {
+        let range: KeyRange = (range.start_bound().cloned(), range.end_bound().cloned());
+        let (mut updated, mut removed) = (0, 0);
+        let res = self.update_leaves(&range, &mut f, &mut updated, &mut removed);
+        if removed > 0 && !self.deferred_merges {
+            self.rebalance_range(&range)?;
+        }
+        res.map(|()| updated)
+    }
+
+    /// update_leaves applies f to the pairs within range for update_range, counting the
+    /// pairs updated or deleted and the pairs deleted as it goes.
+    fn update_leaves<F>(
+        &mut self,
+        range: &KeyRange,
+        f: &mut F,
+        updated: &mut usize,
+        removed: &mut usize,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&str, &str) -> Option<String>,
+    {
+        let (start, end) = range;
+        let mut leaves = Leaves::new(self, start.clone());
+        let mut past_end = false;
+        while !past_end {
+            let (offset, mut node) = match leaves.next_leaf()? {
+                Some(leaf) => leaf,
+                None => break,
+            };
+            let pairs = match node.node_type {
+                NodeType::Leaf(pairs) => pairs,
+                _ => return Err(Error::UnexpectedError),
+            };
+            let tree = leaves.tree();
+            let mut res = Ok(());
+            let mut dirty = false;
+            let mut kept = Vec::with_capacity(pairs.len());
+            let mut mutations = Vec::new();
+            let mut trashed = Vec::new();
+            for kv in pairs {
+                past_end = past_end || !scan::before_end(end, &kv.key);
+                if res.is_err() || past_end || !scan::after_start(start, &kv.key) {
+                    kept.push(kv);
+                    continue;
+                }
+                let value = f(&kv.key, &kv.value);
+                match tree.update_pair(&kv, value) {
+                    Ok(new_kv) => {
+                        match &new_kv {
+                            Some(new_kv) if new_kv.value == kv.value => (),
+                            Some(_) => mutations.push((Mutation::Update, kv.key)),
+                            None => {
+                                mutations.push((Mutation::Delete, kv.key.clone()));
+                                trashed.push(kv);
+                            }
+                        }
+                        kept.extend(new_kv);
+                        dirty = true;
+                        *updated += 1;
+                    }
+                    Err(e) => {
+                        kept.push(kv);
+                        res = Err(e);
+                    }
+                }
+            }
+            if dirty {
+                tree.mark_len_dirty()?;
+                tree.len -= trashed.len();
+                *removed += trashed.len();
+                node.node_type = NodeType::Leaf(kept);
+                tree.pager
+                    .write_page_at_offset(Page::try_from(&node)?, &offset)?;
+                tree.sampler.record_write(&offset);
+            }
+            if let Some(trash) = tree.trash.as_mut() {
+                for kv in trashed.iter() {
+                    trash.put(kv)?;
+                }
+            }
+            for (mutation, key) in mutations {
+                tree.audit(mutation, &key)?;
+            }
+            res?;
+        }
+        Ok(())
+    }
+
+    /// rebalance_range restores the occupancy of the nodes holding keys within range
+    /// once pairs were removed from their leaves in place, then shrinks the root.
+    fn rebalance_range(&mut self, range: &KeyRange) -> Result<(), Error> {
+        let root_offset = self.root_offset;
+        self.rebalance_subtree(root_offset, range, None, None)?;
+        let mut root = self.read_node(&root_offset)?;
+        while let NodeType::Internal(_, keys) = &root.node_type {
+            if !keys.is_empty() {
+                break;
+            }
+            self.shrink_root(self.root_offset, root)?;
+            root = self.read_node(&self.root_offset.clone())?;
+        }
+        Ok(())
+    }
+
+    /// rebalance_subtree restores the occupancy of the subtree at offset, whose keys lie
+    /// in (lower, upper], bottom up and only below the children overlapping range.
+    fn rebalance_subtree(
+        &mut self,
+        offset: Offset,
+        range: &KeyRange,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+    ) -> Result<(), Error> {
+        let (children, keys) = match self.read_node(&offset)?.node_type {
+            NodeType::Internal(children, keys) => (children, keys),
+            _ => return Ok(()),
+        };
+        for (idx, child) in children.into_iter().enumerate() {
+            let lo = if idx == 0 { lower } else { keys.get(idx - 1) };
+            let hi = keys.get(idx).or(upper);
+            if scan::overlap(range, lo, hi) != Overlap::Disjoint {
+                self.rebalance_subtree(child, range, lo, hi)?;
+            }
+        }
+        self.restore_occupancy(offset)
+    }
+
+    /// retain removes every pair for which f returns false, walking the leaves in key
+    /// order and writing each modified leaf once, then rebalances the nodes left short
+    /// of pairs as with delete_range. Returns the number of pairs removed.
+    pub fn retain<F: FnMut(&str, &str) -> bool>(&mut self, mut f: F) -> Result<usize, Error> {
+        let mut removed = 0;
+        let res = self.retain_leaves(&mut f, &mut removed);
+        if removed > 0 && !self.deferred_merges {
+            self.rebalance_range(&(Bound::Unbounded, Bound::Unbounded))?;
+        }
+        res.map(|()| removed)
+    }
+
+    /// retain_leaves removes the pairs f rejects for retain, counting them as it goes.
+    fn retain_leaves<F: FnMut(&str, &str) -> bool>(
+        &mut self,
+        f: &mut F,
+        removed: &mut usize,
+    ) -> Result<(), Error> {
+        let mut leaves = Leaves::new(self, Bound::Unbounded);
+        while let Some((offset, mut node)) = leaves.next_leaf()? {
+            let pairs = match node.node_type {
+                NodeType::Leaf(pairs) => pairs,
+                _ => return Err(Error::UnexpectedError),
+            };
+            let (kept, gone): (Vec<_>, Vec<_>) =
+                pairs.into_iter().partition(|kv| f(&kv.key, &kv.value));
+            if gone.is_empty() {
+                continue;
+            }
+            let tree = leaves.tree();
+            if !tree.write_mode.allows_delete() {
+                return Err(Error::Immutable {
+                    key: gone[0].key.clone(),
+                });
+            }
+            tree.mark_len_dirty()?;
+            tree.len -= gone.len();
+            node.node_type = NodeType::Leaf(kept);
+            tree.pager
+                .write_page_at_offset(Page::try_from(&node)?, &offset)?;
+            tree.sampler.record_write(&offset);
+            *removed += gone.len();
+            tree.discard_pairs(gone)?;
+        }
+        Ok(())
+    }
+
+    /// update_pair validates and accounts for replacing old with value, or removing it on None,
+    /// returning the pair to store. Nothing is recorded if the update is rejected.
+    fn update_pair(
+        &mut self,
+        old: &KeyValuePair,
+        value: Option<String>,
+    ) -> Result<Option<KeyValuePair>, Error> {
+        let kv = match value {
+            Some(value) => {
+                if value.len() > VALUE_SIZE {
+                    return Err(Error::ValueOverflowError);
+                }
+                if value != old.value && !self.write_mode.allows_overwrite() {
+                    return Err(Error::Immutable {
+                        key: old.key.clone(),
+                    });
+                }
+                let kv = KeyValuePair::new(old.key.clone(), value);
+                self.quotas.record_delete(old);
+                if let Err(e) = self.quotas.check(&kv) {
+                    self.quotas.record_insert(old);
+                    return Err(e);
+                }
+                self.quotas.record_insert(&kv);
+                self.logical_bytes += kv.value.len();
+                Some(kv)
+            }
+            None => {
+                if !self.write_mode.allows_delete() {
+                    return Err(Error::Immutable {
+                        key: old.key.clone(),
+                    });
+                }
+                self.quotas.record_delete(old);
+                None
+            }
+        };
+        self.record_key_write(&old.key)?;
+        self.logical_bytes += old.key.len();
+        Ok(kv)
+    }
+
+    /// copy_range inserts every pair of this tree within range into dest,
+    /// reading one batch of pairs at a time. Returns the number of pairs copied.
+    pub fn copy_range<R: RangeBounds<String>>(
+        &mut self,
+        range: R,
+        dest: &mut BTree,
+    ) -> Result<usize, Error> {
+        let mut copied = 0;
+        for chunk in self.scan_chunks(range, COPY_CHUNK_SIZE) {
+            for kv in chunk? {
+                dest.insert(kv)?;
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// export_subtree writes every pair whose key starts with prefix into a new tree file
+    /// at dest, with the same b parameter, independent of this tree once written.
+    /// The pairs are bulk loaded one sorted batch at a time with insert_all.
+    /// Returns the number of pairs exported.
+    pub fn export_subtree(&mut self, prefix: &str, dest: &'static Path) -> Result<usize, Error> {
+        // Building the destination truncates it, which must never hit this tree's own file.
+        if dest == self.pager.path() {
+            return Err(Error::UnexpectedError);
+        }
+        let mut dest = BTreeBuilder::new().path(dest).b_parameter(self.b).build()?;
+        let mut exported = 0;
+        for chunk in self.scan_chunks(scan::prefix_range(prefix), COPY_CHUNK_SIZE) {
+            exported += dest.insert_all(chunk?)?;
+        }
+        dest.persist_stats()?;
+        Ok(exported)
+    }
+
+    /// value_version returns the format version of the stored values, as recorded in the header.
+    pub fn value_version(&self) -> usize {
+        self.value_version
+    }
+
+    /// migrate brings every stored value up to the latest version of migrations, running
+    /// each pending migration over the whole tree in turn. The header records the new
+    /// version after every migration completes, so a migration interrupted by a crash
+    /// runs again over values it may have already rewritten. Returns the new version.
+    pub fn migrate(&mut self, migrations: &Migrations) -> Result<usize, Error> {
+        while let Some(migration) = migrations.get(self.value_version) {
+            self.update_range(.., |key, value| Some(migration(key, value)))?;
+            self.value_version += 1;
+            self.write_header()?;
+        }
+        Ok(self.value_version)
+    }
+
+    /// export writes the pairs selected by filter to writer in ascending key order,
+    /// one tab separated key and value per line, and returns the number of pairs written.
+    pub fn export<W: Write>(
+        &mut self,
+        writer: &mut W,
+        filter: &ExportFilter,
+    ) -> Result<usize, Error> {
+        let mut exported = 0;
+        for kv in self.scan(..) {
+            if let Some(kv) = filter.apply(kv?) {
+                export::write_pair(writer, &kv)?;
+                exported += 1;
+            }
+        }
+        Ok(exported)
+    }
+
+    /// scoped returns a handle whose operations are confined to keys starting with prefix.
+    pub fn scoped(&mut self, prefix: &str) -> Scoped<'_> {
+        Scoped::new(self, prefix)
+    }
+
+    /// heatmap returns the sampled accesses of every leaf still in the tree, hottest first.
+    /// Sampling is enabled with BTreeBuilder::access_sampling.
+    pub fn heatmap(&mut self) -> Result<Vec<LeafHeat>, Error> {
+        let sampled: Vec<(Offset, usize, usize)> = self
+            .sampler
+            .leaves()
+            .map(|(offset, reads, writes)| (*offset, reads, writes))
+            .collect();
+        let mut heatmap = Vec::new();
+        for (offset, reads, writes) in sampled {
+            // Skip leaves that were merged away since they were sampled.
+            if !self.pager.is_allocated(&offset) {
+                continue;
+            }
+            if let NodeType::Leaf(pairs) = Node::try_from(self.pager.get_page(&offset)?)?.node_type
+            {
+                let range = match (pairs.first(), pairs.last()) {
+                    (Some(first), Some(last)) => Some((first.key.clone(), last.key.clone())),
+                    _ => None,
+                };
+                heatmap.push(LeafHeat {
+                    offset,
+                    range,
+                    reads,
+                    writes,
+                });
+            }
+        }
+        heatmap.sort_by_key(|heat| cmp::Reverse(heat.reads + heat.writes));
+        Ok(heatmap)
+    }
+
+    /// pages returns the layout of every page reachable from the root,
+    /// parents before their children.
+    pub fn pages(&mut self) -> Pages<'_> {
+        let b = self.b;
+        Pages::new(self, b)
+    }
+
+    /// write_rates returns the estimated write rates of the busiest key prefixes,
+    /// or nothing if BTreeBuilder::write_rates was not configured.
+    pub fn write_rates(&self) -> Vec<PrefixRate> {
+        self.write_rates
+            .as_ref()
+            .map_or_else(Vec::new, |write_rates| write_rates.rates())
+    }
+
+    /// io_stats returns the cumulative logical and physical bytes written to the tree.
+    pub fn io_stats(&self) -> IoStats {
+        IoStats {
+            logical_bytes: self.logical_bytes,
+            physical_bytes: self.pager.bytes_written(),
+        }
+    }
+
+    /// lifetime_stats returns the counters accumulated since the tree file was created,
+    /// including previous processes. Counters are persisted with every header write,
+    /// use BTree::persist_stats to persist them explicitly, e.g. before closing.
+    pub fn lifetime_stats(&self) -> LifetimeStats {
+        LifetimeStats {
+            logical_bytes: self.lifetime.logical_bytes + self.logical_bytes,
+            physical_bytes: self.lifetime.physical_bytes + self.pager.bytes_written(),
+            ..self.lifetime
+        }
+    }
+
+    /// persist_stats writes the current lifetime counters and pair count to the header.
+    /// It is also done when the tree is dropped, ignoring errors.
+    pub fn persist_stats(&mut self) -> Result<(), Error> {
+        self.len_dirty = false;
+        self.write_header()
+    }
+
+    /// read_node reads and deserializes the node at offset.
+    pub(crate) fn read_node(&mut self, offset: &Offset) -> Result<Node, Error> {
+        Node::try_from(self.pager.get_page(offset)?)
+    }
+
+    /// root_offset returns the offset of the current root node.
+    pub(crate) fn root_offset(&self) -> Offset {
+        self.root_offset
+    }
+
+    /// walk visits every node reachable from the root, parents before their children.
+    fn walk<F: FnMut(&Offset, &Node)>(&mut self, mut visit: F) -> Result<(), Error> {
+        let mut stack = vec![self.root_offset];
+        while let Some(offset) = stack.pop() {
+            let node = Node::try_from(self.pager.get_page(&offset)?)?;
+            visit(&offset, &node);
+            if let NodeType::Internal(children, _) = node.node_type {
+                stack.extend(children.into_iter().rev());
+            }
+        }
+        Ok(())
+    }
+
+    /// len returns the number of pairs in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// mark_len_dirty marks the pair count in the header as stale before the first
+    /// modification since it was last recorded, so that a crash before the next
+    /// header write makes opening the tree recount the pairs.
+    fn mark_len_dirty(&mut self) -> Result<(), Error> {
+        if !self.len_dirty {
+            self.len_dirty = true;
+            self.write_header()?;
+        }
+        Ok(())
+    }
+
+    /// height returns the number of levels of the tree, a lone root leaf being one level.
+    fn height(&mut self) -> Result<usize, Error> {
+        if let Some(height) = self.height {
+            return Ok(height);
+        }
+        let mut height = 1;
+        let mut offset = self.root_offset;
+        while let NodeType::Internal(children, _) = self.read_node(&offset)?.node_type {
+            offset = *children.first().ok_or(Error::UnexpectedError)?;
+            height += 1;
+        }
+        self.height = Some(height);
+        Ok(height)
+    }
+
+    /// write_header persists the current root offset, b parameter
+    /// and lifetime counters to the header page.
+    fn write_header(&mut self) -> Result<(), Error> {
+        let header = Header {
+            root_offset: self.root_offset,
+            b: self.b,
+            value_version: self.value_version,
+            lifetime: self.lifetime_stats(),
+            len: if self.len_dirty { None } else { Some(self.len) },
+            generation: self.generation,
+        };
+        // The root may have changed.
+        self.height = None;
+        self.pager
+            .write_page_at_offset(Page::try_from(&header)?, &Offset(HEADER_OFFSET))
+    }
+
+    /// replace_with replaces the tree file with the tree stored at new_path (e.g. the output
+    /// of a compaction or an import) and rebinds this handle to it.
+    /// The new file is validated and synced before it is atomically renamed over the current
+    /// file, and the directory is synced after the rename so the swap survives a crash.
+    pub fn replace_with(&mut self, new_path: &Path) -> Result<(), Error> {
+        let mut new_pager = Pager::open(new_path)?;
+        let mut header = Header::try_from(new_pager.get_page(&Offset(HEADER_OFFSET))?)?;
+        // Stamp the new file before it becomes visible, so that handles still reading
+        // the current one can tell it was swapped out.
+        header.generation = self.generation + 1;
+        new_pager.write_page_at_offset(Page::try_from(&header)?, &Offset(HEADER_OFFSET))?;
+        new_pager.sync()?;
+        drop(new_pager);
+
+        let path = self.pager.path().to_path_buf();
+        fs::rename(new_path, &path)?;
+        pager::sync_dir(&path)?;
+        // The counters carry over to the new file, which continues the life of this one.
+        let lifetime = self.lifetime_stats();
+        self.rebind(Pager::open(&path)?)?;
+        self.lifetime = LifetimeStats {
+            logical_bytes: lifetime.logical_bytes - self.logical_bytes,
+            physical_bytes: lifetime.physical_bytes,
+            compactions: lifetime.compactions + 1,
+            ..lifetime
+        };
+        self.write_header()
+    }
+
+    /// generation returns the number of times the file this handle reads was replaced.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// is_stale returns true if the file at the tree's path was replaced by another
+    /// handle since this one read it, in which case this handle still reads the old file.
+    pub fn is_stale(&self) -> Result<bool, Error> {
+        let header = Header::try_from(Pager::read_header_page(self.pager.path())?)?;
+        Ok(header.generation != self.generation)
+    }
+
+    /// staleness returns how long ago this handle last read the header,
+    /// i.e. how far its view of the tree may lag behind a writer using another handle.
+    pub fn staleness(&self) -> Duration {
+        self.validated_at.elapsed()
+    }
+
+    /// refresh rereads the header, picking up the root and pair count last recorded
+    /// by a writer using another handle. Fails with Error::StaleHandle if the file
+    /// was replaced, since this handle still reads the old one.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        if self.is_stale()? {
+            return Err(Error::StaleHandle);
+        }
+        let header = Header::try_from(self.pager.get_page(&Offset(HEADER_OFFSET))?)?;
+        self.root_offset = header.root_offset;
+        self.b = header.b;
+        self.value_version = header.value_version;
+        self.len = header.len.unwrap_or(self.len);
+        self.height = None;
+        self.validated_at = Instant::now();
+        Ok(())
+    }
+
+    /// revalidate refreshes the header if it is older than BTreeBuilder::revalidate_every.
+    pub(crate) fn revalidate(&mut self) -> Result<(), Error> {
+        match self.revalidate_every {
+            Some(every) if self.validated_at.elapsed() >= every => self.refresh(),
+            _ => Ok(()),
+        }
+    }
+
+    /// ensure_current fails with Error::StaleHandle if stale checks are enabled
+    /// and the file was replaced.
+    pub(crate) fn ensure_current(&self) -> Result<(), Error> {
+        if self.stale_checks && self.is_stale()? {
+            return Err(Error::StaleHandle);
+        }
+        Ok(())
+    }
+
+    /// rebind points this handle at the tree stored in pager, resetting any state
+    /// derived from the previous file.
+    fn rebind(&mut self, mut pager: Pager) -> Result<(), Error> {
+        let header = Header::try_from(pager.get_page(&Offset(HEADER_OFFSET))?)?;
+        pager.set_secure_wipe(self.pager.secure_wipe());
+        pager.set_max_size(self.pager.max_size());
+        self.pager = pager;
+        self.root_offset = header.root_offset;
+        self.b = header.b;
+        self.value_version = header.value_version;
+        self.generation = header.generation;
+        self.validated_at = Instant::now();
+        self.height = None;
+        self.sampler.reset();
+        self.len_dirty = false;
+        self.restore_state(header.len)?;
+        // Return pages orphaned in the new file to the free list.
+        self.gc()?;
+        Ok(())
+    }
+
+    /// restore_state recomputes the in-memory state derived from the contents of the file,
+    /// len being the pair count recorded in the header.
+    fn restore_state(&mut self, len: Option<usize>) -> Result<(), Error> {
+        if let Some(len) = len {
+            self.len = len;
+            if self.quotas.is_empty() {
+                return Ok(());
+            }
+        }
+        let mut quotas = self.quotas.clone();
+        quotas.reset();
+        let mut count = 0;
+        self.walk(|_, node| {
+            if let NodeType::Leaf(pairs) = &node.node_type {
+                count += pairs.len();
+                pairs.iter().for_each(|kv| quotas.record_insert(kv));
+            }
+        })?;
+        self.quotas = quotas;
+        self.len = count;
+        Ok(())
+    }
+
+    /// print_sub_tree is a helper function for recursively printing the nodes rooted at a node given by its offset.
+    fn print_sub_tree(&mut self, prefix: String, offset: Offset) -> Result<(), Error> {
+        println!("{}Node at offset: {}", prefix, offset.0);
+        let curr_prefix = format!("{}|->", prefix);
+        let page = self.pager.get_page(&offset)?;
+        let node = Node::try_from(page)?;
+        match node.node_type {
+            NodeType::Internal(children, keys) => {
+                println!("{}Keys: {:?}", curr_prefix, keys);
+                println!("{}Children: {:?}", curr_prefix, children);
+                let child_prefix = format!("{}   |  ", prefix);
+                for child_offset in children {
+                    self.print_sub_tree(child_prefix.clone(), child_offset)?;
+                }
+                Ok(())
+            }
+            NodeType::Leaf(pairs) => {
+                println!("{}Key value pairs: {:?}", curr_prefix, pairs);
+                Ok(())
+            }
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// print is a helper for recursively printing the tree.
+    pub fn print(&mut self) -> Result<(), Error> {
+        println!();
+        self.print_sub_tree("".to_string(), self.root_offset)
+    }
+}
+
+/// trim_padding strips the zero bytes padding a fixed size key or value.
+fn trim_padding(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0x00).unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|b| *b != 0x00)
+        .map_or(start, |idx| idx + 1);
+    &bytes[start..end]
+}
+
+/// Extending a tree inserts the pairs with BTree::insert_all.
+impl Extend<KeyValuePair> for BTree {
+    /// extend inserts every pair of pairs, see BTree::insert_all.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any pair cannot be stored, use insert_all to handle the error instead.
+    fn extend<I: IntoIterator<Item = KeyValuePair>>(&mut self, pairs: I) {
+        if let Err(e) = self.insert_all(pairs) {
+            panic!("failed to extend the tree: {:?}", e);
+        }
+    }
+}
+
+impl Drop for BTree {
+    /// drop records the pair count in the header, so reopening the tree need not recount.
+    fn drop(&mut self) {
+        if self.len_dirty {
+            let _ = self.persist_stats();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn search_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_search"))
+            .b_parameter(2)
+            .build()?;
+        btree.insert(KeyValuePair::new("a".to_string(), "shalom".to_string()))?;
+        btree.insert(KeyValuePair::new("b".to_string(), "hello".to_string()))?;
+        btree.insert(KeyValuePair::new("c".to_string(), "marhaba".to_string()))?;
+
+        let mut kv = btree.search("b".to_string())?;
+        assert_eq!(kv.key, "b");
+        assert_eq!(kv.value, "hello");
+
+        kv = btree.search("c".to_string())?;
+        assert_eq!(kv.key, "c");
+        assert_eq!(kv.value, "marhaba");
+
+        // Sanity check:
+        btree.print()
+    }
+
+    #[test]
+    fn get_and_contains_key() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_get"))
+            .b_parameter(2)
+            .build()?;
+        btree.insert(KeyValuePair::new("a".to_string(), "shalom".to_string()))?;
+
+        assert!(btree.contains_key("a")?);
+        assert!(!btree.contains_key("b")?);
+        assert_eq!(
+            btree.get("a")?,
+            Some(KeyValuePair::new("a".to_string(), "shalom".to_string()))
+        );
+        assert_eq!(btree.get("b")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn first_and_last() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_first_last"))
+            .b_parameter(2)
+            .build()?;
+        assert_eq!(btree.first()?, None);
+        assert_eq!(btree.last()?, None);
+        for key in ["m", "c", "x", "a", "q", "z", "f"].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), "v".to_string()))?;
+        }
+        assert_eq!(btree.first()?.map(|kv| kv.key), Some("a".to_string()));
+        assert_eq!(btree.last()?.map(|kv| kv.key), Some("z".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn pop_first_and_last() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_pop"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..10 {
+            btree.insert(KeyValuePair::new(format!("k{}", i), i.to_string()))?;
+        }
+        let mut popped = Vec::new();
+        while let Some(kv) = btree.pop_first()? {
+            popped.push(kv.key);
+            if let Some(kv) = btree.pop_last()? {
+                popped.push(kv.key);
+            }
+        }
+        assert_eq!(
+            popped,
+            vec!["k0", "k9", "k1", "k8", "k2", "k7", "k3", "k6", "k4", "k5"]
+        );
+        assert!(btree.is_empty());
+        assert_eq!(btree.pop_last()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn insert_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_insert"))
+            .b_parameter(2)
+            .build()?;
+        btree.insert(KeyValuePair::new("a".to_string(), "shalom".to_string()))?;
+        btree.insert(KeyValuePair::new("b".to_string(), "hello".to_string()))?;
+        btree.insert(KeyValuePair::new("c".to_string(), "marhaba".to_string()))?;
+        btree.insert(KeyValuePair::new("d".to_string(), "olah".to_string()))?;
+        btree.insert(KeyValuePair::new("e".to_string(), "salam".to_string()))?;
+        btree.insert(KeyValuePair::new("f".to_string(), "hallo".to_string()))?;
+        btree.insert(KeyValuePair::new("g".to_string(), "Konnichiwa".to_string()))?;
+        btree.insert(KeyValuePair::new("h".to_string(), "Ni hao".to_string()))?;
+        btree.insert(KeyValuePair::new("i".to_string(), "Ciao".to_string()))?;
+
+        let mut kv = btree.search("a".to_string())?;
+        assert_eq!(kv.key, "a");
+        assert_eq!(kv.value, "shalom");
+
+        kv = btree.search("b".to_string())?;
+        assert_eq!(kv.key, "b");
+        assert_eq!(kv.value, "hello");
+
+        kv = btree.search("c".to_string())?;
+        assert_eq!(kv.key, "c");
+        assert_eq!(kv.value, "marhaba");
+
+        kv = btree.search("d".to_string())?;
+        assert_eq!(kv.key, "d");
+        assert_eq!(kv.value, "olah");
+
+        kv = btree.search("e".to_string())?;
+        assert_eq!(kv.key, "e");
+        assert_eq!(kv.value, "salam");
+
+        kv = btree.search("f".to_string())?;
+        assert_eq!(kv.key, "f");
+        assert_eq!(kv.value, "hallo");
+
+        kv = btree.search("g".to_string())?;
+        assert_eq!(kv.key, "g");
+        assert_eq!(kv.value, "Konnichiwa");
+
+        kv = btree.search("h".to_string())?;
+        assert_eq!(kv.key, "h");
+        assert_eq!(kv.value, "Ni hao");
+
+        kv = btree.search("i".to_string())?;
+        assert_eq!(kv.key, "i");
+        assert_eq!(kv.value, "Ciao");
+
+        // Sanity check:
+        btree.print()
+    }
+
+    #[test]
+    fn insert_replaces_existing_value() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::quota::Quota;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_insert_replace"))
+            .b_parameter(2)
+            .quota("q/", Quota::Bytes(8))
+            .build()?;
+        for i in 0..10 {
+            assert_eq!(
+                btree.insert(KeyValuePair::new(format!("k{}", i), "old".to_string()))?,
+                None
+            );
+        }
+        let old = btree.insert(KeyValuePair::new("k4".to_string(), "new".to_string()))?;
+        assert_eq!(
+            old,
+            Some(KeyValuePair::new("k4".to_string(), "old".to_string()))
+        );
+        assert_eq!(btree.search("k4".to_string())?.value, "new");
+        assert_eq!(btree.len(), 10);
+        assert_eq!(btree.scan(..).count(), 10);
+
+        assert!(!btree.insert_if_absent(KeyValuePair::new("k4".to_string(), "x".to_string()))?);
+        assert_eq!(btree.search("k4".to_string())?.value, "new");
+        assert!(btree.insert_if_absent(KeyValuePair::new("k10".to_string(), "x".to_string()))?);
+
+        // Replacing a value only counts the difference against the quota.
+        btree.insert(KeyValuePair::new("q/a".to_string(), "abcd".to_string()))?;
+        btree.insert(KeyValuePair::new("q/a".to_string(), "efgh".to_string()))?;
+        assert!(matches!(
+            btree.insert(KeyValuePair::new("q/a".to_string(), "abcdef".to_string())),
+            Err(Error::QuotaExceeded { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn upsert_computes_from_existing_value() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_upsert"))
+            .b_parameter(2)
+            .build()?;
+        let incr = |value: Option<&str>| {
+            let count: u32 = value.map_or(0, |value| value.parse().unwrap_or(0));
+            (count + 1).to_string()
+        };
+        for _ in 0..3 {
+            for key in ["a", "b", "c", "d", "e"].iter() {
+                btree.upsert(key, incr)?;
+            }
+        }
+        let old = btree.upsert("c", incr)?;
+        assert_eq!(old.map(|kv| kv.value), Some("3".to_string()));
+        assert_eq!(btree.search("c".to_string())?.value, "4");
+        assert_eq!(btree.search("e".to_string())?.value, "3");
+        assert_eq!(btree.len(), 5);
+
+        assert!(matches!(
+            btree.upsert("a", |_| "a value too long".to_string()),
+            Err(Error::ValueOverflowError)
+        ));
+        assert_eq!(btree.search("a".to_string())?.value, "3");
+        Ok(())
+    }
+
+    #[test]
+    fn apply_batch_validates_before_writing() -> Result<(), Error> {
+        use crate::batch::WriteBatch;
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_batch"))
+            .b_parameter(2)
+            .build()?;
+        let mut batch = WriteBatch::new();
+        for i in 0..10 {
+            batch.put(KeyValuePair::new(format!("k{}", i), i.to_string()));
+        }
+        batch
+            .delete(Key("k3".to_string()))
+            .delete(Key("missing".to_string()));
+        btree.apply_batch(&batch)?;
+        assert_eq!(btree.len(), 9);
+        assert!(!btree.contains_key("k3")?);
+
+        // An oversized value rejects the batch before the first put is applied.
+        let mut batch = WriteBatch::new();
+        batch
+            .put(KeyValuePair::new("a".to_string(), "b".to_string()))
+            .put(KeyValuePair::new(
+                "c".to_string(),
+                "a value too long".to_string(),
+            ));
+        assert!(matches!(
+            btree.apply_batch(&batch),
+            Err(Error::ValueOverflowError)
+        ));
+        assert!(!btree.contains_key("a")?);
+        Ok(())
+    }
+
+    #[test]
+    fn apply_batch_checks_quotas_and_write_mode() -> Result<(), Error> {
+        use crate::batch::WriteBatch;
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::quota::Quota;
+        use crate::write_mode::WriteMode;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_batch_checks"))
+            .b_parameter(2)
+            .write_mode(WriteMode::WriteOnce)
+            .quota("q", Quota::Entries(2))
+            .build()?;
+        btree.insert(KeyValuePair::new("a".to_string(), "a".to_string()))?;
+
+        // The put of an existing key comes last, nothing before it is applied.
+        let mut batch = WriteBatch::new();
+        batch
+            .put(KeyValuePair::new("b".to_string(), "b".to_string()))
+            .put(KeyValuePair::new("a".to_string(), "c".to_string()));
+        assert!(matches!(
+            btree.apply_batch(&batch),
+            Err(Error::KeyAlreadyExists)
+        ));
+        assert!(!btree.contains_key("b")?);
+
+        // A key deleted earlier in the batch may be put again.
+        let mut batch = WriteBatch::new();
+        batch
+            .delete(Key("a".to_string()))
+            .put(KeyValuePair::new("a".to_string(), "c".to_string()));
+        btree.apply_batch(&batch)?;
+        assert_eq!(btree.search("a".to_string())?.value, "c");
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put(KeyValuePair::new("q1".to_string(), "1".to_string()))
+            .put(KeyValuePair::new("q2".to_string(), "2".to_string()))
+            .put(KeyValuePair::new("q3".to_string(), "3".to_string()));
+        assert!(matches!(
+            btree.apply_batch(&batch),
+            Err(Error::QuotaExceeded { .. })
+        ));
+        assert!(!btree.contains_key("q1")?);
+        assert_eq!(btree.quota_usage("q").map(|usage| usage.entries), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn delete_rebalances_and_shrinks_tree() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::{self, CheckLevel};
+        use crate::header::Header;
+        use crate::node_type::{Key, KeyValuePair, NodeType};
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_delete"))
+            .b_parameter(2)
+            .build()?;
+        let n = 60;
+        for i in 0..n {
+            let key = format!("k{:02}", (i * 37) % n);
+            btree.insert(KeyValuePair::new(key, i.to_string()))?;
+        }
+
+        let mut remaining: Vec<String> = (0..n).map(|i| format!("k{:02}", i)).collect();
+        for i in 0..n {
+            let key = format!("k{:02}", (i * 23) % n);
+            btree.delete(Key(key.clone()))?;
+            remaining.retain(|k| *k != key);
+
+            assert!(matches!(btree.search(key.clone()), Err(Error::KeyNotFound)));
+            assert!(matches!(btree.delete(Key(key)), Err(Error::KeyNotFound)));
+            let header = Header {
+                root_offset: btree.root_offset,
+                b: btree.b,
+                value_version: 0,
+                lifetime: btree.lifetime,
+                len: Some(btree.len),
+                generation: btree.generation,
+            };
+            check::check(&mut btree.pager, &header, CheckLevel::Full)?;
+            let mut underflows = 0;
+            btree.walk(|_, node| {
+                let len = match &node.node_type {
+                    NodeType::Leaf(pairs) => pairs.len(),
+                    NodeType::Internal(_, keys) => keys.len(),
+                    NodeType::Unexpected => 0,
+                };
+                if !node.is_root && len < 1 {
+                    underflows += 1;
+                }
+            })?;
+            assert_eq!(underflows, 0);
+            let keys: Vec<String> = btree
+                .scan_chunks(.., n)
+                .flat_map(|chunk| chunk.unwrap())
+                .map(|kv| kv.key)
+                .collect();
+            assert_eq!(keys, remaining);
+        }
+
+        // Every page but the header and an empty root leaf was freed.
+        assert_eq!(btree.pager.allocated_pages().len(), 2);
+        assert_eq!(
+            btree.read_node(&btree.root_offset())?.node_type,
+            NodeType::Leaf(vec![])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn gc_reclaims_unreachable_pages() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node::Node;
+        use crate::node_type::{KeyValuePair, NodeType};
+        use crate::page::Page;
+        use std::convert::TryFrom;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_gc"))
+            .b_parameter(2)
+            .build()?;
+        for key in ["a", "b", "c", "d", "e"].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), "v".to_string()))?;
+        }
+        // Nothing is leaked by regular inserts.
+        assert_eq!(btree.gc()?, 0);
+
+        // Simulate a page leaked by a crashed operation.
+        let orphan = Node::new(NodeType::Leaf(vec![]), false, Some(btree.root_offset));
+        let orphan_offset = btree.pager.write_page(Page::try_from(&orphan)?)?;
+        assert_eq!(btree.gc()?, 1);
+        assert!(!btree.pager.allocated_pages().contains(&orphan_offset));
+
+        let kv = btree.search("e".to_string())?;
+        assert_eq!(kv.value, "v");
+        Ok(())
+    }
+
+    #[test]
+    fn replace_with_rebinds_to_new_file() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut live = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_replace"))
+            .b_parameter(2)
+            .build()?;
+        live.insert(KeyValuePair::new("old".to_string(), "v".to_string()))?;
+
+        let mut replacement = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_replace_new"))
+            .b_parameter(2)
+            .build()?;
+        for key in ["a", "b", "c", "d", "e"].iter() {
+            replacement.insert(KeyValuePair::new(key.to_string(), "new".to_string()))?;
+        }
+        drop(replacement);
+
+        live.replace_with(Path::new("/tmp/db_replace_new"))?;
+        assert!(!Path::new("/tmp/db_replace_new").exists());
+        assert!(matches!(
+            live.search("old".to_string()),
+            Err(Error::KeyNotFound)
+        ));
+        assert_eq!(live.search("c".to_string())?.value, "new");
+
+        // The rebound handle keeps working.
+        live.insert(KeyValuePair::new("f".to_string(), "v".to_string()))?;
+        assert_eq!(live.search("f".to_string())?.value, "v");
+        assert_eq!(live.search("a".to_string())?.value, "new");
+        Ok(())
+    }
+
+    #[test]
+    fn stale_checks_detect_replaced_file() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::CheckLevel;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let builder = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_stale"))
+            .b_parameter(2)
+            .stale_checks(true);
+        let mut live = builder.build()?;
+        live.insert(KeyValuePair::new("a".to_string(), "old".to_string()))?;
+        let mut reader = builder.open_with_check(CheckLevel::Header)?;
+        assert!(!reader.is_stale()?);
+        assert_eq!(reader.search("a".to_string())?.value, "old");
+
+        let mut replacement = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_stale_new"))
+            .b_parameter(2)
+            .build()?;
+        replacement.insert(KeyValuePair::new("a".to_string(), "new".to_string()))?;
+        drop(replacement);
+        live.replace_with(Path::new("/tmp/db_stale_new"))?;
+        assert_eq!(live.generation(), 1);
+
+        // The reader still holds the old file and refuses to serve from it.
+        assert!(reader.is_stale()?);
+        assert!(matches!(
+            reader.search("a".to_string()),
+            Err(Error::StaleHandle)
+        ));
+        assert!(matches!(reader.get_raw("a"), Err(Error::StaleHandle)));
+        assert!(matches!(
+            reader.insert_all(vec![KeyValuePair::new("b".to_string(), "b".to_string())]),
+            Err(Error::StaleHandle)
+        ));
+        assert!(matches!(
+            reader.scan(..).next(),
+            Some(Err(Error::StaleHandle))
+        ));
+        assert!(matches!(reader.first(), Err(Error::StaleHandle)));
+        assert!(matches!(reader.cursor().next(), Err(Error::StaleHandle)));
+        assert!(matches!(reader.count_range(..), Err(Error::StaleHandle)));
+        assert!(matches!(
+            reader.retain(|_, _| false),
+            Err(Error::StaleHandle)
+        ));
+        assert!(matches!(reader.delete_range(..), Err(Error::StaleHandle)));
+        assert!(matches!(reader.clear(), Err(Error::StaleHandle)));
+        let mut reader = builder.open_with_check(CheckLevel::Header)?;
+        assert_eq!(reader.generation(), 1);
+        assert_eq!(reader.search("a".to_string())?.value, "new");
+        Ok(())
+    }
+
+    #[test]
+    fn revalidating_reader_follows_writer() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::CheckLevel;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+        use std::time::Duration;
+
+        let mut writer = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_revalidate"))
+            .b_parameter(2)
+            .build()?;
+        writer.insert(KeyValuePair::new("k00".to_string(), "0".to_string()))?;
+        let mut eager = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_revalidate"))
+            .revalidate_every(Duration::from_secs(0))
+            .open_with_check(CheckLevel::Header)?;
+        let mut lazy = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_revalidate"))
+            .revalidate_every(Duration::from_secs(3600))
+            .open_with_check(CheckLevel::Header)?;
+
+        // The writer's root splits several times.
+        for i in 1..30 {
+            writer.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let scanned = eager.scan(..).collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(scanned.len(), 30);
+        assert_eq!(eager.last()?.unwrap().key, "k29");
+        let mut cursor = eager.cursor();
+        assert!(cursor.seek(&Key("k15".to_string()))?);
+        assert_eq!(cursor.key(), Some("k15"));
+        for i in 0..30 {
+            assert_eq!(eager.search(format!("k{:02}", i))?.value, i.to_string());
+        }
+        assert!(lazy.staleness() < Duration::from_secs(3600));
+        lazy.refresh()?;
+        assert_eq!(lazy.search("k29".to_string())?.value, "29");
+        Ok(())
+    }
+
+    #[test]
+    fn io_stats_tracks_write_amplification() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::page_layout::PAGE_SIZE;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_io_stats"))
+            .b_parameter(2)
+            .build()?;
+        // The first insert also reserves pages for future splits.
+        btree.insert(KeyValuePair::new("a".to_string(), "b".to_string()))?;
+        let before = btree.io_stats();
+        btree.insert(KeyValuePair::new("ab".to_string(), "cd".to_string()))?;
+        let stats = btree.io_stats().since(&before);
+        assert_eq!(stats.logical_bytes, 4);
+        assert_eq!(stats.physical_bytes, PAGE_SIZE);
+        assert_eq!(stats.write_amplification(), (PAGE_SIZE / 4) as f64);
+        Ok(())
+    }
+
+    #[test]
+    fn lifetime_stats_survive_reopen() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::CheckLevel;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let builder = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_lifetime_stats"))
+            .b_parameter(2);
+        let mut btree = builder.build()?;
+        btree.insert(KeyValuePair::new("ab".to_string(), "cd".to_string()))?;
+        btree.insert(KeyValuePair::new("ef".to_string(), "gh".to_string()))?;
+        btree.delete(Key("ab".to_string()))?;
+        // Deleting a missing key writes nothing.
+        assert!(matches!(
+            btree.delete(Key("ab".to_string())),
+            Err(Error::KeyNotFound)
+        ));
+        let before = btree.lifetime_stats();
+        btree.persist_stats()?;
+        assert_eq!(before.writes, 3);
+        assert_eq!(before.logical_bytes, 10);
+        drop(btree);
+
+        let mut btree = builder.open_with_check(CheckLevel::None)?;
+        assert_eq!(btree.lifetime_stats(), before);
+        btree.insert(KeyValuePair::new("ij".to_string(), "kl".to_string()))?;
+        let after = btree.lifetime_stats();
+        assert_eq!(after.writes, 4);
+        assert_eq!(after.logical_bytes, 14);
+        assert!(after.physical_bytes > before.physical_bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn len_survives_reopen_and_crash() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::CheckLevel;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let builder = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_len"))
+            .b_parameter(2);
+        let mut btree = builder.build()?;
+        assert!(btree.is_empty());
+        for i in 0..10 {
+            btree.insert(KeyValuePair::new(format!("k{}", i), "v".to_string()))?;
+        }
+        btree.delete(Key("k3".to_string()))?;
+        btree.update_range("k5".to_string()..="k6".to_string(), |_, _| None)?;
+        assert_eq!(btree.len(), 7);
+        drop(btree);
+
+        let mut btree = builder.open_with_check(CheckLevel::None)?;
+        assert_eq!(btree.len(), 7);
+        btree.insert(KeyValuePair::new("k5".to_string(), "v".to_string()))?;
+        // Skip the header write of a clean close, the count is recomputed on open.
+        std::mem::forget(btree);
+        let btree = builder.open_with_check(CheckLevel::None)?;
+        assert_eq!(btree.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn max_size_caps_file_growth() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::page_layout::PAGE_SIZE;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_max_size"))
+            .b_parameter(2)
+            .max_size(PAGE_SIZE * 8)
+            .build()?;
+        let mut inserted = 0;
+        let err = loop {
+            let kv = KeyValuePair::new(format!("k{:02}", inserted), "v".to_string());
+            match btree.insert(kv) {
+                Ok(_) => inserted += 1,
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(err, Error::CapacityExceeded));
+        assert!(inserted > 0);
+        // The failed insert left the tree intact.
+        assert_eq!(btree.len(), inserted);
+        for i in 0..inserted {
+            assert_eq!(btree.search(format!("k{:02}", i))?.value, "v");
+        }
+        assert!(btree.search(format!("k{:02}", inserted)).is_err());
+
+        // Overwrites and inserts that fit in their leaf need no new page at the cap.
+        btree.insert(KeyValuePair::new("k00".to_string(), "w".to_string()))?;
+        assert_eq!(btree.search("k00".to_string())?.value, "w");
+        btree.delete(crate::node_type::Key("k01".to_string()))?;
+        btree.insert(KeyValuePair::new("k01".to_string(), "v".to_string()))?;
+        assert_eq!(btree.len(), inserted);
+        assert!(btree.health().pages <= 8);
+        Ok(())
+    }
+
+    #[test]
+    fn tuned_for_sets_b_parameter() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::workload::Workload;
+        use std::path::Path;
+
+        for workload in [
+            Workload::PointLookup,
+            Workload::RangeScan,
+            Workload::WriteHeavy,
+        ]
+        .iter()
+        {
+            let mut btree = BTreeBuilder::new()
+                .path(Path::new("/tmp/db_tuned_for"))
+                .tuned_for(*workload)
+                .build()?;
+            assert_eq!(btree.b, workload.b_parameter());
+            for i in 0..300 {
+                btree.insert(KeyValuePair::new(format!("k{}", i), "v".to_string()))?;
+            }
+            assert_eq!(btree.search("k299".to_string())?.value, "v");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn delete_range_drops_covered_subtrees() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::{self, CheckLevel};
+        use crate::header::Header;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_delete_range"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..60 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let pages = btree.health().pages - btree.health().free_pages;
+        assert_eq!(
+            btree.delete_range("k05".to_string().."k50".to_string())?,
+            45
+        );
+        assert_eq!(btree.len(), 15);
+        assert!(btree.health().pages - btree.health().free_pages < pages);
+        let header = Header {
+            root_offset: btree.root_offset,
+            b: btree.b,
+            value_version: 0,
+            lifetime: btree.lifetime,
+            len: Some(btree.len),
+            generation: btree.generation,
+        };
+        check::check(&mut btree.pager, &header, CheckLevel::Full)?;
+        let keys: Vec<String> = btree
+            .scan(..)
+            .map(|kv| kv.map(|kv| kv.key))
+            .collect::<Result<_, _>>()?;
+        let expected: Vec<String> = (0..5).chain(50..60).map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(keys, expected);
+
+        // The tree keeps working after whole subtrees are gone.
+        btree.insert(KeyValuePair::new("k20".to_string(), "x".to_string()))?;
+        btree.delete(Key("k03".to_string()))?;
+        assert_eq!(btree.search("k20".to_string())?.value, "x");
+
+        assert_eq!(btree.delete_range(..)?, 15);
+        assert!(btree.is_empty());
+        assert!(btree.scan(..).next().is_none());
+        btree.insert(KeyValuePair::new("a".to_string(), "1".to_string()))?;
+        assert_eq!(btree.search("a".to_string())?.value, "1");
+        Ok(())
+    }
+
+    #[test]
+    fn clear_empties_tree_and_frees_pages() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::expiry::Expiry;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+        use std::time::Duration;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_clear"))
+            .b_parameter(2)
+            .build()?;
+        btree.set_expiry(Expiry::new(
+            BTreeBuilder::new()
+                .path(Path::new("/tmp/db_clear_expiry"))
+                .b_parameter(2)
+                .build()?,
+        ));
+        for i in 0..30 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        btree.insert_with_ttl(
+            KeyValuePair::new("k05".to_string(), "5".to_string()),
+            Duration::from_secs(60),
+        )?;
+        assert!(btree.height()? > 1);
+        let pages = btree.health().pages;
+        btree.clear()?;
+        assert!(btree.is_empty());
+        assert_eq!(btree.height()?, 1);
+        assert!(btree.expiry().unwrap().expires_at("k05")?.is_none());
+        assert!(btree.scan(..).next().is_none());
+        let health = btree.health();
+        assert_eq!((health.pages, health.free_pages), (pages, pages - 2));
+
+        // Freed pages are reused before the file grows.
+        for i in 0..30 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        assert_eq!(btree.health().pages, pages);
+        assert_eq!(btree.search("k07".to_string())?.value, "7");
+        Ok(())
+    }
+
+    #[test]
+    fn pages_describe_reachable_layout() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::stats::PageKind;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_pages"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..20 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let root_offset = btree.root_offset;
+        let pages = btree.pages().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!((pages[0].offset, pages[0].parent), (root_offset, None));
+        assert_eq!(pages[0].kind, PageKind::Internal);
+
+        let leaves: Vec<_> = pages
+            .iter()
+            .filter(|page| page.kind == PageKind::Leaf)
+            .collect();
+        assert_eq!(leaves.iter().map(|page| page.keys).sum::<usize>(), 20);
+        assert!(pages.iter().all(|page| page.fill > 0.0 && page.fill <= 1.0));
+        // Every parent is an internal page listed before its children.
+        for (idx, page) in pages.iter().enumerate().skip(1) {
+            let parent = pages[..idx].iter().find(|p| Some(p.offset) == page.parent);
+            assert_eq!(parent.map(|p| p.kind), Some(PageKind::Internal));
+        }
+        assert_eq!(
+            pages.len(),
+            btree.health().pages - btree.health().free_pages - 1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn retain_removes_rejected_pairs() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::{self, CheckLevel};
+        use crate::header::Header;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_retain"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..30 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let removed = btree.retain(|_, value| value.parse::<u32>().is_ok_and(|i| i % 3 == 0))?;
+        assert_eq!(removed, 20);
+        assert_eq!(btree.len(), 10);
+        let keys: Vec<String> = btree
+            .scan(..)
+            .map(|kv| kv.map(|kv| kv.key))
+            .collect::<Result<_, _>>()?;
+        let expected: Vec<String> = (0..30).step_by(3).map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(btree.retain(|_, _| true)?, 0);
+        // The leaves thinned out by the predicate were merged back to full occupancy.
+        let header = Header {
+            root_offset: btree.root_offset,
+            b: btree.b,
+            value_version: 0,
+            lifetime: btree.lifetime,
+            len: Some(btree.len),
+            generation: btree.generation,
+        };
+        check::check(&mut btree.pager, &header, CheckLevel::Full)?;
+        assert_eq!(btree.retain(|_, _| false)?, 10);
+        assert_eq!(btree.height()?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn insert_all_sorts_and_fills_leaves() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_insert_all"))
+            .b_parameter(3)
+            .build()?;
+        btree.insert(KeyValuePair::new("k07".to_string(), "old".to_string()))?;
+        let pairs = (0..100)
+            .rev()
+            .map(|i| KeyValuePair::new(format!("k{:02}", i), i.to_string()));
+        let before = btree.io_stats().physical_bytes;
+        assert_eq!(btree.insert_all(pairs)?, 100);
+        let bulk = btree.io_stats().physical_bytes - before;
+        assert_eq!(btree.len(), 100);
+        assert_eq!(btree.search("k07".to_string())?.value, "7");
+
+        // A key given twice keeps its last value.
+        btree.extend(vec![
+            KeyValuePair::new("a".to_string(), "1".to_string()),
+            KeyValuePair::new("a".to_string(), "2".to_string()),
+        ]);
+        assert_eq!(btree.search("a".to_string())?.value, "2");
+        let keys: Vec<String> = btree
+            .scan(..)
+            .map(|kv| kv.map(|kv| kv.key))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(keys.len(), 101);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        // Inserting the same pairs one by one writes more pages.
+        let mut single = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_insert_all_single"))
+            .b_parameter(3)
+            .build()?;
+        let before = single.io_stats().physical_bytes;
+        for i in 0..100 {
+            single.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        assert!(single.io_stats().physical_bytes - before > bulk);
+        Ok(())
+    }
+
+    #[test]
+    fn count_range_counts_keys_within_bounds() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_count_range"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..50 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        assert_eq!(btree.count_range(..)?, 50);
+        assert_eq!(btree.count_range("k10".to_string().."k35".to_string())?, 25);
+        assert_eq!(
+            btree.count_range("k10".to_string()..="k35".to_string())?,
+            26
+        );
+        assert_eq!(btree.count_range("k095".to_string()..)?, 40);
+        assert_eq!(btree.count_range(.."a".to_string())?, 0);
+        assert_eq!(btree.count_range("z".to_string()..)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_folds_operands_into_stored_value() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use std::path::Path;
+        use std::sync::Arc;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_merge"))
+            .b_parameter(2)
+            .merge_operator(Arc::new(|_, old, operand| {
+                let old: i64 = old.and_then(|old| old.parse().ok()).unwrap_or(0);
+                (old + operand.parse::<i64>().unwrap_or(0)).to_string()
+            }))
+            .build()?;
+        for operand in ["1", "5", "-2"].iter() {
+            btree.merge("hits", operand)?;
+        }
+        assert_eq!(btree.search("hits".to_string())?.value, "4");
+
+        let mut plain = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_merge_plain"))
+            .b_parameter(2)
+            .build()?;
+        assert!(matches!(
+            plain.merge("hits", "1"),
+            Err(Error::NoMergeOperator)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn compare_and_swap_checks_current_value() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_cas"))
+            .b_parameter(2)
+            .build()?;
+        assert!(!btree.compare_and_swap("k", Some("a"), Some("b"))?);
+        assert!(btree.compare_and_swap("k", None, Some("a"))?);
+        assert!(!btree.compare_and_swap("k", None, Some("x"))?);
+        assert!(btree.compare_and_swap("k", Some("a"), Some("b"))?);
+        assert_eq!(btree.search("k".to_string())?.value, "b");
+
+        assert!(!btree.compare_and_swap("k", Some("a"), None)?);
+        assert!(btree.compare_and_swap("k", Some("b"), None)?);
+        assert_eq!(btree.get("k")?, None);
+        assert!(btree.compare_and_swap("k", None, None)?);
+        Ok(())
+    }
+
+    #[test]
+    fn increment_adds_to_stored_integer() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_increment"))
+            .b_parameter(2)
+            .build()?;
+        assert_eq!(btree.increment("hits", 5)?, 5);
+        assert_eq!(btree.increment("hits", -2)?, 3);
+        assert_eq!(btree.get("hits")?.map(|kv| kv.value), Some("3".to_string()));
+        assert_eq!(btree.len(), 1);
+
+        btree.insert(KeyValuePair::new("name".to_string(), "abc".to_string()))?;
+        assert!(matches!(
+            btree.increment("name", 1),
+            Err(Error::InvalidNumber { .. })
+        ));
+        // Sums that do not fit in a value are rejected like any other long value.
+        btree.insert(KeyValuePair::new(
+            "max".to_string(),
+            "9999999999".to_string(),
+        ))?;
+        assert!(matches!(
+            btree.increment("max", 1),
+            Err(Error::ValueOverflowError)
+        ));
+        assert_eq!(
+            btree.get("name")?.map(|kv| kv.value),
+            Some("abc".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compact_range_repacks_leaves_within_range() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::{self, CheckLevel};
+        use crate::header::Header;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::stats::PageKind;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_compact_range"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..60 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let leaves = |btree: &mut crate::btree::BTree| -> Result<usize, Error> {
+            let pages = btree.pages().collect::<Result<Vec<_>, _>>()?;
+            Ok(pages
+                .iter()
+                .filter(|page| page.kind == PageKind::Leaf)
+                .count())
+        };
+        let before = leaves(&mut btree)?;
+        let freed = btree.compact_range("k10".to_string().."k40".to_string())?;
+        assert!(freed > 0);
+        assert!(leaves(&mut btree)? < before);
+
+        let header = Header {
+            root_offset: btree.root_offset,
+            b: btree.b,
+            value_version: 0,
+            lifetime: btree.lifetime,
+            len: Some(btree.len),
+            generation: btree.generation,
+        };
+        check::check(&mut btree.pager, &header, CheckLevel::Full)?;
+        let keys: Vec<String> = btree
+            .scan(..)
+            .map(|kv| kv.map(|kv| kv.key))
+            .collect::<Result<_, _>>()?;
+        let expected: Vec<String> = (0..60).map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(keys, expected);
+
+        // Full leaves split again as the range takes new writes.
+        btree.insert(KeyValuePair::new("k20a".to_string(), "x".to_string()))?;
+        btree.delete(Key("k21".to_string()))?;
+        assert_eq!(btree.search("k20a".to_string())?.value, "x");
+        assert_eq!(btree.len(), 60);
+        Ok(())
+    }
+
+    #[test]
+    fn deferred_merges_leave_sparse_nodes_until_maintenance() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::{self, CheckLevel};
+        use crate::header::Header;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_deferred_merges"))
+            .b_parameter(2)
+            .deferred_merges(true)
+            .build()?;
+        for i in 0..60 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let free_pages = btree.health().free_pages;
+        for i in (0..60).filter(|i| i % 4 != 0) {
+            btree.delete(Key(format!("k{:02}", i)))?;
+        }
+        // Nothing was merged, so no page was freed.
+        assert_eq!(btree.health().free_pages, free_pages);
+        btree.delete_range("k30".to_string().."k40".to_string())?;
+        let expected: Vec<String> = (0..60)
+            .filter(|i| i % 4 == 0 && !(30..40).contains(i))
+            .map(|i| format!("k{:02}", i))
+            .collect();
+        let keys: Vec<String> = btree.keys().collect::<Result<_, _>>()?;
+        assert_eq!(keys, expected);
+        assert_eq!(btree.search("k44".to_string())?.value, "44");
+
+        assert!(btree.merge_sparse_nodes()? > 0);
+        let pages = btree.pages().collect::<Result<Vec<_>, _>>()?;
+        assert!(pages
+            .iter()
+            .all(|page| page.parent.is_none() || page.keys >= btree.b - 1));
+        let header = Header {
+            root_offset: btree.root_offset,
+            b: btree.b,
+            value_version: 0,
+            lifetime: btree.lifetime,
+            len: Some(btree.len),
+            generation: btree.generation,
+        };
+        check::check(&mut btree.pager, &header, CheckLevel::Full)?;
+        let keys: Vec<String> = btree.keys().collect::<Result<_, _>>()?;
+        assert_eq!(keys, expected);
+        assert_eq!(btree.merge_sparse_nodes()?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn get_many_returns_pairs_in_request_order() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_get_many"))
+            .b_parameter(2)
+            .build()?;
+        for i in (0..40).step_by(2) {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let keys: Vec<String> = ["k30", "k03", "k00", "k38", "k30", "z", "k12"]
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+        let values: Vec<Option<String>> = btree
+            .get_many(&keys)?
+            .into_iter()
+            .map(|kv| kv.map(|kv| kv.value))
+            .collect();
+        let expected: Vec<Option<String>> = keys
+            .iter()
+            .map(|key| btree.get(key).map(|kv| kv.map(|kv| kv.value)))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, expected);
+        assert_eq!(expected[0], Some("30".to_string()));
+        assert_eq!(expected[1], None);
+        assert!(btree.get_many(&[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn seek_floor_and_ceiling_find_nearest_keys() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_seek_floor"))
+            .b_parameter(2)
+            .build()?;
+        assert!(btree.seek_floor("k10")?.is_none());
+        for i in (10..50).step_by(5) {
+            btree.insert(KeyValuePair::new(format!("k{}", i), i.to_string()))?;
+        }
+        let key = |kv: Option<KeyValuePair>| kv.map(|kv| kv.key);
+        assert_eq!(key(btree.seek_floor("k25")?), Some("k25".to_string()));
+        assert_eq!(key(btree.seek_floor("k27")?), Some("k25".to_string()));
+        assert_eq!(key(btree.seek_floor("k09")?), None);
+        assert_eq!(key(btree.seek_floor("z")?), Some("k45".to_string()));
+        assert_eq!(key(btree.seek_ceiling("k25")?), Some("k25".to_string()));
+        assert_eq!(key(btree.seek_ceiling("k27")?), Some("k30".to_string()));
+        assert_eq!(key(btree.seek_ceiling("a")?), Some("k10".to_string()));
+        assert_eq!(key(btree.seek_ceiling("k46")?), None);
+        Ok(())
+    }
+
+    #[test]
+    fn longest_prefix_prefers_longer_matches() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_longest_prefix"))
+            .b_parameter(2)
+            .build()?;
+        for (key, value) in [("10", "a"), ("10.1", "b"), ("10.1.2", "c"), ("192", "d")].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), value.to_string()))?;
+        }
+        let value = |kv: Option<KeyValuePair>| kv.map(|kv| kv.value);
+        assert_eq!(
+            value(btree.longest_prefix("10.1.2.3")?),
+            Some("c".to_string())
+        );
+        assert_eq!(
+            value(btree.longest_prefix("10.1.9")?),
+            Some("b".to_string())
+        );
+        assert_eq!(value(btree.longest_prefix("10.1")?), Some("b".to_string()));
+        assert_eq!(value(btree.longest_prefix("10.2")?), Some("a".to_string()));
+        assert_eq!(value(btree.longest_prefix("19")?), None);
+        assert_eq!(value(btree.longest_prefix("")?), None);
+        // Queries longer than any key still match their short prefixes.
+        assert_eq!(
+            value(btree.longest_prefix("192.168.0.0.1")?),
+            Some("d".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn export_subtree_writes_prefix_to_new_tree() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::CheckLevel;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_export_subtree"))
+            .b_parameter(2)
+            .build()?;
+        for tenant in ["a", "b", "c"].iter() {
+            for i in 0..20 {
+                btree.insert(KeyValuePair::new(
+                    format!("{}/{:02}", tenant, i),
+                    i.to_string(),
+                ))?;
             }
-            NodeType::Unexpected => Err(Error::UnexpectedError),
         }
+        let dest = Path::new("/tmp/db_export_subtree_b");
+        assert_eq!(btree.export_subtree("b/", dest)?, 20);
+        assert!(btree
+            .export_subtree("a/", Path::new("/tmp/db_export_subtree"))
+            .is_err());
+
+        let mut exported = BTreeBuilder::new()
+            .path(dest)
+            .open_with_check(CheckLevel::Full)?;
+        assert_eq!(exported.len(), 20);
+        let keys: Vec<String> = exported.keys().collect::<Result<_, _>>()?;
+        let expected: Vec<String> = (0..20).map(|i| format!("b/{:02}", i)).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(exported.search("b/07".to_string())?.value, "7");
+        assert_eq!(btree.len(), 60);
+        Ok(())
     }
 
-    /// print is a helper for recursively printing the tree.
-    pub fn print(&mut self) -> Result<(), Error> {
-        println!();
-        self.print_sub_tree("".to_string(), self.root_offset.clone())
+    #[test]
+    fn default_builder_requires_a_path() -> Result<(), Error> {
+        use crate::btree::{BTree, BTreeBuilder, DEFAULT_B_PARAMETER, DEFAULT_FILE_NAME};
+        use crate::node_type::KeyValuePair;
+        use std::fs;
+        use std::path::Path;
+
+        assert!(matches!(
+            BTreeBuilder::default().build(),
+            Err(Error::PathRequired)
+        ));
+        assert!(matches!(
+            BTreeBuilder::new().path(Path::new("")).build(),
+            Err(Error::PathRequired)
+        ));
+
+        let dir = Path::new("/tmp/db_open_default_in");
+        fs::create_dir_all(dir)?;
+        let _ = fs::remove_file(dir.join(DEFAULT_FILE_NAME));
+        let mut btree = BTree::open_default_in(dir)?;
+        assert_eq!(btree.b, DEFAULT_B_PARAMETER);
+        for i in 0..500 {
+            btree.insert(KeyValuePair::new(format!("k{:03}", i), i.to_string()))?;
+        }
+        btree.persist_stats()?;
+        drop(btree);
+        let mut btree = BTree::open_default_in(dir)?;
+        assert_eq!(btree.len(), 500);
+        assert_eq!(btree.search("k499".to_string())?.value, "499");
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::error::Error;
+    #[test]
+    fn key_policy_strict_and_truncate() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::key_policy::KeyPolicy;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut strict = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_key_strict"))
+            .b_parameter(2)
+            .build()?;
+        let long_key = "averylongkey".to_string();
+        assert!(matches!(
+            strict.insert(KeyValuePair::new(long_key.clone(), "v".to_string())),
+            Err(Error::KeyOverflowError)
+        ));
+        assert!(matches!(
+            strict.insert(KeyValuePair::new(
+                "k".to_string(),
+                "averylongvalue".to_string()
+            )),
+            Err(Error::ValueOverflowError)
+        ));
+        assert_eq!(strict.io_stats().logical_bytes, 0);
+
+        let mut truncating = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_key_truncate"))
+            .b_parameter(2)
+            .key_policy(KeyPolicy::Truncate)
+            .build()?;
+        truncating.insert(KeyValuePair::new(long_key.clone(), "v".to_string()))?;
+        let kv = truncating.search(long_key)?;
+        assert_eq!(kv.key, "averylong~");
+        assert_eq!(kv.value, "v");
+        // A key that would alias the truncated one cannot be written, only read.
+        assert!(matches!(
+            truncating.insert(KeyValuePair::new(kv.key.clone(), "w".to_string())),
+            Err(Error::ReservedKey { .. })
+        ));
+        assert!(matches!(
+            truncating.insert(KeyValuePair::new("short~".to_string(), "w".to_string())),
+            Err(Error::ReservedKey { .. })
+        ));
+        assert_eq!(truncating.search(kv.key)?.value, "v");
+        Ok(())
+    }
 
     #[test]
-    fn search_works() -> Result<(), Error> {
+    fn get_raw_reads_invalid_utf8_values() -> Result<(), Error> {
         use crate::btree::BTreeBuilder;
         use crate::node_type::KeyValuePair;
+        use crate::page_layout::{KEY_SIZE, LEAF_NODE_HEADER_SIZE, VALUE_SIZE};
         use std::path::Path;
 
         let mut btree = BTreeBuilder::new()
-            .path(Path::new("/tmp/db"))
+            .path(Path::new("/tmp/db_get_raw"))
             .b_parameter(2)
             .build()?;
         btree.insert(KeyValuePair::new("a".to_string(), "shalom".to_string()))?;
         btree.insert(KeyValuePair::new("b".to_string(), "hello".to_string()))?;
-        btree.insert(KeyValuePair::new("c".to_string(), "marhaba".to_string()))?;
 
-        let mut kv = btree.search("b".to_string())?;
-        assert_eq!(kv.key, "b");
-        assert_eq!(kv.value, "hello");
+        // Corrupt the first byte of the second value in the root leaf.
+        let root_offset = btree.root_offset;
+        let corrupt_offset = LEAF_NODE_HEADER_SIZE + 2 * KEY_SIZE + VALUE_SIZE;
+        let mut page = btree.pager.get_page(&root_offset)?;
+        page.write_bytes_at_offset(&[0xff], corrupt_offset, 1)?;
+        btree.pager.write_page_at_offset(page, &root_offset)?;
 
-        kv = btree.search("c".to_string())?;
-        assert_eq!(kv.key, "c");
-        assert_eq!(kv.value, "marhaba");
+        match btree.search("b".to_string()) {
+            Err(Error::InvalidUtf8 { offset }) => assert_eq!(offset, corrupt_offset),
+            _ => return Err(Error::UnexpectedError),
+        }
+        assert_eq!(btree.get_raw("b")?, vec![0xff, b'e', b'l', b'l', b'o']);
+        assert_eq!(btree.get_raw("a")?, b"shalom".to_vec());
+        assert!(matches!(btree.get_raw("c"), Err(Error::KeyNotFound)));
+        Ok(())
+    }
 
-        // Sanity check:
-        btree.print()
+    #[test]
+    fn quota_rejects_inserts_over_limit() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::quota::{Quota, QuotaUsage};
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_quota"))
+            .b_parameter(2)
+            .quota("t1/", Quota::Entries(2))
+            .quota("t2/", Quota::Bytes(10))
+            .build()?;
+        btree.insert(KeyValuePair::new("t1/a".to_string(), "v".to_string()))?;
+        btree.insert(KeyValuePair::new("t1/b".to_string(), "v".to_string()))?;
+        match btree.insert(KeyValuePair::new("t1/c".to_string(), "v".to_string())) {
+            Err(Error::QuotaExceeded { prefix }) => assert_eq!(prefix, "t1/"),
+            _ => return Err(Error::UnexpectedError),
+        }
+        assert!(btree.search("t1/c".to_string()).is_err());
+
+        btree.insert(KeyValuePair::new("t2/a".to_string(), "123456".to_string()))?;
+        assert!(matches!(
+            btree.insert(KeyValuePair::new("t2/b".to_string(), "1".to_string())),
+            Err(Error::QuotaExceeded { .. })
+        ));
+        // Unconfigured prefixes are unlimited.
+        btree.insert(KeyValuePair::new("t3/a".to_string(), "v".to_string()))?;
+
+        assert_eq!(
+            btree.quota_usage("t1/"),
+            Some(QuotaUsage {
+                entries: 2,
+                bytes: 10
+            })
+        );
+        assert_eq!(btree.quota_usage("t3/"), None);
+        Ok(())
     }
 
     #[test]
-    fn insert_works() -> Result<(), Error> {
+    fn heatmap_ranks_leaves_by_access() -> Result<(), Error> {
         use crate::btree::BTreeBuilder;
         use crate::node_type::KeyValuePair;
         use std::path::Path;
 
         let mut btree = BTreeBuilder::new()
-            .path(Path::new("/tmp/db"))
+            .path(Path::new("/tmp/db_heatmap"))
             .b_parameter(2)
+            .access_sampling(1)
             .build()?;
-        btree.insert(KeyValuePair::new("a".to_string(), "shalom".to_string()))?;
-        btree.insert(KeyValuePair::new("b".to_string(), "hello".to_string()))?;
-        btree.insert(KeyValuePair::new("c".to_string(), "marhaba".to_string()))?;
-        btree.insert(KeyValuePair::new("d".to_string(), "olah".to_string()))?;
-        btree.insert(KeyValuePair::new("e".to_string(), "salam".to_string()))?;
-        btree.insert(KeyValuePair::new("f".to_string(), "hallo".to_string()))?;
-        btree.insert(KeyValuePair::new("g".to_string(), "Konnichiwa".to_string()))?;
-        btree.insert(KeyValuePair::new("h".to_string(), "Ni hao".to_string()))?;
-        btree.insert(KeyValuePair::new("i".to_string(), "Ciao".to_string()))?;
+        for key in ["a", "b", "c", "d", "e", "f"].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), "v".to_string()))?;
+        }
+        for _ in 0..10 {
+            btree.search("f".to_string())?;
+        }
 
-        let mut kv = btree.search("a".to_string())?;
-        assert_eq!(kv.key, "a");
-        assert_eq!(kv.value, "shalom");
+        let heatmap = btree.heatmap()?;
+        assert!(heatmap.len() > 1);
+        let hottest = &heatmap[0];
+        assert!(hottest.reads >= 10);
+        let (first, last) = hottest.range.clone().ok_or(Error::UnexpectedError)?;
+        assert!(first.as_str() <= "f" && "f" <= last.as_str());
+        Ok(())
+    }
 
-        kv = btree.search("b".to_string())?;
-        assert_eq!(kv.key, "b");
-        assert_eq!(kv.value, "hello");
+    #[test]
+    fn update_range_rewrites_and_deletes() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
 
-        kv = btree.search("c".to_string())?;
-        assert_eq!(kv.key, "c");
-        assert_eq!(kv.value, "marhaba");
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_update_range"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..20 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), format!("v{}", i)))?;
+        }
 
-        kv = btree.search("d".to_string())?;
-        assert_eq!(kv.key, "d");
-        assert_eq!(kv.value, "olah");
+        let updated = btree.update_range("k05".to_string().."k11".to_string(), |key, value| {
+            if ["k06", "k08", "k10"].contains(&key) {
+                Some(value.to_uppercase())
+            } else {
+                None
+            }
+        })?;
+        assert_eq!(updated, 6);
+        assert_eq!(btree.search("k04".to_string())?.value, "v4");
+        assert_eq!(btree.search("k06".to_string())?.value, "V6");
+        assert_eq!(btree.search("k10".to_string())?.value, "V10");
+        assert_eq!(btree.search("k11".to_string())?.value, "v11");
+        assert!(btree.search("k07".to_string()).is_err());
+        assert_eq!(btree.scan_chunks(.., 100).next().unwrap()?.len(), 17);
 
-        kv = btree.search("e".to_string())?;
-        assert_eq!(kv.key, "e");
-        assert_eq!(kv.value, "salam");
+        let res = btree.update_range(.., |_, _| Some("far too long".to_string()));
+        assert!(matches!(res, Err(Error::ValueOverflowError)));
+        assert_eq!(btree.search("k00".to_string())?.value, "v0");
+        Ok(())
+    }
 
-        kv = btree.search("f".to_string())?;
-        assert_eq!(kv.key, "f");
-        assert_eq!(kv.value, "hallo");
+    #[test]
+    fn update_range_rebalances_after_deletes() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::{self, CheckLevel};
+        use crate::header::Header;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
 
-        kv = btree.search("g".to_string())?;
-        assert_eq!(kv.key, "g");
-        assert_eq!(kv.value, "Konnichiwa");
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_update_range_rebalance"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..60 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let pages = btree.health().pages - btree.health().free_pages;
+        let deleted = btree.update_range("k05".to_string().."k50".to_string(), |_, _| None)?;
+        assert_eq!(deleted, 45);
+        assert!(btree.health().pages - btree.health().free_pages < pages);
+        let header = Header {
+            root_offset: btree.root_offset,
+            b: btree.b,
+            value_version: 0,
+            lifetime: btree.lifetime,
+            len: Some(btree.len),
+            generation: btree.generation,
+        };
+        check::check(&mut btree.pager, &header, CheckLevel::Full)?;
+        let keys: Vec<String> = btree.keys().collect::<Result<_, _>>()?;
+        let expected: Vec<String> = (0..5).chain(50..60).map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(keys, expected);
 
-        kv = btree.search("h".to_string())?;
-        assert_eq!(kv.key, "h");
-        assert_eq!(kv.value, "Ni hao");
+        assert_eq!(btree.update_range(.., |_, _| None)?, 15);
+        assert!(btree.is_empty());
+        assert_eq!(btree.height()?, 1);
+        Ok(())
+    }
 
-        kv = btree.search("i".to_string())?;
-        assert_eq!(kv.key, "i");
-        assert_eq!(kv.value, "Ciao");
+    #[test]
+    fn copy_range_copies_into_another_tree() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
 
-        // Sanity check:
-        btree.print()
+        let mut hot = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_copy_range_src"))
+            .b_parameter(2)
+            .build()?;
+        let mut cold = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_copy_range_dest"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..20 {
+            hot.insert(KeyValuePair::new(format!("k{:02}", i), format!("v{}", i)))?;
+        }
+
+        assert_eq!(hot.copy_range(..="k09".to_string(), &mut cold)?, 10);
+        assert_eq!(cold.search("k09".to_string())?.value, "v9");
+        assert!(cold.search("k10".to_string()).is_err());
+        assert_eq!(hot.search("k00".to_string())?.value, "v0");
+        Ok(())
+    }
+
+    #[test]
+    fn key_validator_rejects_bad_keys() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::key_policy::{KeyRule, KeyValidator};
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_key_validator"))
+            .b_parameter(2)
+            .key_validator(
+                KeyValidator::new()
+                    .max_length(8)
+                    .charset("abcdefghijklmnopqrstuvwxyz0123456789:")
+                    .prefixes(&["user:", "org:"]),
+            )
+            .build()?;
+        btree.insert(KeyValuePair::new("user:1".to_string(), "a".to_string()))?;
+
+        let res = btree.insert(KeyValuePair::new("user:1234".to_string(), "b".to_string()));
+        assert!(matches!(
+            res,
+            Err(Error::InvalidKey {
+                rule: KeyRule::MaxLength(8),
+                ..
+            })
+        ));
+        let res = btree.insert(KeyValuePair::new("user:AB".to_string(), "b".to_string()));
+        assert!(matches!(
+            res,
+            Err(Error::InvalidKey {
+                rule: KeyRule::Charset(_),
+                ..
+            })
+        ));
+        let res = btree.insert(KeyValuePair::new("team:1".to_string(), "b".to_string()));
+        assert!(matches!(
+            res,
+            Err(Error::InvalidKey {
+                rule: KeyRule::Prefix(_),
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_runs_pending_migrations() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::CheckLevel;
+        use crate::migrations::Migrations;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+        use std::sync::Arc;
+
+        let path = Path::new("/tmp/db_migrate");
+        let mut btree = BTreeBuilder::new().path(path).b_parameter(2).build()?;
+        for i in 0..10 {
+            btree.insert(KeyValuePair::new(format!("k{}", i), i.to_string()))?;
+        }
+        assert_eq!(btree.value_version(), 0);
+
+        let v1 = Migrations::new().step(Arc::new(|_, value| format!("n={}", value)));
+        assert_eq!(btree.migrate(&v1)?, 1);
+        // Migrations already applied are not run again.
+        let v2 = v1.step(Arc::new(|_, value| value.to_uppercase()));
+        assert_eq!(btree.migrate(&v2)?, 2);
+        assert_eq!(btree.search("k3".to_string())?.value, "N=3");
+
+        let btree = BTreeBuilder::new()
+            .path(path)
+            .open_with_check(CheckLevel::None)?;
+        assert_eq!(btree.value_version(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn write_modes_reject_changes() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::write_mode::WriteMode;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_write_once"))
+            .b_parameter(2)
+            .write_mode(WriteMode::WriteOnce)
+            .build()?;
+        for i in 0..5 {
+            btree.insert(KeyValuePair::new(format!("k{}", i), "a".to_string()))?;
+        }
+        let res = btree.insert(KeyValuePair::new("k1".to_string(), "b".to_string()));
+        assert!(matches!(res, Err(Error::KeyAlreadyExists)));
+        let res = btree.update_range(.., |_, _| Some("b".to_string()));
+        assert!(matches!(res, Err(Error::Immutable { .. })));
+        // Unchanged values are not overwrites, and deletes are allowed.
+        assert_eq!(
+            btree.update_range(.., |_, value| Some(value.to_string()))?,
+            5
+        );
+        assert_eq!(btree.update_range(.."k1".to_string(), |_, _| None)?, 1);
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_append_only"))
+            .b_parameter(2)
+            .write_mode(WriteMode::AppendOnly)
+            .build()?;
+        btree.insert(KeyValuePair::new("k".to_string(), "a".to_string()))?;
+        let res = btree.delete(Key("k".to_string()));
+        assert!(matches!(res, Err(Error::Immutable { .. })));
+        let res = btree.update_range(.., |_, _| None);
+        assert!(matches!(res, Err(Error::Immutable { .. })));
+        assert_eq!(btree.search("k".to_string())?.value, "a");
+        Ok(())
     }
 }
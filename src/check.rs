@@ -0,0 +1,293 @@
+use crate::error::Error;
+use crate::header::Header;
+use crate::node::Node;
+use crate::node_type::{Key, NodeType, Offset};
+use crate::page_layout::{NODE_TYPE_OFFSET, PAGE_SIZE};
+use crate::pager::Pager;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+/// CheckLevel selects how much of a tree file is validated when it is opened,
+/// trading startup time for confidence after an unclean shutdown.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheckLevel {
+    /// Only read the header.
+    None,
+    /// Validate the header fields and the root node.
+    Header,
+    /// Validate every internal node: key ordering, key bounds and child offsets.
+    Quick,
+    /// Validate every node, including the pairs of each leaf, the tree's balance and
+    /// the occupancy of every node but the root.
+    Full,
+}
+
+/// Health is a quick status of an open tree, cheap enough for liveness probes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Health {
+    /// The header page on disk is readable and points at the root this handle uses.
+    pub header_valid: bool,
+    /// Number of pages in the file, including the header.
+    pub pages: usize,
+    /// Number of pages on the free list.
+    pub free_pages: usize,
+}
+
+impl Health {
+    /// free_ratio returns the fraction of the file's pages that are free.
+    pub fn free_ratio(&self) -> f64 {
+        if self.pages == 0 {
+            return 0.0;
+        }
+        self.free_pages as f64 / self.pages as f64
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.header_valid
+    }
+}
+
+/// check validates the tree described by header according to level,
+/// returning Error::Corrupted with the offset of the first invalid node.
+pub fn check(pager: &mut Pager, header: &Header, level: CheckLevel) -> Result<(), Error> {
+    check_tree(pager, header, level, true)
+}
+
+/// check_tree is check, leaving out the occupancy of the nodes unless occupancy is set.
+/// Trees built with BTreeBuilder::deferred_merges keep sparse nodes until they are merged.
+pub(crate) fn check_tree(
+    pager: &mut Pager,
+    header: &Header,
+    level: CheckLevel,
+    occupancy: bool,
+) -> Result<(), Error> {
+    if level == CheckLevel::None {
+        return Ok(());
+    }
+    let num_pages = pager.num_pages();
+    let root_offset = header.root_offset;
+    if header.b == 0 || !is_valid_offset(&root_offset, num_pages) {
+        return Err(Error::InvalidHeader);
+    }
+    let root = read_node(pager, &root_offset)?;
+    if !root.is_root {
+        return Err(corrupted(&root_offset));
+    }
+    if level == CheckLevel::Header {
+        return Ok(());
+    }
+
+    // Every node is visited along with the bounds its keys must lie within and its depth.
+    let mut stack: Vec<(Offset, Option<Key>, Option<Key>, usize)> =
+        vec![(root_offset, None, None, 0)];
+    let mut visited = HashSet::new();
+    let mut leaf_depth = None;
+    // Every node but the root holds at least b - 1 keys.
+    let min_keys = header.b - 1;
+    let occupancy = occupancy && level == CheckLevel::Full;
+    while let Some((offset, lower, upper, depth)) = stack.pop() {
+        // A node reachable twice means a cycle or a child shared by two parents.
+        if !visited.insert(offset) {
+            return Err(corrupted(&offset));
+        }
+        if level == CheckLevel::Quick {
+            let page = pager.get_page(&offset)?;
+            let node_type = page.get_ptr_from_offset(NODE_TYPE_OFFSET, 1)[0];
+            if let NodeType::Leaf(_) = NodeType::from(node_type) {
+                continue;
+            }
+        }
+
+        match read_node(pager, &offset)?.node_type {
+            NodeType::Internal(children, keys) => {
+                let keys_in_bounds = is_sorted_within(keys.iter(), &lower, &upper);
+                let offsets_valid = children
+                    .iter()
+                    .all(|child| is_valid_offset(child, num_pages));
+                let underflow = occupancy && depth > 0 && keys.len() < min_keys;
+                if children.len() != keys.len() + 1
+                    || !keys_in_bounds
+                    || !offsets_valid
+                    || underflow
+                {
+                    return Err(corrupted(&offset));
+                }
+                for (idx, child) in children.into_iter().enumerate() {
+                    let child_lower = if idx == 0 {
+                        lower.clone()
+                    } else {
+                        Some(keys[idx - 1].clone())
+                    };
+                    let child_upper = keys.get(idx).cloned().or_else(|| upper.clone());
+                    stack.push((child, child_lower, child_upper, depth + 1));
+                }
+            }
+            NodeType::Leaf(pairs) => {
+                let keys: Vec<Key> = pairs.into_iter().map(|kv| Key(kv.key)).collect();
+                let balanced = *leaf_depth.get_or_insert(depth) == depth;
+                let underflow = occupancy && depth > 0 && keys.len() < min_keys;
+                if !balanced || underflow || !is_sorted_within(keys.iter(), &lower, &upper) {
+                    return Err(corrupted(&offset));
+                }
+            }
+            NodeType::Unexpected => return Err(corrupted(&offset)),
+        }
+    }
+    Ok(())
+}
+
+fn read_node(pager: &mut Pager, offset: &Offset) -> Result<Node, Error> {
+    Node::try_from(pager.get_page(offset)?).map_err(|_| corrupted(offset))
+}
+
+fn corrupted(offset: &Offset) -> Error {
+    Error::Corrupted { offset: offset.0 }
+}
+
+/// is_valid_offset returns true if offset points at a node page within the file.
+fn is_valid_offset(offset: &Offset, num_pages: usize) -> bool {
+    offset.0.is_multiple_of(PAGE_SIZE) && offset.0 > 0 && offset.0 / PAGE_SIZE < num_pages
+}
+
+/// is_sorted_within returns true if keys are sorted and lie within [lower, upper].
+fn is_sorted_within<'a, I: Iterator<Item = &'a Key>>(
+    keys: I,
+    lower: &Option<Key>,
+    upper: &Option<Key>,
+) -> bool {
+    let mut prev = lower.as_ref();
+    for key in keys {
+        if prev.is_some_and(|prev| prev > key) {
+            return false;
+        }
+        prev = Some(key);
+    }
+    match (prev, upper) {
+        (Some(last), Some(upper)) => last <= upper,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn open_with_check_detects_unsorted_leaf() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::CheckLevel;
+        use crate::header::Header;
+        use crate::node::Node;
+        use crate::node_type::{KeyValuePair, NodeType, Offset};
+        use crate::page::Page;
+        use crate::page_layout::HEADER_OFFSET;
+        use crate::pager::Pager;
+        use std::convert::TryFrom;
+        use std::path::Path;
+
+        let path = Path::new("/tmp/db_check");
+        let mut btree = BTreeBuilder::new().path(path).b_parameter(2).build()?;
+        for key in ["a", "b", "c", "d", "e", "f"].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), "v".to_string()))?;
+        }
+        drop(btree);
+
+        let mut btree = BTreeBuilder::new()
+            .path(path)
+            .open_with_check(CheckLevel::Full)?;
+        assert_eq!(btree.search("f".to_string())?.value, "v");
+        drop(btree);
+
+        // Reverse the pairs of the leftmost leaf.
+        let mut pager = Pager::open(path)?;
+        let header = Header::try_from(pager.get_page(&Offset(HEADER_OFFSET))?)?;
+        let root = Node::try_from(pager.get_page(&header.root_offset)?)?;
+        let leaf_offset = match root.node_type {
+            NodeType::Internal(children, _) => children[0],
+            _ => return Err(Error::UnexpectedError),
+        };
+        let mut leaf = Node::try_from(pager.get_page(&leaf_offset)?)?;
+        if let NodeType::Leaf(ref mut pairs) = leaf.node_type {
+            pairs.reverse();
+        }
+        pager.write_page_at_offset(Page::try_from(&leaf)?, &leaf_offset)?;
+        drop(pager);
+
+        let builder = BTreeBuilder::new().path(path);
+        assert!(builder.open_with_check(CheckLevel::Quick).is_ok());
+        match builder.open_with_check(CheckLevel::Full) {
+            Err(Error::Corrupted { offset }) => assert_eq!(offset, leaf_offset.0),
+            _ => return Err(Error::UnexpectedError),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn health_reports_header_and_free_space() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::Offset;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::page::Page;
+        use crate::page_layout::{HEADER_OFFSET, PAGE_SIZE};
+        use crate::pager::Pager;
+        use std::path::Path;
+
+        let path = Path::new("/tmp/db_health");
+        let mut btree = BTreeBuilder::new().path(path).b_parameter(2).build()?;
+        let health = btree.health();
+        assert!(health.is_healthy());
+        assert_eq!((health.pages, health.free_pages), (2, 0));
+
+        for key in ["a", "b", "c", "d"].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), "v".to_string()))?;
+        }
+        for key in ["a", "b", "c", "d"].iter() {
+            btree.delete(Key(key.to_string()))?;
+        }
+        let health = btree.health();
+        assert!(health.free_pages > 0);
+        assert!(health.free_ratio() > 0.0 && health.free_ratio() < 1.0);
+
+        // Clobber the header behind the tree's back.
+        let mut pager = Pager::open(path)?;
+        pager.write_page_at_offset(Page::new([0x00; PAGE_SIZE]), &Offset(HEADER_OFFSET))?;
+        drop(pager);
+        assert!(!btree.health().is_healthy());
+        Ok(())
+    }
+
+    #[test]
+    fn full_check_verifies_occupancy() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::CheckLevel;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let path = Path::new("/tmp/db_check_occupancy");
+        let deferred = BTreeBuilder::new()
+            .path(path)
+            .b_parameter(2)
+            .deferred_merges(true);
+        let mut btree = deferred.build()?;
+        for i in 0..20 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), "v".to_string()))?;
+        }
+        for i in 0..15 {
+            btree.delete(Key(format!("k{:02}", i)))?;
+        }
+        drop(btree);
+
+        // Sparse nodes are expected under deferred merges, and corruption otherwise.
+        let mut btree = deferred.open_with_check(CheckLevel::Full)?;
+        let builder = BTreeBuilder::new().path(path);
+        assert!(builder.open_with_check(CheckLevel::Quick).is_ok());
+        assert!(matches!(
+            builder.open_with_check(CheckLevel::Full),
+            Err(Error::Corrupted { .. })
+        ));
+        btree.merge_sparse_nodes()?;
+        drop(btree);
+        builder.open_with_check(CheckLevel::Full)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{self, Key, KeyValuePair, NodeType, Offset};
+
+/// Where to go at each internal node while descending to a leaf.
+enum Descent<'k> {
+    Seek(&'k str),
+    First,
+    Last,
+}
+
+/// Cursor is a position within the pairs of a tree that can be moved in either
+/// direction. It remembers the leaf it is on and the path leading to it, so stepping
+/// to a neighbouring pair only reads a page when crossing into another leaf.
+///
+/// A new cursor is not positioned on any pair; next moves it to the first pair
+/// and prev to the last. Moving past either end leaves it unpositioned again.
+pub struct Cursor<'a> {
+    tree: &'a mut BTree,
+    /// Internal nodes on the path to the current leaf,
+    /// along with the index of the child descended into.
+    stack: Vec<(Vec<Offset>, usize)>,
+    pairs: Vec<KeyValuePair>,
+    /// Index of the current pair within pairs, None when unpositioned.
+    pos: Option<usize>,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(tree: &'a mut BTree) -> Cursor<'a> {
+        Cursor {
+            tree,
+            stack: Vec::new(),
+            pairs: Vec::new(),
+            pos: None,
+        }
+    }
+
+    /// seek positions the cursor on the first pair with a key not less than key,
+    /// returning false if there is no such pair.
+    pub fn seek(&mut self, key: &Key) -> Result<bool, Error> {
+        self.stack.clear();
+        self.tree.ensure_current()?;
+        self.tree.revalidate()?;
+        let root_offset = self.tree.root_offset();
+        self.descend(root_offset, Descent::Seek(&key.0))?;
+        let idx = self.pairs.partition_point(|kv| kv.key < key.0);
+        if idx < self.pairs.len() {
+            self.pos = Some(idx);
+            return Ok(true);
+        }
+        self.next_leaf()
+    }
+
+    /// next moves the cursor to the following pair, returning false past the last pair.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<bool, Error> {
+        match self.pos {
+            None => {
+                self.stack.clear();
+                self.tree.ensure_current()?;
+                self.tree.revalidate()?;
+                let root_offset = self.tree.root_offset();
+                self.descend(root_offset, Descent::First)?;
+                if self.pairs.is_empty() {
+                    return self.next_leaf();
+                }
+                self.pos = Some(0);
+                Ok(true)
+            }
+            Some(idx) if idx + 1 < self.pairs.len() => {
+                self.pos = Some(idx + 1);
+                Ok(true)
+            }
+            Some(_) => self.next_leaf(),
+        }
+    }
+
+    /// prev moves the cursor to the preceding pair, returning false before the first pair.
+    pub fn prev(&mut self) -> Result<bool, Error> {
+        match self.pos {
+            None => {
+                self.stack.clear();
+                self.tree.ensure_current()?;
+                self.tree.revalidate()?;
+                let root_offset = self.tree.root_offset();
+                self.descend(root_offset, Descent::Last)?;
+                if self.pairs.is_empty() {
+                    return self.prev_leaf();
+                }
+                self.pos = Some(self.pairs.len() - 1);
+                Ok(true)
+            }
+            Some(idx) if idx > 0 => {
+                self.pos = Some(idx - 1);
+                Ok(true)
+            }
+            Some(_) => self.prev_leaf(),
+        }
+    }
+
+    /// key returns the key of the current pair, or None if the cursor is unpositioned.
+    pub fn key(&self) -> Option<&str> {
+        self.current().map(|kv| kv.key.as_str())
+    }
+
+    /// value returns the value of the current pair, or None if the cursor is unpositioned.
+    pub fn value(&self) -> Option<&str> {
+        self.current().map(|kv| kv.value.as_str())
+    }
+
+    fn current(&self) -> Option<&KeyValuePair> {
+        self.pos.and_then(|idx| self.pairs.get(idx))
+    }
+
+    /// descend walks down from offset to a leaf and loads its pairs.
+    fn descend(&mut self, mut offset: Offset, descent: Descent) -> Result<(), Error> {
+        loop {
+            let node = self.tree.read_node(&offset)?;
+            match node.node_type {
+                NodeType::Internal(children, keys) => {
+                    let idx = match descent {
+                        Descent::Seek(key) => node_type::child_index(&keys, key),
+                        Descent::First => 0,
+                        Descent::Last => children.len().saturating_sub(1),
+                    };
+                    offset = *children.get(idx).ok_or(Error::UnexpectedError)?;
+                    self.stack.push((children, idx));
+                }
+                NodeType::Leaf(pairs) => {
+                    self.pairs = pairs;
+                    return Ok(());
+                }
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+        }
+    }
+
+    /// next_leaf moves the cursor to the first pair of the next non-empty leaf.
+    fn next_leaf(&mut self) -> Result<bool, Error> {
+        loop {
+            let child = match self.stack.last_mut() {
+                None => {
+                    self.pos = None;
+                    return Ok(false);
+                }
+                Some((children, idx)) => {
+                    *idx += 1;
+                    children.get(*idx).copied()
+                }
+            };
+            match child {
+                Some(offset) => {
+                    self.descend(offset, Descent::First)?;
+                    if !self.pairs.is_empty() {
+                        self.pos = Some(0);
+                        return Ok(true);
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    /// prev_leaf moves the cursor to the last pair of the previous non-empty leaf.
+    fn prev_leaf(&mut self) -> Result<bool, Error> {
+        loop {
+            let child = match self.stack.last_mut() {
+                None => {
+                    self.pos = None;
+                    return Ok(false);
+                }
+                Some((children, idx)) => match idx.checked_sub(1) {
+                    Some(prev) => {
+                        *idx = prev;
+                        children.get(prev).copied()
+                    }
+                    None => None,
+                },
+            };
+            match child {
+                Some(offset) => {
+                    self.descend(offset, Descent::Last)?;
+                    if !self.pairs.is_empty() {
+                        self.pos = Some(self.pairs.len() - 1);
+                        return Ok(true);
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn cursor_moves_both_ways() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_cursor"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..20 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let mut cursor = btree.cursor();
+        assert_eq!(cursor.key(), None);
+
+        assert!(cursor.seek(&Key("k05".to_string()))?);
+        assert_eq!((cursor.key(), cursor.value()), (Some("k05"), Some("5")));
+        assert!(cursor.next()?);
+        assert_eq!(cursor.key(), Some("k06"));
+        assert!(cursor.prev()? && cursor.prev()?);
+        assert_eq!(cursor.key(), Some("k04"));
+
+        // Seeking a missing key lands on the next one.
+        assert!(cursor.seek(&Key("k05a".to_string()))?);
+        assert_eq!(cursor.key(), Some("k06"));
+        assert!(!cursor.seek(&Key("z".to_string()))?);
+        assert_eq!(cursor.key(), None);
+
+        // Walk the whole tree in both directions.
+        let mut forward = Vec::new();
+        while cursor.next()? {
+            forward.push(cursor.key().unwrap_or_default().to_string());
+        }
+        let expected: Vec<String> = (0..20).map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(forward, expected);
+        let mut backward = Vec::new();
+        while cursor.prev()? {
+            backward.push(cursor.key().unwrap_or_default().to_string());
+        }
+        assert_eq!(backward, expected.into_iter().rev().collect::<Vec<_>>());
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use crate::btree::BTree;
+use crate::error::Error;
+
+/// Entry is a view into the pair stored under a single key, which may be absent,
+/// as returned by BTree::entry. Each method writes the leaf at most once.
+pub struct Entry<'a> {
+    tree: &'a mut BTree,
+    key: String,
+    /// Value currently stored under key, None if the key is absent.
+    value: Option<String>,
+}
+
+impl<'a> Entry<'a> {
+    /// new looks up the value currently stored under key.
+    pub fn new(tree: &'a mut BTree, key: &str) -> Result<Entry<'a>, Error> {
+        let value = tree.get(key)?.map(|kv| kv.value);
+        Ok(Entry {
+            tree,
+            key: key.to_string(),
+            value,
+        })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// value returns the value stored under the key, or None if the key is absent.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// and_modify applies f to the stored value and writes it back,
+    /// doing nothing if the key is absent.
+    pub fn and_modify<F: FnOnce(&mut String)>(mut self, f: F) -> Result<Entry<'a>, Error> {
+        if let Some(value) = self.value.as_mut() {
+            f(value);
+            let value = value.clone();
+            self.tree.upsert(&self.key, |_| value)?;
+        }
+        Ok(self)
+    }
+
+    /// or_insert stores default if the key is absent,
+    /// returning the value stored under the key.
+    pub fn or_insert(self, default: String) -> Result<String, Error> {
+        self.or_insert_with(|| default)
+    }
+
+    /// or_insert_with stores the value returned by f if the key is absent,
+    /// returning the value stored under the key.
+    pub fn or_insert_with<F: FnOnce() -> String>(self, f: F) -> Result<String, Error> {
+        match self.value {
+            Some(value) => Ok(value),
+            None => {
+                let value = f();
+                let stored = value.clone();
+                self.tree.upsert(&self.key, |_| stored)?;
+                Ok(value)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn entry_inserts_or_modifies() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_entry"))
+            .b_parameter(2)
+            .build()?;
+        for word in ["b", "a", "b", "c", "b", "a"].iter() {
+            btree
+                .entry(word)?
+                .and_modify(|count| *count = (count.parse::<u32>().unwrap_or(0) + 1).to_string())?
+                .or_insert("1".to_string())?;
+        }
+        assert_eq!(btree.search("a".to_string())?.value, "2");
+        assert_eq!(btree.search("b".to_string())?.value, "3");
+        assert_eq!(btree.search("c".to_string())?.value, "1");
+
+        let entry = btree.entry("d")?;
+        assert_eq!((entry.key(), entry.value()), ("d", None));
+        assert_eq!(entry.or_insert_with(|| "x".to_string())?, "x");
+        assert_eq!(btree.entry("d")?.or_insert("y".to_string())?, "x");
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use crate::key_policy::KeyRule;
+
+#[derive(Clone, Debug)]
 pub enum Error {
   KeyNotFound,
   KeyAlreadyExists,
@@ -6,11 +8,84 @@ pub enum Error {
   KeyOverflowError,
   ValueOverflowError,
   TryFromSliceError(&'static str),
+  /// Never returned anymore, page bytes that are not valid UTF-8 are reported
+  /// as InvalidUtf8 along with the position of the first invalid byte.
+  #[deprecated(note = "use Error::InvalidUtf8 instead")]
   UTF8Error,
+  /// Page bytes that should hold a string are not valid UTF-8,
+  /// offset is the position of the first invalid byte within the page.
+  InvalidUtf8 {
+    offset: usize,
+  },
+  /// The first page of the file is not a valid tree header.
+  InvalidHeader,
+  /// The node at offset failed a consistency check.
+  Corrupted {
+    offset: usize,
+  },
+  /// Inserting would exceed the quota configured for prefix.
+  QuotaExceeded {
+    prefix: String,
+  },
+  /// The key breaks a rule of the configured KeyValidator.
+  InvalidKey {
+    key: String,
+    rule: KeyRule,
+  },
+  /// The pair stored under key cannot be changed under the configured WriteMode.
+  Immutable {
+    key: String,
+  },
+  /// The device holding the tree file ran out of space. The failed operation
+  /// released the pages it allocated; the tree remains usable once space is freed.
+  DiskFull,
+  /// Growing the tree file would exceed the size configured with BTreeBuilder::max_size.
+  CapacityExceeded,
+  /// The tree file was replaced by another handle since this one read it,
+  /// reopen the tree to see the current file.
+  StaleHandle,
+  /// BTree::merge was called on a tree built without BTreeBuilder::merge_operator.
+  NoMergeOperator,
+  /// BTree::increment found a value under key that is not an integer,
+  /// or adding to it would overflow.
+  InvalidNumber {
+    key: String,
+  },
+  /// BTree::insert_with_ttl was called on a tree without an Expiry, see BTree::set_expiry.
+  NoExpiry,
+  /// BTreeBuilder::build or open_with_check was called without a path,
+  /// set one with BTreeBuilder::path.
+  PathRequired,
+  /// The key ends with TRUNCATION_MARKER and cannot be written under KeyPolicy::Truncate.
+  ReservedKey {
+    key: String,
+  },
+  /// TieredTree::delete was called for a key held by a read-only archive tier.
+  Archived {
+    key: String,
+  },
 }
 
 impl std::convert::From<std::io::Error> for Error {
-  fn from(_e: std::io::Error) -> Error {
-    Error::UnexpectedError
+  fn from(e: std::io::Error) -> Error {
+    match e.kind() {
+      std::io::ErrorKind::StorageFull => Error::DiskFull,
+      _ => Error::UnexpectedError,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::error::Error;
+
+  #[test]
+  fn storage_full_maps_to_disk_full() {
+    use std::io;
+
+    let e = Error::from(io::Error::from(io::ErrorKind::StorageFull));
+    assert!(matches!(e, Error::DiskFull));
+    let e = Error::from(io::Error::from(io::ErrorKind::NotFound));
+    assert!(matches!(e, Error::UnexpectedError));
   }
 }
@@ -0,0 +1,166 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{Key, KeyValuePair};
+use crate::record_log;
+use std::time::Duration;
+
+/// Expiry keeps the expiry times set with BTree::insert_with_ttl in a separate tree,
+/// see BTree::set_expiry. Leaf cells have no room for a timestamp, so each expiring key
+/// is stored again in this tree along with the time it expires at, in milliseconds since
+/// the Unix epoch written in base 36 to fit in a single value.
+pub struct Expiry {
+    tree: BTree,
+}
+
+impl Expiry {
+    /// new opens the expiry times stored in tree.
+    pub fn new(tree: BTree) -> Expiry {
+        Expiry { tree }
+    }
+
+    /// expires_at returns when key expires in milliseconds since the Unix epoch,
+    /// or None if key does not expire.
+    pub fn expires_at(&mut self, key: &str) -> Result<Option<u64>, Error> {
+        match self.tree.get(key)? {
+            Some(kv) => decode(&kv.value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// is_expired returns true if key expired by now.
+    pub fn is_expired(&mut self, key: &str) -> Result<bool, Error> {
+        if self.tree.is_empty() {
+            return Ok(false);
+        }
+        let now = record_log::now_millis();
+        Ok(self.expires_at(key)?.is_some_and(|at| at <= now))
+    }
+
+    /// expired returns every key that expired by now, in ascending key order.
+    pub fn expired(&mut self) -> Result<Vec<String>, Error> {
+        let now = record_log::now_millis();
+        let mut expired = Vec::new();
+        for kv in self.tree.scan(..) {
+            let kv = kv?;
+            if decode(&kv.value)? <= now {
+                expired.push(kv.key);
+            }
+        }
+        Ok(expired)
+    }
+
+    /// set makes key expire ttl from now.
+    pub(crate) fn set(&mut self, key: &str, ttl: Duration) -> Result<(), Error> {
+        let at = record_log::now_millis().saturating_add(ttl.as_millis() as u64);
+        self.tree
+            .insert(KeyValuePair::new(key.to_string(), encode(at)))?;
+        Ok(())
+    }
+
+    /// clear makes key never expire.
+    pub(crate) fn clear(&mut self, key: &str) -> Result<(), Error> {
+        // Spare the lookup on every write while no key expires.
+        if self.tree.is_empty() {
+            return Ok(());
+        }
+        match self.tree.delete(Key(key.to_string())) {
+            Err(Error::KeyNotFound) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// clear_all makes every key never expire, see BTree::clear.
+    pub(crate) fn clear_all(&mut self) -> Result<(), Error> {
+        self.tree.clear()
+    }
+
+    /// snapshot returns a read-only view of the expiry times for a snapshot of the tree,
+    /// see BTree::par_scan.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn snapshot(&self) -> Result<Expiry, Error> {
+        Ok(Expiry {
+            tree: self.tree.snapshot()?,
+        })
+    }
+
+    pub fn into_inner(self) -> BTree {
+        self.tree
+    }
+}
+
+fn encode(mut at: u64) -> String {
+    let mut digits = Vec::new();
+    loop {
+        digits.extend(std::char::from_digit((at % 36) as u32, 36));
+        at /= 36;
+        if at == 0 {
+            break;
+        }
+    }
+    digits.into_iter().rev().collect()
+}
+
+fn decode(encoded: &str) -> Result<u64, Error> {
+    u64::from_str_radix(encoded, 36).map_err(|_| Error::UnexpectedError)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn expired_pairs_are_hidden_and_purged() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::expiry::Expiry;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+        use std::thread;
+        use std::time::Duration;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_expiry"))
+            .b_parameter(2)
+            .build()?;
+        let expiry = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_expiry_times"))
+            .b_parameter(2)
+            .build()?;
+        let kv = |key: &str| KeyValuePair::new(key.to_string(), "v".to_string());
+        assert!(matches!(
+            btree.insert_with_ttl(kv("a"), Duration::from_secs(60)),
+            Err(Error::NoExpiry)
+        ));
+        btree.set_expiry(Expiry::new(expiry));
+        btree.insert(kv("a"))?;
+        btree.insert_with_ttl(kv("b"), Duration::from_millis(10))?;
+        btree.insert_with_ttl(kv("c"), Duration::from_millis(10))?;
+        btree.insert_with_ttl(kv("d"), Duration::from_secs(60))?;
+        btree.insert_with_ttl(kv("e"), Duration::from_millis(10))?;
+        // Writing a key without a ttl makes it permanent again.
+        btree.insert(kv("c"))?;
+        // Deleting a key drops its expiry along with it.
+        btree.delete(Key("e".to_string()))?;
+        btree.insert(kv("e"))?;
+        thread::sleep(Duration::from_millis(20));
+
+        assert!(btree.get("b")?.is_none());
+        assert!(matches!(btree.get_raw("b"), Err(Error::KeyNotFound)));
+        #[cfg(feature = "rayon")]
+        {
+            let scanned = std::sync::Mutex::new(Vec::new());
+            btree.par_scan(.., |kv| scanned.lock().unwrap().push(kv.key))?;
+            let mut scanned = scanned.into_inner().unwrap();
+            scanned.sort();
+            assert_eq!(scanned, vec!["a", "c", "d", "e"]);
+        }
+        let keys: Vec<String> = btree.keys().collect::<Result<_, _>>()?;
+        assert_eq!(keys, vec!["a", "c", "d", "e"]);
+        // Expired pairs still take up space until purged.
+        assert_eq!(btree.len(), 5);
+        assert_eq!(btree.purge_expired()?, 1);
+        assert_eq!(btree.len(), 4);
+        assert_eq!(btree.expiry().unwrap().expired()?, Vec::<String>::new());
+        assert!(btree.expiry().unwrap().expires_at("d")?.is_some());
+        Ok(())
+    }
+}
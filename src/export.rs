@@ -0,0 +1,112 @@
+use crate::error::Error;
+use crate::node_type::KeyValuePair;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Callback returning the value to export in place of the stored value of a key.
+pub type Redactor = Arc<dyn Fn(&str, &str) -> String + Send + Sync>;
+
+/// ExportFilter selects and masks the pairs written by BTree::export.
+/// By default every pair is exported unchanged.
+#[derive(Clone, Default)]
+pub struct ExportFilter {
+    prefixes: Vec<String>,
+    redactor: Option<Redactor>,
+}
+
+impl ExportFilter {
+    pub fn new() -> ExportFilter {
+        ExportFilter::default()
+    }
+
+    /// allow_prefix restricts the export to keys starting with one of the allowed prefixes.
+    pub fn allow_prefix(mut self, prefix: &str) -> ExportFilter {
+        self.prefixes.push(prefix.to_string());
+        self
+    }
+
+    /// redact replaces every exported value with the result of redactor,
+    /// which is called with the key and the stored value.
+    pub fn redact(mut self, redactor: Redactor) -> ExportFilter {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// apply returns the pair as it should be exported, or None if it is filtered out.
+    pub fn apply(&self, kv: KeyValuePair) -> Option<KeyValuePair> {
+        if !self.prefixes.is_empty() && !self.prefixes.iter().any(|p| kv.key.starts_with(p)) {
+            return None;
+        }
+        match &self.redactor {
+            Some(redactor) => {
+                let value = redactor(&kv.key, &kv.value);
+                Some(KeyValuePair::new(kv.key, value))
+            }
+            None => Some(kv),
+        }
+    }
+}
+
+/// write_pair writes a pair as a line of tab separated key and value,
+/// escaping backslashes, tabs and newlines.
+pub(crate) fn write_pair<W: Write>(writer: &mut W, kv: &KeyValuePair) -> Result<(), Error> {
+    writeln!(writer, "{}\t{}", escape(&kv.key), escape(&kv.value))?;
+    Ok(())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn export_applies_allowlist_and_redaction() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::export::ExportFilter;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+        use std::sync::Arc;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_export"))
+            .b_parameter(2)
+            .build()?;
+        btree.insert(KeyValuePair::new("cfg:a".to_string(), "on".to_string()))?;
+        btree.insert(KeyValuePair::new(
+            "user:1".to_string(),
+            "secret".to_string(),
+        ))?;
+        btree.insert(KeyValuePair::new(
+            "user:2".to_string(),
+            "tab\there".to_string(),
+        ))?;
+        btree.insert(KeyValuePair::new("zzz".to_string(), "other".to_string()))?;
+
+        let filter = ExportFilter::new()
+            .allow_prefix("cfg:")
+            .allow_prefix("user:")
+            .redact(Arc::new(|key, value| {
+                if key.starts_with("user:") {
+                    "***".to_string()
+                } else {
+                    value.to_string()
+                }
+            }));
+        let mut out = Vec::new();
+        assert_eq!(btree.export(&mut out, &filter)?, 3);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "cfg:a\ton\nuser:1\t***\nuser:2\t***\n"
+        );
+
+        let mut out = Vec::new();
+        btree.export(&mut out, &ExportFilter::new().allow_prefix("user:2"))?;
+        assert_eq!(String::from_utf8(out).unwrap(), "user:2\ttab\\there\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::error::Error;
+use crate::node_type::Offset;
+use crate::page::Page;
+use crate::page_layout::{
+    HEADER_B_PARAMETER_OFFSET, HEADER_COMPACTIONS_OFFSET, HEADER_GENERATION_OFFSET,
+    HEADER_LEN_OFFSET, HEADER_LOGICAL_BYTES_OFFSET, HEADER_PHYSICAL_BYTES_OFFSET,
+    HEADER_RECOVERIES_OFFSET, HEADER_ROOT_OFFSET, HEADER_VALUE_VERSION_OFFSET,
+    HEADER_WRITES_OFFSET, MAGIC, MAGIC_OFFSET, MAGIC_SIZE, PAGE_SIZE,
+};
+use crate::stats::LifetimeStats;
+use std::convert::TryFrom;
+
+/// Header is the first page of the tree file, it identifies the file
+/// and records where the root node currently lives.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Header {
+    pub root_offset: Offset,
+    pub b: usize,
+    /// Format version of the stored values, 0 until the first migration.
+    pub value_version: usize,
+    /// Counters as of the last header write, zero in files that predate them.
+    pub lifetime: LifetimeStats,
+    /// Number of pairs in the tree, None if the count may be stale
+    /// because the tree was not closed cleanly.
+    pub len: Option<usize>,
+    /// Number of times the file was replaced by another one, see BTree::replace_with.
+    pub generation: usize,
+}
+
+/// Implement TryFrom<Page> for Header validating the magic bytes of the page.
+impl TryFrom<Page> for Header {
+    type Error = Error;
+    fn try_from(page: Page) -> Result<Header, Error> {
+        if page.get_ptr_from_offset(MAGIC_OFFSET, MAGIC_SIZE) != MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        Ok(Header {
+            root_offset: Offset(page.get_value_from_offset(HEADER_ROOT_OFFSET)?),
+            b: page.get_value_from_offset(HEADER_B_PARAMETER_OFFSET)?,
+            value_version: page.get_value_from_offset(HEADER_VALUE_VERSION_OFFSET)?,
+            lifetime: LifetimeStats {
+                writes: page.get_value_from_offset(HEADER_WRITES_OFFSET)?,
+                logical_bytes: page.get_value_from_offset(HEADER_LOGICAL_BYTES_OFFSET)?,
+                physical_bytes: page.get_value_from_offset(HEADER_PHYSICAL_BYTES_OFFSET)?,
+                compactions: page.get_value_from_offset(HEADER_COMPACTIONS_OFFSET)?,
+                recoveries: page.get_value_from_offset(HEADER_RECOVERIES_OFFSET)?,
+            },
+            len: page
+                .get_value_from_offset(HEADER_LEN_OFFSET)?
+                .checked_sub(1),
+            generation: page.get_value_from_offset(HEADER_GENERATION_OFFSET)?,
+        })
+    }
+}
+
+/// Implement TryFrom<&Header> for Page serializing the header to its on-disk page.
+impl TryFrom<&Header> for Page {
+    type Error = Error;
+    fn try_from(header: &Header) -> Result<Page, Error> {
+        let mut page = Page::new([0x00; PAGE_SIZE]);
+        page.write_bytes_at_offset(&MAGIC, MAGIC_OFFSET, MAGIC_SIZE)?;
+        page.write_value_at_offset(HEADER_ROOT_OFFSET, header.root_offset.0)?;
+        page.write_value_at_offset(HEADER_B_PARAMETER_OFFSET, header.b)?;
+        page.write_value_at_offset(HEADER_VALUE_VERSION_OFFSET, header.value_version)?;
+        let lifetime = &header.lifetime;
+        page.write_value_at_offset(HEADER_WRITES_OFFSET, lifetime.writes)?;
+        page.write_value_at_offset(HEADER_LOGICAL_BYTES_OFFSET, lifetime.logical_bytes)?;
+        page.write_value_at_offset(HEADER_PHYSICAL_BYTES_OFFSET, lifetime.physical_bytes)?;
+        page.write_value_at_offset(HEADER_COMPACTIONS_OFFSET, lifetime.compactions)?;
+        page.write_value_at_offset(HEADER_RECOVERIES_OFFSET, lifetime.recoveries)?;
+        page.write_value_at_offset(HEADER_LEN_OFFSET, header.len.map_or(0, |len| len + 1))?;
+        page.write_value_at_offset(HEADER_GENERATION_OFFSET, header.generation)?;
+        Ok(page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn header_to_page_and_back() -> Result<(), Error> {
+        use crate::header::Header;
+        use crate::node_type::Offset;
+        use crate::page::Page;
+        use crate::page_layout::PAGE_SIZE;
+        use crate::stats::LifetimeStats;
+        use std::convert::TryFrom;
+
+        let header = Header {
+            root_offset: Offset(PAGE_SIZE * 3),
+            b: 2,
+            value_version: 1,
+            lifetime: LifetimeStats {
+                writes: 5,
+                logical_bytes: 40,
+                physical_bytes: PAGE_SIZE * 4,
+                compactions: 1,
+                recoveries: 2,
+            },
+            len: Some(0),
+            generation: 3,
+        };
+        let res = Header::try_from(Page::try_from(&header)?)?;
+        assert_eq!(res, header);
+        let header = Header {
+            len: None,
+            ..header
+        };
+        assert_eq!(Header::try_from(Page::try_from(&header)?)?, header);
+
+        // A page without the magic bytes is not a header.
+        assert!(matches!(
+            Header::try_from(Page::new([0x00; PAGE_SIZE])),
+            Err(Error::InvalidHeader)
+        ));
+        Ok(())
+    }
+}
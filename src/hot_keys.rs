@@ -0,0 +1,102 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Callback invoked with a hot key and its estimated number of writes in the current window.
+pub type HotKeyCallback = Arc<dyn Fn(&str, usize) + Send + Sync>;
+
+/// HotKeyDetector samples writes per key and invokes a callback once per window
+/// for every key whose estimated write count reaches a threshold.
+/// Counts are reset at the end of every window, bounding memory to the keys
+/// sampled within one window.
+#[derive(Clone)]
+pub struct HotKeyDetector {
+  sample_rate: usize,
+  threshold: usize,
+  window: Duration,
+  callback: HotKeyCallback,
+  writes: usize,
+  window_start: Instant,
+  counts: HashMap<String, usize>,
+  reported: HashSet<String>,
+}
+
+impl HotKeyDetector {
+  /// new creates a detector sampling one of every sample_rate writes and reporting keys
+  /// with at least threshold estimated writes within window.
+  pub fn new(
+    sample_rate: usize,
+    threshold: usize,
+    window: Duration,
+    callback: HotKeyCallback,
+  ) -> HotKeyDetector {
+    HotKeyDetector {
+      sample_rate: sample_rate.max(1),
+      threshold,
+      window,
+      callback,
+      writes: 0,
+      window_start: Instant::now(),
+      counts: HashMap::new(),
+      reported: HashSet::new(),
+    }
+  }
+
+  /// record_write accounts for a write to key, invoking the callback if it became hot.
+  pub fn record_write(&mut self, key: &str) {
+    self.writes += 1;
+    if !self.writes.is_multiple_of(self.sample_rate) {
+      return;
+    }
+    if self.window_start.elapsed() >= self.window {
+      self.window_start = Instant::now();
+      self.counts.clear();
+      self.reported.clear();
+    }
+
+    let count = self.counts.entry(key.to_string()).or_insert(0);
+    *count += 1;
+    let estimated_writes = *count * self.sample_rate;
+    if estimated_writes >= self.threshold && self.reported.insert(key.to_string()) {
+      (self.callback)(key, estimated_writes);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::error::Error;
+
+  #[test]
+  fn hot_key_callback_fires_once_per_window() -> Result<(), Error> {
+    use crate::btree::BTreeBuilder;
+    use crate::hot_keys::HotKeyDetector;
+    use crate::node_type::KeyValuePair;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let hot_keys = Arc::new(Mutex::new(Vec::new()));
+    let reported = hot_keys.clone();
+    let detector = HotKeyDetector::new(
+      1,
+      3,
+      Duration::from_secs(60),
+      Arc::new(move |key: &str, writes: usize| {
+        reported.lock().unwrap().push((key.to_string(), writes));
+      }),
+    );
+    let mut btree = BTreeBuilder::new()
+      .path(Path::new("/tmp/db_hot_keys"))
+      .b_parameter(2)
+      .hot_key_detector(detector)
+      .build()?;
+    for _ in 0..5 {
+      btree.insert(KeyValuePair::new("hot".to_string(), "v".to_string()))?;
+    }
+    btree.insert(KeyValuePair::new("cold".to_string(), "v".to_string()))?;
+
+    assert_eq!(*hot_keys.lock().unwrap(), vec![("hot".to_string(), 3)]);
+    Ok(())
+  }
+}
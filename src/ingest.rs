@@ -0,0 +1,191 @@
+use crate::batch::BatchOp;
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{Key, KeyValuePair};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// A write sent to the ingest thread, along with where to report its outcome.
+type Request = (BatchOp, Sender<Result<(), Error>>);
+
+/// Ingestor takes over a tree and applies writes sent from any number of threads
+/// through IngestHandles. A single thread drains the channel, coalescing the pending
+/// writes into batches of up to max_batch operations ordered by key, and applies the
+/// writes of a batch one at a time so that they share one flush of the file while each
+/// reports its own outcome. A failed write leaves the rest of its batch unaffected.
+pub struct Ingestor {
+    sender: SyncSender<Request>,
+    worker: JoinHandle<BTree>,
+}
+
+impl Ingestor {
+    pub fn new(tree: BTree, max_batch: usize) -> Ingestor {
+        let max_batch = max_batch.max(1);
+        let (sender, receiver) = mpsc::sync_channel(max_batch);
+        let worker = thread::spawn(move || ingest(tree, receiver, max_batch));
+        Ingestor { sender, worker }
+    }
+
+    /// handle returns a handle for sending writes to the ingest thread.
+    pub fn handle(&self) -> IngestHandle {
+        IngestHandle {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// finish waits for the ingest thread to apply every write sent before all handles
+    /// were dropped, and returns the tree.
+    pub fn finish(self) -> Result<BTree, Error> {
+        drop(self.sender);
+        self.worker.join().map_err(|_| Error::UnexpectedError)
+    }
+}
+
+/// IngestHandle sends writes to an Ingestor, it can be cloned and moved to other threads.
+/// Each write blocks until the batch holding it was applied and flushed.
+#[derive(Clone)]
+pub struct IngestHandle {
+    sender: SyncSender<Request>,
+}
+
+impl IngestHandle {
+    pub fn put(&self, kv: KeyValuePair) -> Result<(), Error> {
+        self.send(BatchOp::Put(kv))
+    }
+
+    /// delete removes key, deleting an absent key is not an error.
+    pub fn delete(&self, key: Key) -> Result<(), Error> {
+        self.send(BatchOp::Delete(key))
+    }
+
+    fn send(&self, op: BatchOp) -> Result<(), Error> {
+        let (reply, outcome) = mpsc::channel();
+        self.sender
+            .send((op, reply))
+            .map_err(|_| Error::UnexpectedError)?;
+        outcome.recv().map_err(|_| Error::UnexpectedError)?
+    }
+}
+
+/// ingest applies the writes received on receiver to tree until every sender is gone.
+fn ingest(mut tree: BTree, receiver: Receiver<Request>, max_batch: usize) -> BTree {
+    while let Ok(first) = receiver.recv() {
+        let mut requests = vec![first];
+        while requests.len() < max_batch {
+            match receiver.try_recv() {
+                Ok(request) => requests.push(request),
+                Err(_) => break,
+            }
+        }
+        // The sort is stable, writes to the same key keep their order.
+        requests.sort_by(|(a, _), (b, _)| op_key(a).cmp(op_key(b)));
+        let mut applied = Vec::new();
+        for (op, reply) in requests {
+            match tree.apply_op(&op) {
+                Ok(()) => applied.push(reply),
+                Err(e) => {
+                    let _ = reply.send(Err(e));
+                }
+            }
+        }
+        let flushed = tree.flush_batch();
+        for reply in applied {
+            let _ = reply.send(flushed.clone());
+        }
+    }
+    tree
+}
+
+fn op_key(op: &BatchOp) -> &str {
+    match op {
+        BatchOp::Put(kv) => &kv.key,
+        BatchOp::Delete(key) => &key.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn ingestor_applies_writes_from_many_threads() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::ingest::Ingestor;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+        use std::thread;
+
+        let btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_ingest"))
+            .b_parameter(2)
+            .build()?;
+        let ingestor = Ingestor::new(btree, 16);
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let handle = ingestor.handle();
+                thread::spawn(move || -> Result<(), Error> {
+                    for i in 0..25 {
+                        handle.put(KeyValuePair::new(format!("t{}k{:02}", t, i), i.to_string()))?;
+                    }
+                    handle.delete(Key(format!("t{}k00", t)))?;
+                    // A bad write fails on its own.
+                    assert!(matches!(
+                        handle.put(KeyValuePair::new(format!("t{}", t), "x".repeat(11))),
+                        Err(Error::ValueOverflowError)
+                    ));
+                    Ok(())
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().map_err(|_| Error::UnexpectedError)??;
+        }
+
+        let mut btree = ingestor.finish()?;
+        assert_eq!(btree.len(), 4 * 24);
+        assert_eq!(btree.search("t3k24".to_string())?.value, "24");
+        assert!(matches!(
+            btree.search("t1k00".to_string()),
+            Err(Error::KeyNotFound)
+        ));
+        Ok(())
+    }
+    #[test]
+    fn failed_write_leaves_its_batch_applied_once() -> Result<(), Error> {
+        use crate::batch::BatchOp;
+        use crate::btree::BTreeBuilder;
+        use crate::ingest::ingest;
+        use crate::node_type::KeyValuePair;
+        use crate::write_mode::WriteMode;
+        use std::path::Path;
+        use std::sync::mpsc;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_ingest_write_once"))
+            .b_parameter(2)
+            .write_mode(WriteMode::WriteOnce)
+            .build()?;
+        btree.insert(KeyValuePair::new("b".to_string(), "old".to_string()))?;
+        let (sender, receiver) = mpsc::sync_channel(3);
+        let mut outcomes = Vec::new();
+        for key in ["a", "b", "c"] {
+            let (reply, outcome) = mpsc::channel();
+            let kv = KeyValuePair::new(key.to_string(), "new".to_string());
+            sender
+                .send((BatchOp::Put(kv), reply))
+                .map_err(|_| Error::UnexpectedError)?;
+            outcomes.push(outcome);
+        }
+        drop(sender);
+        let mut btree = ingest(btree, receiver, 3);
+
+        let outcomes: Vec<_> = outcomes.into_iter().map(|o| o.recv()).collect();
+        assert!(matches!(outcomes[0], Ok(Ok(()))));
+        assert!(matches!(outcomes[1], Ok(Err(Error::KeyAlreadyExists))));
+        assert!(matches!(outcomes[2], Ok(Ok(()))));
+        assert_eq!(btree.search("b".to_string())?.value, "old");
+        // Only the two stored puts count, nothing was replayed.
+        assert_eq!(btree.lifetime_stats().writes, 3);
+        Ok(())
+    }
+}
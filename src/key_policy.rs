@@ -0,0 +1,134 @@
+use crate::error::Error;
+use crate::page_layout::KEY_SIZE;
+
+/// Marker appended to keys shortened by KeyPolicy::Truncate.
+pub const TRUNCATION_MARKER: char = '~';
+
+/// KeyPolicy decides what happens to keys that do not fit in KEY_SIZE bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum KeyPolicy {
+  /// Oversized keys are rejected with Error::KeyOverflowError
+  /// before the tree is modified.
+  #[default]
+  Strict,
+  /// Oversized keys are cut at a char boundary to KEY_SIZE - 1 bytes and suffixed with
+  /// TRUNCATION_MARKER. Lookups apply the same truncation, so two long keys sharing
+  /// their first KEY_SIZE - 1 bytes refer to the same entry. Writing a key that fits
+  /// but ends with TRUNCATION_MARKER fails with Error::ReservedKey, as it could alias
+  /// a truncated key.
+  Truncate,
+}
+
+impl KeyPolicy {
+  /// apply returns the key as it is stored in the tree under this policy.
+  pub fn apply(&self, key: &str) -> Result<String, Error> {
+    if key.len() <= KEY_SIZE {
+      return Ok(key.to_string());
+    }
+    match self {
+      KeyPolicy::Strict => Err(Error::KeyOverflowError),
+      KeyPolicy::Truncate => {
+        let mut end = KEY_SIZE - TRUNCATION_MARKER.len_utf8();
+        while !key.is_char_boundary(end) {
+          end -= 1;
+        }
+        let mut truncated = key[..end].to_string();
+        truncated.push(TRUNCATION_MARKER);
+        Ok(truncated)
+      }
+    }
+  }
+
+  /// apply_write returns the key a write stores under this policy, rejecting keys
+  /// that could be mistaken for truncated ones. Lookups of such keys are still
+  /// allowed, as they are the keys scans return for truncated entries.
+  pub fn apply_write(&self, key: &str) -> Result<String, Error> {
+    if *self == KeyPolicy::Truncate && key.len() <= KEY_SIZE && key.ends_with(TRUNCATION_MARKER) {
+      return Err(Error::ReservedKey {
+        key: key.to_string(),
+      });
+    }
+    self.apply(key)
+  }
+}
+
+/// KeyRule is a naming rule of a KeyValidator.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum KeyRule {
+  /// Keys must be at most this many bytes long.
+  MaxLength(usize),
+  /// Keys must only contain these characters.
+  Charset(String),
+  /// Keys must start with one of these prefixes.
+  Prefix(Vec<String>),
+}
+
+/// KeyValidator enforces naming rules on every key inserted into the tree,
+/// rejecting violations with Error::InvalidKey. It has no rules by default.
+#[derive(Clone, Debug, Default)]
+pub struct KeyValidator {
+  rules: Vec<KeyRule>,
+}
+
+impl KeyValidator {
+  pub fn new() -> KeyValidator {
+    KeyValidator::default()
+  }
+
+  pub fn max_length(mut self, max_length: usize) -> KeyValidator {
+    self.rules.push(KeyRule::MaxLength(max_length));
+    self
+  }
+
+  pub fn charset(mut self, chars: &str) -> KeyValidator {
+    self.rules.push(KeyRule::Charset(chars.to_string()));
+    self
+  }
+
+  /// prefixes requires keys to start with one of prefixes.
+  pub fn prefixes(mut self, prefixes: &[&str]) -> KeyValidator {
+    let prefixes = prefixes.iter().map(|prefix| prefix.to_string()).collect();
+    self.rules.push(KeyRule::Prefix(prefixes));
+    self
+  }
+
+  /// validate returns Error::InvalidKey for the first rule key breaks.
+  pub fn validate(&self, key: &str) -> Result<(), Error> {
+    for rule in self.rules.iter() {
+      let valid = match rule {
+        KeyRule::MaxLength(max_length) => key.len() <= *max_length,
+        KeyRule::Charset(chars) => key.chars().all(|c| chars.contains(c)),
+        KeyRule::Prefix(prefixes) => prefixes
+          .iter()
+          .any(|prefix| key.starts_with(prefix.as_str())),
+      };
+      if !valid {
+        return Err(Error::InvalidKey {
+          key: key.to_string(),
+          rule: rule.clone(),
+        });
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::error::Error;
+
+  #[test]
+  fn truncate_respects_char_boundaries() -> Result<(), Error> {
+    use crate::key_policy::KeyPolicy;
+
+    assert_eq!(KeyPolicy::Truncate.apply("short")?, "short");
+    assert_eq!(KeyPolicy::Truncate.apply("abcdefghijkl")?, "abcdefghi~");
+    // 'é' takes two bytes and would be split at byte 9.
+    assert_eq!(KeyPolicy::Truncate.apply("abcdefghéxyz")?, "abcdefgh~");
+    assert!(matches!(
+      KeyPolicy::Strict.apply("abcdefghijkl"),
+      Err(Error::KeyOverflowError)
+    ));
+    Ok(())
+  }
+}
@@ -1,7 +1,34 @@
+pub mod analytics;
+pub mod audit;
+pub mod batch;
 pub mod btree;
+pub mod check;
+pub mod cursor;
+pub mod entry;
 pub mod error;
+pub mod expiry;
+pub mod export;
+pub mod header;
+pub mod hot_keys;
+pub mod ingest;
+pub mod key_policy;
+pub mod merge;
+pub mod middleware;
+pub mod migrations;
 pub mod node;
 pub mod node_type;
 pub mod page;
 pub mod page_layout;
 pub mod pager;
+pub mod partitioner;
+pub mod queue;
+pub mod quota;
+pub mod raw;
+pub mod record_log;
+pub mod scan;
+pub mod scoped;
+pub mod stats;
+pub mod tiered;
+pub mod trash;
+pub mod workload;
+pub mod write_mode;
@@ -0,0 +1,7 @@
+use std::sync::Arc;
+
+/// MergeOperator combines the value currently stored under a key, None if the key
+/// is absent, with an operand given to BTree::merge, returning the value to store.
+/// It should be associative so that a sequence of merges can be folded in any grouping,
+/// e.g. adding to a counter or taking the union of sets.
+pub type MergeOperator = Arc<dyn Fn(&str, Option<&str>, &str) -> String + Send + Sync>;
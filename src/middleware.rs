@@ -0,0 +1,132 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{Key, KeyValuePair};
+
+/// Middleware transforms keys and values on their way into and out of a tree,
+/// e.g. hashing keys, adding tenant prefixes or encrypting values.
+/// Every method defaults to the identity transformation.
+pub trait Middleware {
+    /// encode_key maps a user key to the key stored in the tree.
+    fn encode_key(&self, key: &str) -> Result<String, Error> {
+        Ok(key.to_string())
+    }
+
+    /// encode_value maps a user value to the value stored in the tree.
+    fn encode_value(&self, value: &str) -> Result<String, Error> {
+        Ok(value.to_string())
+    }
+
+    /// decode_value maps a value stored in the tree back to the user value.
+    fn decode_value(&self, value: &str) -> Result<String, Error> {
+        Ok(value.to_string())
+    }
+}
+
+/// A pair of middlewares is applied in order, the first one being the closest to the user.
+impl<A: Middleware, B: Middleware> Middleware for (A, B) {
+    fn encode_key(&self, key: &str) -> Result<String, Error> {
+        self.1.encode_key(&self.0.encode_key(key)?)
+    }
+
+    fn encode_value(&self, value: &str) -> Result<String, Error> {
+        self.1.encode_value(&self.0.encode_value(value)?)
+    }
+
+    fn decode_value(&self, value: &str) -> Result<String, Error> {
+        self.0.decode_value(&self.1.decode_value(value)?)
+    }
+}
+
+/// Layered is a BTree with a middleware applied to every operation.
+pub struct Layered<M: Middleware> {
+    tree: BTree,
+    middleware: M,
+}
+
+impl<M: Middleware> Layered<M> {
+    pub fn new(tree: BTree, middleware: M) -> Layered<M> {
+        Layered { tree, middleware }
+    }
+
+    /// insert returns the replaced pair, keyed by the user key like search.
+    pub fn insert(&mut self, kv: KeyValuePair) -> Result<Option<KeyValuePair>, Error> {
+        let key = self.middleware.encode_key(&kv.key)?;
+        let value = self.middleware.encode_value(&kv.value)?;
+        match self.tree.insert(KeyValuePair::new(key, value))? {
+            Some(old) => {
+                let value = self.middleware.decode_value(&old.value)?;
+                Ok(Some(KeyValuePair::new(kv.key, value)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// search returns the pair stored under key, keyed by the user key
+    /// so that non reversible key encodings (e.g. hashes) are supported.
+    pub fn search(&mut self, key: String) -> Result<KeyValuePair, Error> {
+        let kv = self.tree.search(self.middleware.encode_key(&key)?)?;
+        let value = self.middleware.decode_value(&kv.value)?;
+        Ok(KeyValuePair::new(key, value))
+    }
+
+    pub fn delete(&mut self, key: Key) -> Result<(), Error> {
+        let key = self.middleware.encode_key(&key.0)?;
+        self.tree.delete(Key(key))
+    }
+
+    /// into_inner removes the middleware layer, returning the underlying tree.
+    pub fn into_inner(self) -> BTree {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::middleware::Middleware;
+
+    struct TenantPrefix(&'static str);
+
+    impl Middleware for TenantPrefix {
+        fn encode_key(&self, key: &str) -> Result<String, Error> {
+            Ok(format!("{}{}", self.0, key))
+        }
+    }
+
+    struct ReverseValue;
+
+    impl Middleware for ReverseValue {
+        fn encode_value(&self, value: &str) -> Result<String, Error> {
+            Ok(value.chars().rev().collect())
+        }
+
+        fn decode_value(&self, value: &str) -> Result<String, Error> {
+            Ok(value.chars().rev().collect())
+        }
+    }
+
+    #[test]
+    fn layered_applies_middleware_on_every_operation() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::middleware::Layered;
+        use crate::node_type::{Key, KeyValuePair};
+        use std::path::Path;
+
+        let btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_middleware"))
+            .b_parameter(2)
+            .build()?;
+        let mut layered = Layered::new(btree, (TenantPrefix("t1/"), ReverseValue));
+        layered.insert(KeyValuePair::new("a".to_string(), "hello".to_string()))?;
+
+        let kv = layered.search("a".to_string())?;
+        assert_eq!(kv.key, "a");
+        assert_eq!(kv.value, "hello");
+
+        let mut btree = layered.into_inner();
+        assert_eq!(btree.search("t1/a".to_string())?.value, "olleh");
+        let mut layered = Layered::new(btree, TenantPrefix("t1/"));
+        layered.delete(Key("a".to_string()))?;
+        Ok(())
+    }
+}
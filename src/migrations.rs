@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+/// Migration rewrites a value from one format version to the next,
+/// it is called with the key and the value in the previous format.
+pub type Migration = Arc<dyn Fn(&str, &str) -> String + Send + Sync>;
+
+/// Migrations is an ordered list of value format migrations, the migration at index v
+/// upgrades values from version v to version v + 1. See BTree::migrate.
+#[derive(Clone, Default)]
+pub struct Migrations {
+    steps: Vec<Migration>,
+}
+
+impl Migrations {
+    pub fn new() -> Migrations {
+        Migrations::default()
+    }
+
+    /// step registers the migration to the next version.
+    pub fn step(mut self, migration: Migration) -> Migrations {
+        self.steps.push(migration);
+        self
+    }
+
+    /// latest returns the version values have once every migration ran.
+    pub fn latest(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// get returns the migration upgrading values from version.
+    pub fn get(&self, version: usize) -> Option<&Migration> {
+        self.steps.get(version)
+    }
+}
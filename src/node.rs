@@ -3,7 +3,7 @@ use crate::node_type::{Key, KeyValuePair, NodeType, Offset};
 use crate::page::Page;
 use crate::page_layout::{
     FromByte, INTERNAL_NODE_HEADER_SIZE, INTERNAL_NODE_NUM_CHILDREN_OFFSET, IS_ROOT_OFFSET,
-    KEY_SIZE, LEAF_NODE_HEADER_SIZE, LEAF_NODE_NUM_PAIRS_OFFSET, NODE_TYPE_OFFSET,
+    KEY_SIZE, LEAF_NODE_HEADER_SIZE, LEAF_NODE_NUM_PAIRS_OFFSET, NODE_TYPE_OFFSET, PAGE_SIZE,
     PARENT_POINTER_OFFSET, PTR_SIZE, VALUE_SIZE,
 };
 use std::convert::TryFrom;
@@ -44,7 +44,7 @@ impl Node {
                     Node::new(
                         NodeType::Internal(sibling_children, sibling_keys),
                         false,
-                        self.parent_offset.clone(),
+                        self.parent_offset,
                     ),
                 ))
             }
@@ -56,11 +56,7 @@ impl Node {
 
                 Ok((
                     Key(median_pair.key),
-                    Node::new(
-                        NodeType::Leaf(sibling_pairs),
-                        false,
-                        self.parent_offset.clone(),
-                    ),
+                    Node::new(NodeType::Leaf(sibling_pairs), false, self.parent_offset),
                 ))
             }
             NodeType::Unexpected => Err(Error::UnexpectedError),
@@ -76,16 +72,21 @@ impl TryFrom<Page> for Node {
         let raw = page.get_data();
         let node_type = NodeType::from(raw[NODE_TYPE_OFFSET]);
         let is_root = raw[IS_ROOT_OFFSET].from_byte();
-        let parent_offset: Option<Offset>;
-        if is_root {
-            parent_offset = None;
+        let parent_offset = if is_root {
+            None
         } else {
-            parent_offset = Some(Offset(page.get_value_from_offset(PARENT_POINTER_OFFSET)?));
-        }
+            Some(Offset(page.get_value_from_offset(PARENT_POINTER_OFFSET)?))
+        };
 
         match node_type {
             NodeType::Internal(mut children, mut keys) => {
                 let num_children = page.get_value_from_offset(INTERNAL_NODE_NUM_CHILDREN_OFFSET)?;
+                // A count that does not fit in the page can only come from a corrupted page.
+                if num_children
+                    > (PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE + KEY_SIZE) / (PTR_SIZE + KEY_SIZE)
+                {
+                    return Err(Error::UnexpectedError);
+                }
                 let mut offset = INTERNAL_NODE_HEADER_SIZE;
                 for _i in 1..=num_children {
                     let child_offset = page.get_value_from_offset(offset)?;
@@ -98,7 +99,11 @@ impl TryFrom<Page> for Node {
                     let key_raw = page.get_ptr_from_offset(offset, KEY_SIZE);
                     let key = match str::from_utf8(key_raw) {
                         Ok(key) => key,
-                        Err(_) => return Err(Error::UTF8Error),
+                        Err(e) => {
+                            return Err(Error::InvalidUtf8 {
+                                offset: offset + e.valid_up_to(),
+                            })
+                        }
                     };
                     offset += KEY_SIZE;
                     // Trim leading or trailing zeros.
@@ -114,20 +119,33 @@ impl TryFrom<Page> for Node {
             NodeType::Leaf(mut pairs) => {
                 let mut offset = LEAF_NODE_NUM_PAIRS_OFFSET;
                 let num_keys_val_pairs = page.get_value_from_offset(offset)?;
+                if num_keys_val_pairs
+                    > (PAGE_SIZE - LEAF_NODE_HEADER_SIZE) / (KEY_SIZE + VALUE_SIZE)
+                {
+                    return Err(Error::UnexpectedError);
+                }
                 offset = LEAF_NODE_HEADER_SIZE;
 
                 for _i in 0..num_keys_val_pairs {
                     let key_raw = page.get_ptr_from_offset(offset, KEY_SIZE);
                     let key = match str::from_utf8(key_raw) {
                         Ok(key) => key,
-                        Err(_) => return Err(Error::UTF8Error),
+                        Err(e) => {
+                            return Err(Error::InvalidUtf8 {
+                                offset: offset + e.valid_up_to(),
+                            })
+                        }
                     };
                     offset += KEY_SIZE;
 
                     let value_raw = page.get_ptr_from_offset(offset, VALUE_SIZE);
                     let value = match str::from_utf8(value_raw) {
                         Ok(val) => val,
-                        Err(_) => return Err(Error::UTF8Error),
+                        Err(e) => {
+                            return Err(Error::InvalidUtf8 {
+                                offset: offset + e.valid_up_to(),
+                            })
+                        }
                     };
                     offset += VALUE_SIZE;
 
@@ -150,7 +168,6 @@ impl TryFrom<Page> for Node {
 ///  Unit Tests. ///
 ///              ///
 ////////////////////
-
 #[cfg(test)]
 mod tests {
     use crate::error::Error;
@@ -165,7 +182,7 @@ mod tests {
     #[test]
     fn page_to_node_works_for_leaf_node() -> Result<(), Error> {
         const DATA_LEN: usize = LEAF_NODE_HEADER_SIZE + KEY_SIZE + VALUE_SIZE;
-        let page_data: [u8; DATA_LEN + 1] = [
+        let page_data: [u8; DATA_LEN] = [
             0x01, // Is-Root byte.
             0x02, // Leaf Node type byte.
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Parent offset.
@@ -181,7 +198,7 @@ mod tests {
 
         let node = Node::try_from(Page::new(page))?;
 
-        assert_eq!(node.is_root, true);
+        assert!(node.is_root);
         Ok(())
     }
 
@@ -190,7 +207,7 @@ mod tests {
         use crate::node_type::Key;
 
         const DATA_LEN: usize = INTERNAL_NODE_HEADER_SIZE + 3 * PTR_SIZE + 2 * KEY_SIZE;
-        let page_data: [u8; DATA_LEN + 1] = [
+        let page_data: [u8; DATA_LEN] = [
             0x01, // Is-Root byte.
             0x01, // Internal Node type byte.
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Parent offset.
@@ -214,7 +231,7 @@ mod tests {
         if let NodeType::Internal(_, keys) = node.node_type {
             assert_eq!(keys.len(), 2);
 
-            let Key(first_key) = match keys.get(0) {
+            let Key(first_key) = match keys.first() {
                 Some(key) => key,
                 None => return Err(Error::UnexpectedError),
             };
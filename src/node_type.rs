@@ -1,12 +1,21 @@
 use std::cmp::{Eq, Ord, Ordering, PartialOrd};
 use std::convert::From;
 
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 pub struct Offset(pub usize);
 
 #[derive(Clone, Eq, PartialEq, PartialOrd, Ord, Debug)]
 pub struct Key(pub String);
 
+/// child_index returns the index of the child of an internal node with separators keys
+/// that key belongs to. The separators are compared with key in place, so descending
+/// a level does not allocate a Key to search for.
+pub(crate) fn child_index(keys: &[Key], key: &str) -> usize {
+  keys
+    .binary_search_by(|sep| sep.0.as_str().cmp(key))
+    .unwrap_or_else(|x| x)
+}
+
 #[derive(Clone, Eq, Debug)]
 pub struct KeyValuePair {
   pub key: String,
@@ -3,14 +3,37 @@ use std::mem::size_of;
 
 /// A Single Page Size.
 /// Each page represents a node in the BTree
+#[cfg(not(feature = "small-pages"))]
 pub const PAGE_SIZE: usize = 4096;
+#[cfg(feature = "small-pages")]
+pub const PAGE_SIZE: usize = 512;
 
 pub const PTR_SIZE: usize = size_of::<usize>();
 
+/// File header layout, the header occupies the first page of the file
+/// and points at the root node.
+pub const HEADER_OFFSET: usize = 0;
+pub const MAGIC_OFFSET: usize = 0;
+pub const MAGIC_SIZE: usize = 8;
+pub const MAGIC: [u8; MAGIC_SIZE] = *b"b_tree\x00\x01";
+pub const HEADER_ROOT_OFFSET: usize = MAGIC_OFFSET + MAGIC_SIZE;
+pub const HEADER_B_PARAMETER_OFFSET: usize = HEADER_ROOT_OFFSET + PTR_SIZE;
+pub const HEADER_VALUE_VERSION_OFFSET: usize = HEADER_B_PARAMETER_OFFSET + PTR_SIZE;
+/// Lifetime counters, see LifetimeStats.
+pub const HEADER_WRITES_OFFSET: usize = HEADER_VALUE_VERSION_OFFSET + PTR_SIZE;
+pub const HEADER_LOGICAL_BYTES_OFFSET: usize = HEADER_WRITES_OFFSET + PTR_SIZE;
+pub const HEADER_PHYSICAL_BYTES_OFFSET: usize = HEADER_LOGICAL_BYTES_OFFSET + PTR_SIZE;
+pub const HEADER_COMPACTIONS_OFFSET: usize = HEADER_PHYSICAL_BYTES_OFFSET + PTR_SIZE;
+pub const HEADER_RECOVERIES_OFFSET: usize = HEADER_COMPACTIONS_OFFSET + PTR_SIZE;
+/// Number of pairs plus one, or zero if the count may be stale.
+pub const HEADER_LEN_OFFSET: usize = HEADER_RECOVERIES_OFFSET + PTR_SIZE;
+/// Incremented every time the file is replaced, see BTree::replace_with.
+pub const HEADER_GENERATION_OFFSET: usize = HEADER_LEN_OFFSET + PTR_SIZE;
+
 /// Common Node header layout (10 bytes in total)
 pub const IS_ROOT_SIZE: usize = 1;
 pub const IS_ROOT_OFFSET: usize = 0;
-pub const NODE_TYPE_SIZE: usize = 0;
+pub const NODE_TYPE_SIZE: usize = 1;
 pub const NODE_TYPE_OFFSET: usize = 1;
 pub const PARENT_POINTER_OFFSET: usize = 2;
 pub const PARENT_POINTER_SIZE: usize = PTR_SIZE;
@@ -47,6 +70,7 @@ pub const VALUE_SIZE: usize = 10;
 
 /// Wrappers for converting byte to bool and back
 /// The convention used throughout the index file is: one is true; otherwise is false
+#[allow(clippy::wrong_self_convention)]
 pub trait FromByte {
   fn from_byte(&self) -> bool;
 }
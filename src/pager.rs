@@ -1,15 +1,27 @@
 use crate::error::Error;
 use crate::node_type::Offset;
 use crate::page::Page;
-use crate::page_layout::PAGE_SIZE;
+use crate::page_layout::{HEADER_OFFSET, PAGE_SIZE};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::io::{Read, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub struct Pager {
   file: File,
+  path: PathBuf,
   cursor: usize,
+  /// Allocation bitmap, one entry per page in the file;
+  /// true means the page is in use by some node.
+  bitmap: Vec<bool>,
+  /// Number of free entries in the bitmap, kept up to date with it.
+  free: usize,
+  /// Total number of bytes written to the file.
+  bytes_written: usize,
+  /// Whether freed pages are overwritten with zeros.
+  secure_wipe: bool,
+  /// Maximum size of the file in bytes, if capped.
+  max_size: Option<usize>,
 }
 
 impl Pager {
@@ -20,13 +32,61 @@ impl Pager {
       .write(true)
       .truncate(true)
       .open(path)?;
+    // Make the (re)created file itself durable, not only its pages.
+    fd.sync_all()?;
+    sync_dir(path)?;
 
     Ok(Pager {
       file: fd,
+      path: path.to_path_buf(),
       cursor: 0,
+      bitmap: Vec::new(),
+      free: 0,
+      bytes_written: 0,
+      secure_wipe: false,
+      max_size: None,
     })
   }
 
+  /// open opens an existing file without truncating it. Every page of the file is
+  /// considered allocated until BTree::gc returns the unreachable ones to the free list.
+  pub fn open(path: &Path) -> Result<Pager, Error> {
+    let fd = OpenOptions::new().read(true).write(true).open(path)?;
+    let num_pages = fd.metadata()?.len() as usize / PAGE_SIZE;
+
+    Ok(Pager {
+      file: fd,
+      path: path.to_path_buf(),
+      cursor: num_pages * PAGE_SIZE,
+      bitmap: vec![true; num_pages],
+      free: 0,
+      bytes_written: 0,
+      secure_wipe: false,
+      max_size: None,
+    })
+  }
+
+  /// read_header_page reads the header page of the file at path on its own,
+  /// without the cost of opening a Pager over the whole file.
+  pub fn read_header_page(path: &Path) -> Result<Page, Error> {
+    let mut file = File::open(path)?;
+    let mut page: [u8; PAGE_SIZE] = [0x00; PAGE_SIZE];
+    file.seek(SeekFrom::Start(HEADER_OFFSET as u64))?;
+    file.read_exact(&mut page)?;
+    Ok(Page::new(page))
+  }
+
+  /// path returns the path of the underlying file.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// sync flushes the file's data and metadata to disk.
+  pub fn sync(&mut self) -> Result<(), Error> {
+    self.file.sync_all()?;
+    Ok(())
+  }
+
   pub fn get_page(&mut self, offset: &Offset) -> Result<Page, Error> {
     let mut page: [u8; PAGE_SIZE] = [0x00; PAGE_SIZE];
     self.file.seek(SeekFrom::Start(offset.0 as u64))?;
@@ -34,17 +94,288 @@ impl Pager {
     Ok(Page::new(page))
   }
 
+  /// write_page writes a page to the first free page in the file,
+  /// growing the file if no free page is available.
   pub fn write_page(&mut self, page: Page) -> Result<Offset, Error> {
-    self.file.seek(SeekFrom::Start(self.cursor as u64))?;
+    let offset = self.allocate(None)?;
+    self.write_allocated(page, offset)
+  }
+
+  /// write_page_near writes a page to the free page closest to hint,
+  /// keeping related nodes (e.g. siblings) in nearby regions of the file
+  /// so that scans read mostly sequential data.
+  pub fn write_page_near(&mut self, page: Page, hint: &Offset) -> Result<Offset, Error> {
+    let offset = self.allocate(Some(hint))?;
+    self.write_allocated(page, offset)
+  }
+
+  /// write_allocated writes page to a freshly allocated offset, releasing the
+  /// allocation if the write fails (e.g. with Error::DiskFull) so that no page leaks.
+  fn write_allocated(&mut self, page: Page, offset: Offset) -> Result<Offset, Error> {
+    match self.write_page_at_offset(page, &offset) {
+      Ok(()) => Ok(offset),
+      Err(e) => {
+        self.release(&offset);
+        Err(e)
+      }
+    }
+  }
+
+  pub fn write_page_at_offset(&mut self, page: Page, offset: &Offset) -> Result<(), Error> {
+    self.file.seek(SeekFrom::Start(offset.0 as u64))?;
     self.file.write_all(&page.get_data())?;
+    self.bytes_written += PAGE_SIZE;
+    Ok(())
+  }
+
+  /// bytes_written returns the total number of bytes written to the file.
+  pub fn bytes_written(&self) -> usize {
+    self.bytes_written
+  }
+
+  /// set_secure_wipe controls whether freed pages are zeroed on disk
+  /// so that their contents do not linger in the file.
+  pub fn set_secure_wipe(&mut self, secure_wipe: bool) {
+    self.secure_wipe = secure_wipe;
+  }
+
+  pub fn secure_wipe(&self) -> bool {
+    self.secure_wipe
+  }
+
+  /// set_max_size caps the size of the file, allocations that would grow it
+  /// past max_size bytes fail with Error::CapacityExceeded.
+  pub fn set_max_size(&mut self, max_size: Option<usize>) {
+    self.max_size = max_size;
+  }
+
+  pub fn max_size(&self) -> Option<usize> {
+    self.max_size
+  }
+
+  /// free_page marks the page at offset as free so that it can be reused,
+  /// zeroing it first if secure wipe is enabled.
+  pub fn free_page(&mut self, offset: &Offset) -> Result<(), Error> {
+    match self.bitmap.get(offset.0 / PAGE_SIZE) {
+      Some(true) => {
+        if self.secure_wipe {
+          self.write_page_at_offset(Page::new([0x00; PAGE_SIZE]), offset)?;
+        }
+        self.bitmap[offset.0 / PAGE_SIZE] = false;
+        self.free += 1;
+        Ok(())
+      }
+      // Freeing a page twice or outside of the file.
+      _ => Err(Error::UnexpectedError),
+    }
+  }
+
+  /// num_pages returns the number of pages in the file.
+  pub fn num_pages(&self) -> usize {
+    self.bitmap.len()
+  }
+
+  /// is_allocated returns true if the page at offset is in use.
+  pub fn is_allocated(&self, offset: &Offset) -> bool {
+    self.bitmap.get(offset.0 / PAGE_SIZE) == Some(&true)
+  }
+
+  /// free_pages returns the number of pages in the file that are not in use.
+  pub fn free_pages(&self) -> usize {
+    self.free
+  }
+
+  /// allocated_pages returns the offsets of all pages currently in use.
+  pub fn allocated_pages(&self) -> Vec<Offset> {
+    self
+      .bitmap
+      .iter()
+      .enumerate()
+      .filter(|(_, allocated)| **allocated)
+      .map(|(idx, _)| Offset(idx * PAGE_SIZE))
+      .collect()
+  }
+
+  /// reserve makes sure at least n pages are free, growing the file with zeroed pages
+  /// if needed, so that the next n page allocations cannot fail for lack of space.
+  pub fn reserve(&mut self, n: usize) -> Result<(), Error> {
+    for _ in self.free_pages()..n {
+      let offset = self.append()?;
+      if let Err(e) = self.write_page_at_offset(Page::new([0x00; PAGE_SIZE]), &offset) {
+        self.release(&offset);
+        return Err(e);
+      }
+      self.bitmap[offset.0 / PAGE_SIZE] = false;
+      self.free += 1;
+    }
+    Ok(())
+  }
+
+  /// release undoes the allocation of offset. A page appended to the file is cut off
+  /// again, dropping whatever part of it was written.
+  fn release(&mut self, offset: &Offset) {
+    let idx = offset.0 / PAGE_SIZE;
+    if idx + 1 == self.bitmap.len() && self.cursor == offset.0 + PAGE_SIZE {
+      self.bitmap.pop();
+      self.cursor = offset.0;
+      // Best effort, a partial trailing page is ignored when the file is reopened.
+      let _ = self.file.set_len(offset.0 as u64);
+    } else if let Some(allocated) = self.bitmap.get_mut(idx) {
+      if *allocated {
+        *allocated = false;
+        self.free += 1;
+      }
+    }
+  }
+
+  /// allocate reserves a page in the bitmap, preferring the free page
+  /// closest to hint, and appending a new page at the end of the file otherwise.
+  fn allocate(&mut self, hint: Option<&Offset>) -> Result<Offset, Error> {
+    if self.free == 0 {
+      return self.append();
+    }
+    let target = hint.map_or(0, |Offset(offset)| offset / PAGE_SIZE);
+    match self.closest_free(target) {
+      Some(idx) => {
+        self.bitmap[idx] = true;
+        self.free -= 1;
+        Ok(Offset(idx * PAGE_SIZE))
+      }
+      None => self.append(),
+    }
+  }
+
+  /// closest_free returns the index of the free page closest to target,
+  /// the lower one on a tie, searching outwards from target.
+  fn closest_free(&self, target: usize) -> Option<usize> {
+    let is_free = |idx: usize| self.bitmap.get(idx) == Some(&false);
+    let mut distance = 0;
+    while distance <= target || target + distance < self.bitmap.len() {
+      if distance <= target && is_free(target - distance) {
+        return Some(target - distance);
+      }
+      if is_free(target + distance) {
+        return Some(target + distance);
+      }
+      distance += 1;
+    }
+    None
+  }
+
+  /// append allocates a new page at the end of the file, respecting the size cap.
+  fn append(&mut self) -> Result<Offset, Error> {
+    if let Some(max_size) = self.max_size {
+      if self.cursor + PAGE_SIZE > max_size {
+        return Err(Error::CapacityExceeded);
+      }
+    }
     let res = Offset(self.cursor);
+    self.bitmap.push(true);
     self.cursor += PAGE_SIZE;
     Ok(res)
   }
+}
 
-  pub fn write_page_at_offset(&mut self, page: Page, offset: &Offset) -> Result<(), Error> {
-    self.file.seek(SeekFrom::Start(offset.0 as u64))?;
-    self.file.write_all(&page.get_data())?;
+/// sync_dir flushes the directory containing path so that the creation
+/// or renaming of path survives a crash.
+pub fn sync_dir(path: &Path) -> Result<(), Error> {
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new("."),
+  };
+  sync_dir_entries(dir)
+}
+
+#[cfg(unix)]
+fn sync_dir_entries(dir: &Path) -> Result<(), Error> {
+  File::open(dir)?.sync_all()?;
+  Ok(())
+}
+
+/// Directories cannot be opened as files on other platforms,
+/// where directory entries are persisted along with the file metadata.
+#[cfg(not(unix))]
+fn sync_dir_entries(_dir: &Path) -> Result<(), Error> {
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::error::Error;
+
+  #[test]
+  fn write_page_near_prefers_closest_free_page() -> Result<(), Error> {
+    use crate::node_type::Offset;
+    use crate::page::Page;
+    use crate::page_layout::PAGE_SIZE;
+    use crate::pager::Pager;
+    use std::path::Path;
+
+    let mut pager = Pager::new(Path::new("/tmp/pager_locality"))?;
+    for _ in 0..6 {
+      pager.write_page(Page::new([0x00; PAGE_SIZE]))?;
+    }
+    pager.free_page(&Offset(PAGE_SIZE))?;
+    pager.free_page(&Offset(PAGE_SIZE * 4))?;
+    assert_eq!(pager.free_pages(), 2);
+
+    let offset = pager.write_page_near(Page::new([0x00; PAGE_SIZE]), &Offset(PAGE_SIZE * 5))?;
+    assert_eq!(offset, Offset(PAGE_SIZE * 4));
+    let offset = pager.write_page_near(Page::new([0x00; PAGE_SIZE]), &Offset(PAGE_SIZE * 5))?;
+    assert_eq!(offset, Offset(PAGE_SIZE));
+    assert_eq!(pager.free_pages(), 0);
+    // No free pages left, the file grows.
+    let offset = pager.write_page_near(Page::new([0x00; PAGE_SIZE]), &Offset(0))?;
+    assert_eq!(offset, Offset(PAGE_SIZE * 6));
+    Ok(())
+  }
+
+  #[test]
+  fn reserve_grows_free_pages() -> Result<(), Error> {
+    use crate::node_type::Offset;
+    use crate::page::Page;
+    use crate::page_layout::PAGE_SIZE;
+    use crate::pager::Pager;
+    use std::path::Path;
+
+    let mut pager = Pager::new(Path::new("/tmp/pager_reserve"))?;
+    for _ in 0..3 {
+      pager.write_page(Page::new([0x00; PAGE_SIZE]))?;
+    }
+    pager.free_page(&Offset(PAGE_SIZE))?;
+    pager.reserve(3)?;
+    assert_eq!(pager.num_pages(), 5);
+    assert_eq!(pager.allocated_pages().len(), 2);
+    assert_eq!(pager.free_pages(), 3);
+    // Enough pages are already free.
+    pager.reserve(2)?;
+    assert_eq!(pager.num_pages(), 5);
+
+    // Reserved pages are handed out before the file grows.
+    for _ in 0..3 {
+      pager.write_page(Page::new([0x00; PAGE_SIZE]))?;
+    }
+    assert_eq!(pager.num_pages(), 5);
+    assert_eq!(pager.free_pages(), 0);
+    Ok(())
+  }
+
+  #[test]
+  fn secure_wipe_zeroes_freed_pages() -> Result<(), Error> {
+    use crate::page::Page;
+    use crate::page_layout::PAGE_SIZE;
+    use crate::pager::Pager;
+    use std::path::Path;
+
+    let mut pager = Pager::new(Path::new("/tmp/pager_secure_wipe"))?;
+    let kept = pager.write_page(Page::new([0xaa; PAGE_SIZE]))?;
+    let freed = pager.write_page(Page::new([0xaa; PAGE_SIZE]))?;
+    pager.free_page(&kept)?;
+    assert_eq!(pager.get_page(&kept)?.get_data(), [0xaa; PAGE_SIZE]);
+
+    pager.set_secure_wipe(true);
+    pager.free_page(&freed)?;
+    assert_eq!(pager.get_page(&freed)?.get_data(), [0x00; PAGE_SIZE]);
     Ok(())
   }
 }
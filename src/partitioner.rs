@@ -0,0 +1,143 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{Key, KeyValuePair};
+
+/// FNV-1a parameters, a stable hash keeps routing identical across builds
+/// (unlike std's DefaultHasher) so keys are always found in the file they were written to.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Partitioner routes keys across several BTree files using consistent hashing.
+/// Each tree owns virtual_nodes points on a hash ring, a key belongs to the tree
+/// owning the first point at or after the key's hash.
+pub struct Partitioner {
+    trees: Vec<BTree>,
+    virtual_nodes: usize,
+    /// Ring points sorted by hash, each pointing at an index in trees.
+    ring: Vec<(u64, usize)>,
+}
+
+impl Partitioner {
+    pub fn new(trees: Vec<BTree>, virtual_nodes: usize) -> Result<Partitioner, Error> {
+        if trees.is_empty() || virtual_nodes == 0 {
+            return Err(Error::UnexpectedError);
+        }
+        let mut partitioner = Partitioner {
+            trees: Vec::new(),
+            virtual_nodes,
+            ring: Vec::new(),
+        };
+        for tree in trees {
+            partitioner.add_tree(tree);
+        }
+        Ok(partitioner)
+    }
+
+    /// add_tree adds a tree to the ring and returns its index.
+    /// Keys already stored in other trees are not moved.
+    pub fn add_tree(&mut self, tree: BTree) -> usize {
+        let idx = self.trees.len();
+        self.trees.push(tree);
+        for vnode in 0..self.virtual_nodes {
+            self.ring
+                .push((hash(format!("{}-{}", idx, vnode).as_bytes()), idx));
+        }
+        self.ring.sort_unstable();
+        idx
+    }
+
+    /// route returns the index of the tree owning key.
+    pub fn route(&self, key: &str) -> usize {
+        let key_hash = hash(key.as_bytes());
+        let point = match self
+            .ring
+            .binary_search_by_key(&key_hash, |(point, _)| *point)
+        {
+            Ok(idx) => idx,
+            // Wrap around the ring.
+            Err(idx) if idx == self.ring.len() => 0,
+            Err(idx) => idx,
+        };
+        self.ring[point].1
+    }
+
+    /// tree returns the tree at a given index.
+    pub fn tree(&mut self, idx: usize) -> Option<&mut BTree> {
+        self.trees.get_mut(idx)
+    }
+
+    pub fn insert(&mut self, kv: KeyValuePair) -> Result<Option<KeyValuePair>, Error> {
+        let idx = self.route(&kv.key);
+        self.trees[idx].insert(kv)
+    }
+
+    pub fn search(&mut self, key: String) -> Result<KeyValuePair, Error> {
+        let idx = self.route(&key);
+        self.trees[idx].search(key)
+    }
+
+    pub fn delete(&mut self, key: Key) -> Result<(), Error> {
+        let idx = self.route(&key.0);
+        self.trees[idx].delete(key)
+    }
+}
+
+/// hash computes the 64 bit FNV-1a hash of bytes, followed by the murmur3 finalizer
+/// as FNV alone barely mixes the high bits of short keys differing in their last bytes.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn partitioner_routes_keys_across_trees() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::partitioner::Partitioner;
+        use std::path::Path;
+
+        let trees = vec![
+            BTreeBuilder::new()
+                .path(Path::new("/tmp/db_partition_0"))
+                .b_parameter(2)
+                .build()?,
+            BTreeBuilder::new()
+                .path(Path::new("/tmp/db_partition_1"))
+                .b_parameter(2)
+                .build()?,
+            BTreeBuilder::new()
+                .path(Path::new("/tmp/db_partition_2"))
+                .b_parameter(2)
+                .build()?,
+        ];
+        let mut partitioner = Partitioner::new(trees, 16)?;
+
+        let keys: Vec<String> = (0..30).map(|i| format!("key{}", i)).collect();
+        let mut used = [false; 3];
+        for key in keys.iter() {
+            let idx = partitioner.route(key);
+            assert_eq!(idx, partitioner.route(key));
+            used[idx] = true;
+            partitioner.insert(KeyValuePair::new(key.clone(), "v".to_string()))?;
+        }
+        assert_eq!(used, [true; 3]);
+
+        for key in keys.iter() {
+            assert_eq!(partitioner.search(key.clone())?.key, *key);
+            let idx = partitioner.route(key);
+            let tree = partitioner.tree(idx).ok_or(Error::UnexpectedError)?;
+            assert_eq!(tree.search(key.clone())?.key, *key);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{Key, KeyValuePair};
+use crate::page_layout::KEY_SIZE;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Key holding the next sequence number, sorting after every encoded id.
+const NEXT_ID_KEY: &str = "~next";
+
+/// Message is a value received from a Queue along with the id used to acknowledge it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Message {
+    pub id: u64,
+    pub value: String,
+}
+
+/// Queue is a persistent FIFO queue stored in a tree, keyed by monotonically increasing
+/// sequence numbers. Received messages stay in the tree, hidden from other receivers
+/// for the visibility timeout, until they are acknowledged. Messages that are not
+/// acknowledged in time, or were in flight when the process stopped, are delivered
+/// again, giving at-least-once delivery.
+pub struct Queue {
+    tree: BTree,
+    visibility_timeout: Duration,
+    next_id: u64,
+    /// Deadlines of the messages currently in flight.
+    in_flight: HashMap<u64, Instant>,
+}
+
+impl Queue {
+    /// new opens a queue over tree, resuming the sequence numbers stored in it.
+    /// The stored next id is written after each message, so a crash in between leaves
+    /// it behind; the id after the newest message is used when that is larger.
+    pub fn new(mut tree: BTree, visibility_timeout: Duration) -> Result<Queue, Error> {
+        let stored = match tree.search(NEXT_ID_KEY.to_string()) {
+            Ok(kv) => decode_id(&kv.value)?,
+            Err(Error::KeyNotFound) => {
+                tree.insert(KeyValuePair::new(NEXT_ID_KEY.to_string(), encode_id(0)))?;
+                0
+            }
+            Err(e) => return Err(e),
+        };
+        let after_newest = match tree.scan_rev(..NEXT_ID_KEY.to_string()).next() {
+            Some(kv) => decode_id(&kv?.key)? + 1,
+            None => 0,
+        };
+        let next_id = stored.max(after_newest);
+        Ok(Queue {
+            tree,
+            visibility_timeout,
+            next_id,
+            in_flight: HashMap::new(),
+        })
+    }
+
+    /// push appends value to the queue and returns its id. It fails with
+    /// Error::KeyAlreadyExists rather than overwrite a message under a reused id.
+    pub fn push(&mut self, value: String) -> Result<u64, Error> {
+        let id = self.next_id;
+        let key = encode_id(id);
+        if key.len() > KEY_SIZE {
+            return Err(Error::KeyOverflowError);
+        }
+        if !self.tree.insert_if_absent(KeyValuePair::new(key, value))? {
+            return Err(Error::KeyAlreadyExists);
+        }
+        self.next_id += 1;
+        let next_id = encode_id(self.next_id);
+        self.tree
+            .update_range(NEXT_ID_KEY.to_string()..=NEXT_ID_KEY.to_string(), |_, _| {
+                Some(next_id.clone())
+            })?;
+        Ok(id)
+    }
+
+    /// receive returns up to max of the oldest messages that are not in flight,
+    /// hiding them from further receives for the visibility timeout.
+    pub fn receive(&mut self, max: usize) -> Result<Vec<Message>, Error> {
+        let now = Instant::now();
+        self.in_flight.retain(|_, deadline| *deadline > now);
+        let mut messages = Vec::new();
+        for kv in self.tree.scan(..NEXT_ID_KEY.to_string()) {
+            if messages.len() == max {
+                break;
+            }
+            let kv = kv?;
+            let id = decode_id(&kv.key)?;
+            if !self.in_flight.contains_key(&id) {
+                messages.push(Message {
+                    id,
+                    value: kv.value,
+                });
+            }
+        }
+        for message in messages.iter() {
+            self.in_flight
+                .insert(message.id, now + self.visibility_timeout);
+        }
+        Ok(messages)
+    }
+
+    /// ack removes the messages with the given ids from the queue,
+    /// returning the number of messages removed. The leaves drained by acks are
+    /// merged like on any delete, so the file stays as large as the backlog.
+    pub fn ack(&mut self, ids: &[u64]) -> Result<usize, Error> {
+        let mut acked = 0;
+        for id in ids {
+            match self.tree.delete(Key(encode_id(*id))) {
+                Ok(()) => acked += 1,
+                Err(Error::KeyNotFound) => (),
+                Err(e) => return Err(e),
+            }
+            self.in_flight.remove(id);
+        }
+        Ok(acked)
+    }
+
+    pub fn into_inner(self) -> BTree {
+        self.tree
+    }
+}
+
+/// encode_id maps an id to a fixed width key so that keys sort in id order.
+fn encode_id(id: u64) -> String {
+    format!("{:010x}", id)
+}
+
+fn decode_id(key: &str) -> Result<u64, Error> {
+    u64::from_str_radix(key, 16).map_err(|_| Error::UnexpectedError)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn queue_redelivers_unacked_messages() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::queue::{Queue, NEXT_ID_KEY};
+        use std::path::Path;
+        use std::thread;
+        use std::time::Duration;
+
+        let tree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_queue"))
+            .b_parameter(2)
+            .build()?;
+        let mut queue = Queue::new(tree, Duration::from_millis(50))?;
+        for i in 0..5 {
+            assert_eq!(queue.push(format!("job{}", i))?, i);
+        }
+
+        let first = queue.receive(2)?;
+        assert_eq!(
+            first.iter().map(|m| m.value.as_str()).collect::<Vec<_>>(),
+            vec!["job0", "job1"]
+        );
+        let second = queue.receive(10)?;
+        assert_eq!(
+            second.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert_eq!(queue.receive(10)?, vec![]);
+
+        assert_eq!(queue.ack(&[0, 2, 3, 4])?, 4);
+        thread::sleep(Duration::from_millis(60));
+        // job1 was never acknowledged and becomes visible again.
+        let redelivered = queue.receive(10)?;
+        assert_eq!(
+            redelivered.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![1]
+        );
+
+        // Sequence numbers resume after reopening.
+        let mut queue = Queue::new(queue.into_inner(), Duration::from_millis(50))?;
+        assert_eq!(queue.push("job5".to_string())?, 5);
+
+        // A crash after storing a message but before storing the next id
+        // must not hand out the id of the message again.
+        let mut tree = queue.into_inner();
+        tree.update_range(NEXT_ID_KEY.to_string()..=NEXT_ID_KEY.to_string(), |_, _| {
+            Some("5".to_string())
+        })?;
+        let mut queue = Queue::new(tree, Duration::from_millis(50))?;
+        assert_eq!(queue.push("job6".to_string())?, 6);
+        let pending = queue.receive(10)?;
+        assert_eq!(
+            pending.iter().map(|m| m.value.as_str()).collect::<Vec<_>>(),
+            vec!["job1", "job5", "job6"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn queue_acks_reclaim_pages() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::queue::Queue;
+        use std::path::Path;
+        use std::time::Duration;
+
+        let tree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_queue_reclaim"))
+            .b_parameter(2)
+            .build()?;
+        let mut queue = Queue::new(tree, Duration::from_secs(60))?;
+        let mut pages = 0;
+        for round in 0..20 {
+            for i in 0..10 {
+                queue.push(format!("job{}", i))?;
+            }
+            let ids: Vec<u64> = queue.receive(10)?.iter().map(|m| m.id).collect();
+            assert_eq!(queue.ack(&ids)?, 10);
+            let mut tree = queue.into_inner();
+            if round == 0 {
+                pages = tree.health().pages;
+            }
+            // Drained leaves are merged and their pages reused.
+            assert_eq!(tree.health().pages, pages);
+            assert_eq!(tree.len(), 1);
+            queue = Queue::new(tree, Duration::from_secs(60))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use crate::error::Error;
+use crate::node_type::KeyValuePair;
+
+/// Quota limits the data stored under a key prefix.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Quota {
+  /// Maximum number of key value pairs.
+  Entries(usize),
+  /// Maximum number of key and value bytes.
+  Bytes(usize),
+}
+
+/// QuotaUsage is the data currently stored under a key prefix.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct QuotaUsage {
+  pub entries: usize,
+  pub bytes: usize,
+}
+
+/// Quotas tracks usage of every configured prefix and enforces its quota.
+#[derive(Clone, Debug, Default)]
+pub struct Quotas {
+  prefixes: Vec<(String, Quota, QuotaUsage)>,
+}
+
+impl Quotas {
+  pub fn add(&mut self, prefix: String, quota: Quota) {
+    self.prefixes.push((prefix, quota, QuotaUsage::default()));
+  }
+
+  /// is_empty returns true if no quota is configured.
+  pub fn is_empty(&self) -> bool {
+    self.prefixes.is_empty()
+  }
+
+  /// reset zeroes the usage of every prefix, keeping the configured quotas.
+  pub fn reset(&mut self) {
+    for (_, _, usage) in self.prefixes.iter_mut() {
+      *usage = QuotaUsage::default();
+    }
+  }
+
+  /// check returns Error::QuotaExceeded if storing kv would exceed
+  /// the quota of any prefix matching its key.
+  pub fn check(&self, kv: &KeyValuePair) -> Result<(), Error> {
+    for (prefix, quota, usage) in self.matching(&kv.key) {
+      let exceeded = match quota {
+        Quota::Entries(limit) => usage.entries + 1 > *limit,
+        Quota::Bytes(limit) => usage.bytes + kv.key.len() + kv.value.len() > *limit,
+      };
+      if exceeded {
+        return Err(Error::QuotaExceeded {
+          prefix: prefix.clone(),
+        });
+      }
+    }
+    Ok(())
+  }
+
+  /// check_replace returns Error::QuotaExceeded if replacing old with kv, which share
+  /// the same key, would exceed the quota of any prefix matching the key.
+  pub fn check_replace(&self, old: &KeyValuePair, kv: &KeyValuePair) -> Result<(), Error> {
+    for (prefix, quota, usage) in self.matching(&kv.key) {
+      if let Quota::Bytes(limit) = quota {
+        if usage.bytes - old.value.len() + kv.value.len() > *limit {
+          return Err(Error::QuotaExceeded {
+            prefix: prefix.clone(),
+          });
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// record_insert accounts for a pair stored in the tree.
+  pub fn record_insert(&mut self, kv: &KeyValuePair) {
+    for (prefix, _, usage) in self.prefixes.iter_mut() {
+      if kv.key.starts_with(prefix.as_str()) {
+        usage.entries += 1;
+        usage.bytes += kv.key.len() + kv.value.len();
+      }
+    }
+  }
+
+  /// record_delete releases the usage of a pair removed from the tree.
+  pub fn record_delete(&mut self, kv: &KeyValuePair) {
+    for (prefix, _, usage) in self.prefixes.iter_mut() {
+      if kv.key.starts_with(prefix.as_str()) {
+        usage.entries -= 1;
+        usage.bytes -= kv.key.len() + kv.value.len();
+      }
+    }
+  }
+
+  /// usage returns the usage of a configured prefix.
+  pub fn usage(&self, prefix: &str) -> Option<QuotaUsage> {
+    self
+      .prefixes
+      .iter()
+      .find(|(configured, _, _)| configured == prefix)
+      .map(|(_, _, usage)| *usage)
+  }
+
+  fn matching<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a (String, Quota, QuotaUsage)> {
+    self
+      .prefixes
+      .iter()
+      .filter(move |(prefix, _, _)| key.starts_with(prefix.as_str()))
+  }
+}
@@ -0,0 +1,78 @@
+//! Low level access to the on-disk format, for tools such as viewers, converters and
+//! recovery utilities that work on tree files without going through a BTree.
+//!
+//! A tree file is a sequence of PAGE_SIZE byte pages. The page at HEADER_OFFSET holds
+//! the Header, every other page reachable from Header::root_offset holds a Node.
+//! The parsers below never panic on malformed input, they return an error instead.
+
+use crate::error::Error;
+use std::convert::TryFrom;
+
+pub use crate::header::Header;
+pub use crate::node::Node;
+pub use crate::node_type::{Key, KeyValuePair, NodeType, Offset};
+pub use crate::page::Page;
+pub use crate::page_layout::{HEADER_OFFSET, KEY_SIZE, MAGIC, PAGE_SIZE, PTR_SIZE, VALUE_SIZE};
+
+/// page_from_bytes copies a page out of bytes, which must be exactly PAGE_SIZE long.
+pub fn page_from_bytes(bytes: &[u8]) -> Result<Page, Error> {
+    let data = <[u8; PAGE_SIZE]>::try_from(bytes)
+        .map_err(|_| Error::TryFromSliceError("a page must be exactly PAGE_SIZE bytes"))?;
+    Ok(Page::new(data))
+}
+
+/// decode_node parses the node stored in the page bytes.
+pub fn decode_node(bytes: &[u8]) -> Result<Node, Error> {
+    Node::try_from(page_from_bytes(bytes)?)
+}
+
+/// encode_node serializes node into the bytes of a page.
+pub fn encode_node(node: &Node) -> Result<[u8; PAGE_SIZE], Error> {
+    Ok(Page::try_from(node)?.get_data())
+}
+
+/// decode_header parses the header stored in the page bytes,
+/// failing with Error::InvalidHeader if they do not start with MAGIC.
+pub fn decode_header(bytes: &[u8]) -> Result<Header, Error> {
+    Header::try_from(page_from_bytes(bytes)?)
+}
+
+/// encode_header serializes header into the bytes of a page.
+pub fn encode_header(header: &Header) -> Result<[u8; PAGE_SIZE], Error> {
+    Ok(Page::try_from(header)?.get_data())
+}
+
+/// page_offset returns the offset of the idx-th page of a file.
+pub fn page_offset(idx: usize) -> Offset {
+    Offset(idx * PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn raw_round_trips_and_rejects_malformed_pages() -> Result<(), Error> {
+        use crate::raw::{self, KeyValuePair, Node, NodeType, PAGE_SIZE};
+
+        let node = Node::new(
+            NodeType::Leaf(vec![KeyValuePair::new("a".to_string(), "1".to_string())]),
+            true,
+            None,
+        );
+        let bytes = raw::encode_node(&node)?;
+        assert_eq!(raw::decode_node(&bytes)?.node_type, node.node_type);
+
+        assert!(raw::decode_node(&bytes[..PAGE_SIZE - 1]).is_err());
+        assert!(matches!(
+            raw::decode_header(&bytes),
+            Err(Error::InvalidHeader)
+        ));
+        // A pair count larger than the page can hold.
+        let mut corrupted = bytes;
+        corrupted[2..].iter_mut().take(16).for_each(|b| *b = 0xff);
+        assert!(raw::decode_node(&corrupted).is_err());
+        assert_eq!(raw::page_offset(3).0, 3 * PAGE_SIZE);
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::KeyValuePair;
+use crate::page_layout::VALUE_SIZE;
+use crate::scan::Scan;
+use std::iter::Peekable;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Key holding the next sequence number, sorting after every record key.
+const NEXT_SEQ_KEY: &str = "~next";
+/// Records are split into at most this many chunks of up to VALUE_SIZE bytes.
+const MAX_CHUNKS: usize = 0x100;
+
+/// RecordLog stores string records longer than a single value in a tree, in the order
+/// they were appended. Each record spans as many pairs as it needs, keyed by its
+/// sequence number and chunk index; the next sequence number is kept in the tree
+/// so that sequence numbers are never reused.
+pub struct RecordLog {
+    tree: BTree,
+    next_seq: u64,
+}
+
+impl RecordLog {
+    /// new opens a log stored in tree, resuming the sequence numbers stored in it.
+    pub fn new(mut tree: BTree) -> Result<RecordLog, Error> {
+        let next_seq = match tree.search(NEXT_SEQ_KEY.to_string()) {
+            Ok(kv) => u64::from_str_radix(&kv.value, 16).map_err(|_| Error::UnexpectedError)?,
+            Err(Error::KeyNotFound) => {
+                tree.insert(KeyValuePair::new(NEXT_SEQ_KEY.to_string(), "0".to_string()))?;
+                0
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(RecordLog { tree, next_seq })
+    }
+
+    /// append stores record and returns its sequence number.
+    pub fn append(&mut self, record: &str) -> Result<u64, Error> {
+        let seq = self.next_seq;
+        let chunks = chunks(record);
+        if chunks.len() > MAX_CHUNKS || seq > u32::MAX as u64 {
+            return Err(Error::ValueOverflowError);
+        }
+        for (idx, chunk) in chunks.into_iter().enumerate() {
+            self.tree
+                .insert(KeyValuePair::new(chunk_key(seq, idx), chunk.to_string()))?;
+        }
+        self.next_seq += 1;
+        let next_seq = format!("{:x}", self.next_seq);
+        self.tree.update_range(
+            NEXT_SEQ_KEY.to_string()..=NEXT_SEQ_KEY.to_string(),
+            |_, _| Some(next_seq.clone()),
+        )?;
+        Ok(seq)
+    }
+
+    /// records returns the sequence number and contents of every record, oldest first.
+    pub fn records(&mut self) -> Result<Vec<(u64, String)>, Error> {
+        self.iter().collect()
+    }
+
+    /// iter returns the records one at a time, oldest first, so that readers looking for
+    /// a few records can stop early instead of decoding the whole log.
+    pub fn iter(&mut self) -> Records<'_> {
+        Records {
+            scan: self.tree.scan(..NEXT_SEQ_KEY.to_string()).peekable(),
+            reverse: false,
+        }
+    }
+
+    /// iter_rev returns the records one at a time, newest first.
+    pub fn iter_rev(&mut self) -> Records<'_> {
+        Records {
+            scan: self.tree.scan_rev(..NEXT_SEQ_KEY.to_string()).peekable(),
+            reverse: true,
+        }
+    }
+
+    /// remove deletes the record with sequence number seq.
+    pub fn remove(&mut self, seq: u64) -> Result<(), Error> {
+        let removed = self.tree.update_range(
+            chunk_key(seq, 0)..=chunk_key(seq, MAX_CHUNKS - 1),
+            |_, _| None,
+        )?;
+        if removed == 0 {
+            return Err(Error::KeyNotFound);
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> BTree {
+        self.tree
+    }
+}
+
+/// Records yields the sequence number and contents of the records of a RecordLog,
+/// joining the chunks of each record as it reaches them.
+pub struct Records<'a> {
+    scan: Peekable<Scan<'a>>,
+    /// Whether the chunks come last first.
+    reverse: bool,
+}
+
+impl<'a> Records<'a> {
+    fn next_record(&mut self) -> Result<Option<(u64, String)>, Error> {
+        let first = match self.scan.next().transpose()? {
+            Some(kv) => kv,
+            None => return Ok(None),
+        };
+        let seq = chunk_seq(&first.key)?;
+        let mut chunks = vec![first.value];
+        while let Some(Ok(kv)) = self.scan.peek() {
+            if chunk_seq(&kv.key)? != seq {
+                break;
+            }
+            if let Some(Ok(kv)) = self.scan.next() {
+                chunks.push(kv.value);
+            }
+        }
+        if self.reverse {
+            chunks.reverse();
+        }
+        Ok(Some((seq, chunks.concat())))
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<(u64, String), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// now_millis returns the current time in milliseconds since the Unix epoch.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn chunk_key(seq: u64, idx: usize) -> String {
+    format!("{:08x}{:02x}", seq, idx)
+}
+
+/// chunk_seq returns the sequence number of the record a chunk key belongs to.
+fn chunk_seq(key: &str) -> Result<u64, Error> {
+    key.get(..8)
+        .and_then(|seq| u64::from_str_radix(seq, 16).ok())
+        .ok_or(Error::UnexpectedError)
+}
+
+/// chunks splits s at char boundaries into pieces of at most VALUE_SIZE bytes.
+fn chunks(s: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let mut end = VALUE_SIZE.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
@@ -0,0 +1,734 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node::Node;
+use crate::node_type::{self, Key, KeyValuePair, NodeType, Offset};
+use std::cmp;
+use std::ops::{Bound, RangeBounds};
+use std::vec;
+
+/// KeyRange is a range of keys as returned by BTree::split_ranges.
+pub type KeyRange = (Bound<String>, Bound<String>);
+
+/// intersect returns the range of keys contained in both a and b.
+pub(crate) fn intersect(a: &KeyRange, b: &KeyRange) -> KeyRange {
+    let start = match (&a.0, &b.0) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound.clone(),
+        (Bound::Included(x), Bound::Included(y)) => Bound::Included(cmp::max(x, y).clone()),
+        (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(cmp::max(x, y).clone()),
+        (Bound::Included(inc), Bound::Excluded(exc))
+        | (Bound::Excluded(exc), Bound::Included(inc)) => {
+            if inc > exc {
+                Bound::Included(inc.clone())
+            } else {
+                Bound::Excluded(exc.clone())
+            }
+        }
+    };
+    let end = match (&a.1, &b.1) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound.clone(),
+        (Bound::Included(x), Bound::Included(y)) => Bound::Included(cmp::min(x, y).clone()),
+        (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(cmp::min(x, y).clone()),
+        (Bound::Included(inc), Bound::Excluded(exc))
+        | (Bound::Excluded(exc), Bound::Included(inc)) => {
+            if inc < exc {
+                Bound::Included(inc.clone())
+            } else {
+                Bound::Excluded(exc.clone())
+            }
+        }
+    };
+    (start, end)
+}
+
+/// prefix_range returns the range of keys starting with prefix: from the prefix itself
+/// up to, but excluding, the smallest string greater than every key with the prefix.
+pub fn prefix_range(prefix: &str) -> KeyRange {
+    let mut end: Vec<char> = prefix.chars().collect();
+    while let Some(last) = end.pop() {
+        // Skip the surrogate range, which holds no chars.
+        let next = match last as u32 + 1 {
+            0xD800 => Some('\u{E000}'),
+            next => char::from_u32(next),
+        };
+        if let Some(next) = next {
+            end.push(next);
+            return (
+                Bound::Included(prefix.to_string()),
+                Bound::Excluded(end.into_iter().collect()),
+            );
+        }
+    }
+    (Bound::Included(prefix.to_string()), Bound::Unbounded)
+}
+
+/// after_start returns true if key is not before the start bound of a range.
+pub(crate) fn after_start(start: &Bound<String>, key: &str) -> bool {
+    match start {
+        Bound::Included(start) => key >= start.as_str(),
+        Bound::Excluded(start) => key > start.as_str(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// before_end returns true if key is not past the end bound of a range.
+pub(crate) fn before_end(end: &Bound<String>, key: &str) -> bool {
+    match end {
+        Bound::Included(end) => key <= end.as_str(),
+        Bound::Excluded(end) => key < end.as_str(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Overlap is how the keys of a subtree relate to a range.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Overlap {
+    /// No key of the subtree can be within the range.
+    Disjoint,
+    /// Some keys of the subtree may be within the range.
+    Partial,
+    /// Every key of the subtree is within the range.
+    Covered,
+}
+
+/// overlap classifies a subtree whose keys lie in (lower, upper] against range,
+/// a missing bound meaning the subtree is unbounded on that side.
+pub(crate) fn overlap(range: &KeyRange, lower: Option<&Key>, upper: Option<&Key>) -> Overlap {
+    let before = upper.is_some_and(|upper| !after_start(&range.0, &upper.0));
+    let after = lower.is_some_and(|lower| !before_end(&range.1, &lower.0));
+    if before || after {
+        return Overlap::Disjoint;
+    }
+    let from_start = lower.map_or(range.0 == Bound::Unbounded, |lower| {
+        after_start(&range.0, &lower.0)
+    });
+    let to_end = upper.map_or(range.1 == Bound::Unbounded, |upper| {
+        before_end(&range.1, &upper.0)
+    });
+    if from_start && to_end {
+        Overlap::Covered
+    } else {
+        Overlap::Partial
+    }
+}
+
+/// Leaves walks the leaves of a tree from left to right, starting with the leaf that
+/// may hold the start bound, or from right to left starting with the leaf that may
+/// hold the end bound. Only the internal nodes on the path to the current leaf
+/// are kept in memory.
+pub(crate) struct Leaves<'a> {
+    tree: &'a mut BTree,
+    /// Bound of the first leaf visited.
+    seek: Bound<String>,
+    reverse: bool,
+    /// Internal nodes on the path to the current leaf, along with the index of the
+    /// next child to visit, or one past it when walking in reverse.
+    stack: Vec<(Vec<Offset>, usize)>,
+    started: bool,
+}
+
+impl<'a> Leaves<'a> {
+    pub(crate) fn new(tree: &'a mut BTree, start: Bound<String>) -> Leaves<'a> {
+        Leaves {
+            tree,
+            seek: start,
+            reverse: false,
+            stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// new_rev walks the leaves from right to left, starting at the end bound.
+    pub(crate) fn new_rev(tree: &'a mut BTree, end: Bound<String>) -> Leaves<'a> {
+        Leaves {
+            reverse: true,
+            ..Leaves::new(tree, end)
+        }
+    }
+
+    /// tree gives access to the walked tree in between leaves. Leaves may be rewritten
+    /// in place as long as the internal nodes are left untouched.
+    pub(crate) fn tree(&mut self) -> &mut BTree {
+        self.tree
+    }
+
+    /// descend walks down from offset to a leaf, either towards the seek bound
+    /// or along the leftmost (rightmost in reverse) children.
+    fn descend(&mut self, mut offset: Offset, seek: bool) -> Result<(Offset, Node), Error> {
+        loop {
+            let node = self.tree.read_node(&offset)?;
+            match node.node_type {
+                NodeType::Internal(children, keys) => {
+                    // A key equal to a separator lives in the child left of it,
+                    // which is the last child that may hold it in either direction.
+                    let idx = match &self.seek {
+                        Bound::Included(key) | Bound::Excluded(key) if seek => {
+                            node_type::child_index(&keys, key)
+                        }
+                        _ if self.reverse => children.len().saturating_sub(1),
+                        _ => 0,
+                    };
+                    offset = *children.get(idx).ok_or(Error::UnexpectedError)?;
+                    let next = if self.reverse { idx } else { idx + 1 };
+                    self.stack.push((children, next));
+                }
+                NodeType::Leaf(_) => return Ok((offset, node)),
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+        }
+    }
+
+    /// next_leaf returns the offset and contents of the next leaf, or None past the last leaf.
+    pub(crate) fn next_leaf(&mut self) -> Result<Option<(Offset, Node)>, Error> {
+        if !self.started {
+            self.started = true;
+            self.tree.ensure_current()?;
+            self.tree.revalidate()?;
+            let root_offset = self.tree.root_offset();
+            return self.descend(root_offset, true).map(Some);
+        }
+        loop {
+            let next = match self.stack.last_mut() {
+                None => return Ok(None),
+                Some((children, next)) if self.reverse => match next.checked_sub(1) {
+                    Some(prev) => {
+                        *next = prev;
+                        children.get(prev).copied()
+                    }
+                    None => None,
+                },
+                Some((children, next)) => {
+                    let child = children.get(*next).copied();
+                    *next += 1;
+                    child
+                }
+            };
+            match next {
+                Some(offset) => return self.descend(offset, false).map(Some),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Scan iterates over the pairs of a tree within a key range in ascending key order,
+/// or descending order when created with Scan::new_rev. Leaves are read one at a time,
+/// so memory is bounded by the height of the tree and the size of a single leaf
+/// no matter how large the range is.
+pub struct Scan<'a> {
+    leaves: Leaves<'a>,
+    start: Bound<String>,
+    end: Bound<String>,
+    reverse: bool,
+    /// Remaining pairs of the current leaf.
+    pairs: vec::IntoIter<KeyValuePair>,
+    done: bool,
+}
+
+impl<'a> Scan<'a> {
+    pub fn new<R: RangeBounds<String>>(tree: &'a mut BTree, range: R) -> Scan<'a> {
+        let start = range.start_bound().cloned();
+        Scan {
+            leaves: Leaves::new(tree, start.clone()),
+            start,
+            end: range.end_bound().cloned(),
+            reverse: false,
+            pairs: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    /// new_rev scans the range from the largest key down.
+    pub fn new_rev<R: RangeBounds<String>>(tree: &'a mut BTree, range: R) -> Scan<'a> {
+        let end = range.end_bound().cloned();
+        Scan {
+            leaves: Leaves::new_rev(tree, end.clone()),
+            start: range.start_bound().cloned(),
+            end,
+            reverse: true,
+            pairs: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    fn next_pair(&mut self) -> Result<Option<KeyValuePair>, Error> {
+        loop {
+            let next = if self.reverse {
+                self.pairs.next_back()
+            } else {
+                self.pairs.next()
+            };
+            match next {
+                Some(kv) => {
+                    // Whether the scan went past the range, and whether
+                    // it has reached the range yet.
+                    let (past, reached) = if self.reverse {
+                        (
+                            !after_start(&self.start, &kv.key),
+                            before_end(&self.end, &kv.key),
+                        )
+                    } else {
+                        (
+                            !before_end(&self.end, &kv.key),
+                            after_start(&self.start, &kv.key),
+                        )
+                    };
+                    if past {
+                        return Ok(None);
+                    }
+                    if reached && !self.leaves.tree().is_expired(&kv.key)? {
+                        return Ok(Some(kv));
+                    }
+                }
+                None => match self.leaves.next_leaf()? {
+                    Some((_, node)) => match node.node_type {
+                        NodeType::Leaf(pairs) => self.pairs = pairs.into_iter(),
+                        _ => return Err(Error::UnexpectedError),
+                    },
+                    None => return Ok(None),
+                },
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Scan<'a> {
+    type Item = Result<KeyValuePair, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_pair() {
+            Ok(Some(kv)) => Some(Ok(kv)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// ScanChunks batches the pairs of a Scan into vectors of up to chunk_size pairs.
+pub struct ScanChunks<'a> {
+    scan: Scan<'a>,
+    chunk_size: usize,
+}
+
+impl<'a> ScanChunks<'a> {
+    pub fn new(scan: Scan<'a>, chunk_size: usize) -> ScanChunks<'a> {
+        ScanChunks {
+            scan,
+            chunk_size: chunk_size.max(1),
+        }
+    }
+}
+
+impl<'a> Iterator for ScanChunks<'a> {
+    type Item = Result<Vec<KeyValuePair>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        for res in self.scan.by_ref().take(self.chunk_size) {
+            match res {
+                Ok(kv) => chunk.push(kv),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        if chunk.is_empty() {
+            return None;
+        }
+        Some(Ok(chunk))
+    }
+}
+
+/// Keys yields the keys of a Scan, moving each key out of its pair instead of cloning it.
+/// Leaves are still decoded whole, values included; with values capped at VALUE_SIZE
+/// bytes, decoding them costs about as much as skipping them.
+pub struct Keys<'a> {
+    scan: Scan<'a>,
+}
+
+impl<'a> Keys<'a> {
+    pub fn new(scan: Scan<'a>) -> Keys<'a> {
+        Keys { scan }
+    }
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scan.next().map(|res| res.map(|kv| kv.key))
+    }
+}
+
+/// Values yields the values of a Scan in key order,
+/// moving each value out of its pair instead of cloning it.
+/// Like Keys, it decodes the whole of every leaf.
+pub struct Values<'a> {
+    scan: Scan<'a>,
+}
+
+impl<'a> Values<'a> {
+    pub fn new(scan: Scan<'a>) -> Values<'a> {
+        Values { scan }
+    }
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scan.next().map(|res| res.map(|kv| kv.value))
+    }
+}
+
+/// Join merge-joins two scans by key, yielding the pairs of both sides for every key
+/// present in both. Both scans are consumed in a single pass.
+pub struct Join<'a> {
+    left: Scan<'a>,
+    right: Scan<'a>,
+    next_left: Option<KeyValuePair>,
+    next_right: Option<KeyValuePair>,
+}
+
+impl<'a> Join<'a> {
+    pub fn new(left: Scan<'a>, right: Scan<'a>) -> Join<'a> {
+        Join {
+            left,
+            right,
+            next_left: None,
+            next_right: None,
+        }
+    }
+
+    fn next_match(&mut self) -> Result<Option<(KeyValuePair, KeyValuePair)>, Error> {
+        loop {
+            if self.next_left.is_none() {
+                self.next_left = self.left.next().transpose()?;
+            }
+            if self.next_right.is_none() {
+                self.next_right = self.right.next().transpose()?;
+            }
+            let ordering = match (&self.next_left, &self.next_right) {
+                (Some(left), Some(right)) => left.key.cmp(&right.key),
+                _ => return Ok(None),
+            };
+            match ordering {
+                cmp::Ordering::Less => self.next_left = None,
+                cmp::Ordering::Greater => self.next_right = None,
+                cmp::Ordering::Equal => {
+                    return Ok(self.next_left.take().zip(self.next_right.take()));
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Join<'a> {
+    type Item = Result<(KeyValuePair, KeyValuePair), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_match().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn scan_respects_bounds() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::ops::Bound;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_scan"))
+            .b_parameter(2)
+            .build()?;
+        for i in (0..30).rev() {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let keys = |btree: &mut crate::btree::BTree, range: (Bound<String>, Bound<String>)| {
+            btree
+                .scan(range)
+                .map(|kv| kv.map(|kv| kv.key))
+                .collect::<Result<Vec<_>, Error>>()
+        };
+        let expected = |ids: std::ops::Range<usize>| -> Vec<String> {
+            ids.map(|i| format!("k{:02}", i)).collect()
+        };
+
+        assert_eq!(
+            keys(&mut btree, (Bound::Unbounded, Bound::Unbounded))?,
+            expected(0..30)
+        );
+        assert_eq!(
+            keys(
+                &mut btree,
+                (
+                    Bound::Included("k05".to_string()),
+                    Bound::Excluded("k12".to_string())
+                )
+            )?,
+            expected(5..12)
+        );
+        assert_eq!(
+            keys(
+                &mut btree,
+                (
+                    Bound::Excluded("k05".to_string()),
+                    Bound::Included("k12".to_string())
+                )
+            )?,
+            expected(6..13)
+        );
+        // Bounds need not be stored keys.
+        assert_eq!(
+            keys(
+                &mut btree,
+                (Bound::Included("k2".to_string()), Bound::Unbounded)
+            )?,
+            expected(20..30)
+        );
+        assert_eq!(
+            keys(
+                &mut btree,
+                (Bound::Included("x".to_string()), Bound::Unbounded)
+            )?,
+            expected(0..0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn scan_rev_walks_keys_backwards() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_scan_rev"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..30 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+        let all = btree
+            .scan_rev(..)
+            .map(|kv| kv.map(|kv| kv.key))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let expected: Vec<String> = (0..30).rev().map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(all, expected);
+
+        let some = btree
+            .scan_rev("k05".to_string()..="k12".to_string())
+            .map(|kv| kv.map(|kv| kv.key))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let expected: Vec<String> = (5..13).rev().map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(some, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn keys_and_values_follow_key_order() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_keys_values"))
+            .b_parameter(2)
+            .build()?;
+        for (key, value) in [("c", "3"), ("a", "1"), ("d", "4"), ("b", "2")].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), value.to_string()))?;
+        }
+        let keys = btree.keys().collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(keys, vec!["a", "b", "c", "d"]);
+        let values = btree.values().collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(values, vec!["1", "2", "3", "4"]);
+        Ok(())
+    }
+
+    #[test]
+    fn scan_prefix_stops_past_prefix() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::scan::prefix_range;
+        use std::ops::Bound;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_scan_prefix"))
+            .b_parameter(2)
+            .build()?;
+        for key in ["a", "u", "u/1", "u/2", "u/3", "u0", "ua", "v"].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), "v".to_string()))?;
+        }
+        let keys = btree
+            .scan_prefix("u/")
+            .map(|kv| kv.map(|kv| kv.key))
+            .collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(keys, vec!["u/1", "u/2", "u/3"]);
+        assert_eq!(btree.scan_prefix("u").count(), 6);
+        assert_eq!(btree.scan_prefix("").count(), 8);
+        assert_eq!(btree.scan_prefix("w").count(), 0);
+
+        assert_eq!(
+            prefix_range("a\u{10FFFF}"),
+            (
+                Bound::Included("a\u{10FFFF}".to_string()),
+                Bound::Excluded("b".to_string())
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn scan_chunks_batches_range() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_scan_chunks"))
+            .b_parameter(2)
+            .build()?;
+        // Insert out of order to exercise splits on both sides.
+        for key in ["e", "a", "i", "c", "g", "b", "j", "d", "h", "f"].iter() {
+            btree.insert(KeyValuePair::new(key.to_string(), key.to_uppercase()))?;
+        }
+
+        let chunks = btree
+            .scan_chunks("b".to_string().."h".to_string(), 4)
+            .collect::<Result<Vec<_>, Error>>()?;
+        let keys: Vec<Vec<&str>> = chunks
+            .iter()
+            .map(|chunk| chunk.iter().map(|kv| kv.key.as_str()).collect())
+            .collect();
+        assert_eq!(keys, vec![vec!["b", "c", "d", "e"], vec!["f", "g"]]);
+        assert_eq!(chunks[0][0].value, "B");
+
+        let all: usize = btree
+            .scan_chunks(.., 3)
+            .map(|chunk| chunk.map(|chunk| chunk.len()))
+            .sum::<Result<usize, Error>>()?;
+        assert_eq!(all, 10);
+
+        let empty = btree.scan_chunks("x".to_string().., 3).count();
+        assert_eq!(empty, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn split_ranges_cover_keyspace() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_split_ranges"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..40 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+
+        let ranges = btree.split_ranges(4)?;
+        assert_eq!(ranges.len(), 4);
+        let mut keys = Vec::new();
+        for range in ranges {
+            let mut part = Vec::new();
+            for chunk in btree.scan_chunks(range, 16) {
+                part.extend(chunk?.into_iter().map(|kv| kv.key));
+            }
+            assert!(!part.is_empty());
+            keys.extend(part);
+        }
+        let expected: Vec<String> = (0..40).map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(keys, expected);
+
+        assert_eq!(btree.split_ranges(1)?.len(), 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_scan_visits_every_pair_in_range() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+        use std::sync::Mutex;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_par_scan"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..50 {
+            btree.insert(KeyValuePair::new(format!("k{:02}", i), i.to_string()))?;
+        }
+
+        let seen = Mutex::new(Vec::new());
+        btree.par_scan("k10".to_string()..="k39".to_string(), |kv| {
+            seen.lock().unwrap().push(kv.key);
+        })?;
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        let expected: Vec<String> = (10..40).map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(seen, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn join_matches_keys_of_both_trees() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::path::Path;
+
+        let mut users = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_join_left"))
+            .b_parameter(2)
+            .build()?;
+        let mut emails = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_join_right"))
+            .b_parameter(2)
+            .build()?;
+        for i in 0..20 {
+            users.insert(KeyValuePair::new(
+                format!("u{:02}", i),
+                format!("name{}", i),
+            ))?;
+        }
+        for i in (0..30).step_by(3) {
+            emails.insert(KeyValuePair::new(
+                format!("u{:02}", i),
+                format!("mail{}", i),
+            ))?;
+        }
+
+        let joined = users
+            .join(.., &mut emails, "u03".to_string()..)
+            .map(|res| res.map(|(left, right)| (left.key, left.value, right.value)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let expected: Vec<(String, String, String)> = (3..20)
+            .step_by(3)
+            .map(|i| {
+                (
+                    format!("u{:02}", i),
+                    format!("name{}", i),
+                    format!("mail{}", i),
+                )
+            })
+            .collect();
+        assert_eq!(joined, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{Key, KeyValuePair};
+use crate::scan::{self, KeyRange, Scan};
+use std::ops::{Bound, RangeBounds};
+
+/// Scoped is a handle over a BTree that confines all operations to keys starting
+/// with a prefix; the prefix is prepended to keys going into the tree and stripped
+/// from keys coming out of it, giving callers a logical namespace.
+pub struct Scoped<'a> {
+    tree: &'a mut BTree,
+    prefix: String,
+}
+
+impl<'a> Scoped<'a> {
+    pub fn new(tree: &'a mut BTree, prefix: &str) -> Scoped<'a> {
+        Scoped {
+            tree,
+            prefix: prefix.to_string(),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn insert(&mut self, kv: KeyValuePair) -> Result<Option<KeyValuePair>, Error> {
+        match self
+            .tree
+            .insert(KeyValuePair::new(self.scope(&kv.key), kv.value))?
+        {
+            Some(old) => self.unscope(old).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn search(&mut self, key: String) -> Result<KeyValuePair, Error> {
+        let kv = self.tree.search(self.scope(&key))?;
+        self.unscope(kv)
+    }
+
+    /// get returns the pair stored under key, or None if there is none.
+    pub fn get(&mut self, key: &str) -> Result<Option<KeyValuePair>, Error> {
+        match self.tree.get(&self.scope(key))? {
+            Some(kv) => self.unscope(kv).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn delete(&mut self, key: Key) -> Result<(), Error> {
+        self.tree.delete(Key(self.scope(&key.0)))
+    }
+
+    /// scan returns the pairs of the namespace within range in ascending key order,
+    /// never reaching past the keys starting with the prefix.
+    pub fn scan<R: RangeBounds<String>>(&mut self, range: R) -> ScopedScan<'_> {
+        let scope = |bound: Bound<&String>| match bound {
+            Bound::Included(key) => Bound::Included(self.scope(key)),
+            Bound::Excluded(key) => Bound::Excluded(self.scope(key)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let range: KeyRange = (scope(range.start_bound()), scope(range.end_bound()));
+        let range = scan::intersect(&scan::prefix_range(&self.prefix), &range);
+        ScopedScan {
+            prefix: self.prefix.clone(),
+            scan: self.tree.scan(range),
+        }
+    }
+
+    /// scope maps a key of the namespace to its key in the tree.
+    fn scope(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// unscope maps a pair read from the tree back into the namespace.
+    fn unscope(&self, kv: KeyValuePair) -> Result<KeyValuePair, Error> {
+        unscope(&self.prefix, kv)
+    }
+}
+
+fn unscope(prefix: &str, kv: KeyValuePair) -> Result<KeyValuePair, Error> {
+    match kv.key.strip_prefix(prefix) {
+        Some(key) => Ok(KeyValuePair::new(key.to_string(), kv.value)),
+        None => Err(Error::UnexpectedError),
+    }
+}
+
+/// ScopedScan yields the pairs of a Scoped::scan with the prefix stripped from their keys.
+pub struct ScopedScan<'a> {
+    prefix: String,
+    scan: Scan<'a>,
+}
+
+impl<'a> Iterator for ScopedScan<'a> {
+    type Item = Result<KeyValuePair, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scan
+            .next()
+            .map(|res| res.and_then(|kv| unscope(&self.prefix, kv)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn scoped_handles_do_not_collide() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::scoped::ScopedScan;
+        use std::path::Path;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_scoped"))
+            .b_parameter(2)
+            .build()?;
+        btree
+            .scoped("t1/")
+            .insert(KeyValuePair::new("user".to_string(), "alice".to_string()))?;
+        btree
+            .scoped("t2/")
+            .insert(KeyValuePair::new("user".to_string(), "bob".to_string()))?;
+
+        let kv = btree.scoped("t1/").search("user".to_string())?;
+        assert_eq!(kv.key, "user");
+        assert_eq!(kv.value, "alice");
+        let kv = btree.scoped("t2/").search("user".to_string())?;
+        assert_eq!(kv.value, "bob");
+
+        // The underlying tree sees the prefixed keys.
+        assert_eq!(btree.search("t1/user".to_string())?.value, "alice");
+        assert!(btree.scoped("t3/").search("user".to_string()).is_err());
+        assert_eq!(btree.scoped("t1/").get("user")?.unwrap().value, "alice");
+        assert!(btree.scoped("t1/").get("group")?.is_none());
+
+        for key in ["a", "b", "c"] {
+            btree
+                .scoped("t1/")
+                .insert(KeyValuePair::new(key.to_string(), key.to_string()))?;
+        }
+        // Scans stay within the namespace and return unprefixed keys.
+        let keys = |scan: ScopedScan| -> Result<Vec<String>, Error> {
+            scan.map(|kv| kv.map(|kv| kv.key)).collect()
+        };
+        assert_eq!(
+            keys(btree.scoped("t1/").scan(..))?,
+            vec!["a", "b", "c", "user"]
+        );
+        assert_eq!(
+            keys(btree.scoped("t1/").scan("b".to_string()..))?,
+            vec!["b", "c", "user"]
+        );
+        assert_eq!(
+            keys(btree.scoped("t1/").scan(.."c".to_string()))?,
+            vec!["a", "b"]
+        );
+        assert_eq!(keys(btree.scoped("t2/").scan(..))?, vec!["user"]);
+        Ok(())
+    }
+}
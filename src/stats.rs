@@ -0,0 +1,313 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{NodeType, Offset};
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// IoStats is a snapshot of the cumulative I/O performed by a BTree.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct IoStats {
+  /// Bytes of keys and values handed to the tree by the user.
+  pub logical_bytes: usize,
+  /// Bytes of pages written to the tree file.
+  pub physical_bytes: usize,
+}
+
+impl IoStats {
+  /// write_amplification returns the number of physical bytes written
+  /// for every logical byte, or 0 if nothing was written yet.
+  pub fn write_amplification(&self) -> f64 {
+    if self.logical_bytes == 0 {
+      return 0.0;
+    }
+    self.physical_bytes as f64 / self.logical_bytes as f64
+  }
+
+  /// since returns the I/O performed between an earlier snapshot and this one,
+  /// allowing the ratio to be tracked over time windows.
+  pub fn since(&self, earlier: &IoStats) -> IoStats {
+    IoStats {
+      logical_bytes: self.logical_bytes - earlier.logical_bytes,
+      physical_bytes: self.physical_bytes - earlier.physical_bytes,
+    }
+  }
+}
+
+/// LifetimeStats are counters accumulated over the whole life of a tree file.
+/// They are persisted in the header, so they survive reopening the tree.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct LifetimeStats {
+  /// Number of inserted, updated and deleted pairs.
+  pub writes: usize,
+  /// Bytes of keys and values handed to the tree by the user.
+  pub logical_bytes: usize,
+  /// Bytes of pages written to the tree file.
+  pub physical_bytes: usize,
+  /// Number of times the file was replaced by a compacted copy, see BTree::replace_with.
+  pub compactions: usize,
+  /// Number of garbage collections that reclaimed pages leaked by an interrupted
+  /// operation, see BTree::gc.
+  pub recoveries: usize,
+}
+
+/// LeafHeat is the sampled number of accesses to a single leaf.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LeafHeat {
+  pub offset: Offset,
+  /// Smallest and largest keys currently stored in the leaf, None if it is empty.
+  pub range: Option<(String, String)>,
+  pub reads: usize,
+  pub writes: usize,
+}
+
+/// PageKind is the kind of node stored in a page.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PageKind {
+  Internal,
+  Leaf,
+}
+
+/// PageInfo describes the physical layout of a single page reachable from the root.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PageInfo {
+  pub offset: Offset,
+  pub kind: PageKind,
+  /// Number of keys in the node, separators for internal nodes and pairs for leaves.
+  pub keys: usize,
+  /// Fraction of the node's capacity of 2b - 1 keys in use.
+  pub fill: f64,
+  /// Offset of the node pointing at this one, None for the root.
+  pub parent: Option<Offset>,
+}
+
+/// Pages walks every page reachable from the root of a tree, parents before
+/// their children and siblings from left to right, as returned by BTree::pages.
+pub struct Pages<'a> {
+  tree: &'a mut BTree,
+  /// Pages still to visit along with their parent, the next one last.
+  stack: Vec<(Offset, Option<Offset>)>,
+  capacity: usize,
+}
+
+impl<'a> Pages<'a> {
+  pub fn new(tree: &'a mut BTree, b: usize) -> Pages<'a> {
+    let stack = vec![(tree.root_offset(), None)];
+    Pages {
+      tree,
+      stack,
+      capacity: 2 * b - 1,
+    }
+  }
+}
+
+impl<'a> Iterator for Pages<'a> {
+  type Item = Result<PageInfo, Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let (offset, parent) = self.stack.pop()?;
+    let node = match self.tree.read_node(&offset) {
+      Ok(node) => node,
+      Err(e) => {
+        self.stack.clear();
+        return Some(Err(e));
+      }
+    };
+    let (kind, keys) = match node.node_type {
+      NodeType::Internal(children, keys) => {
+        self.stack.extend(
+          children
+            .into_iter()
+            .rev()
+            .map(|child| (child, Some(offset))),
+        );
+        (PageKind::Internal, keys.len())
+      }
+      NodeType::Leaf(pairs) => (PageKind::Leaf, pairs.len()),
+      NodeType::Unexpected => {
+        self.stack.clear();
+        return Some(Err(Error::UnexpectedError));
+      }
+    };
+    Some(Ok(PageInfo {
+      offset,
+      kind,
+      keys,
+      fill: keys as f64 / self.capacity as f64,
+      parent,
+    }))
+  }
+}
+
+/// AccessSampler counts one of every rate leaf accesses, per leaf.
+/// A rate of 0 disables sampling.
+#[derive(Clone, Debug, Default)]
+pub struct AccessSampler {
+  rate: usize,
+  accesses: usize,
+  /// Sampled (reads, writes) per leaf offset.
+  leaves: HashMap<Offset, (usize, usize)>,
+}
+
+impl AccessSampler {
+  pub fn new(rate: usize) -> AccessSampler {
+    AccessSampler {
+      rate,
+      ..AccessSampler::default()
+    }
+  }
+
+  pub fn record_read(&mut self, offset: &Offset) {
+    if self.sample() {
+      self.leaves.entry(*offset).or_default().0 += 1;
+    }
+  }
+
+  pub fn record_write(&mut self, offset: &Offset) {
+    if self.sample() {
+      self.leaves.entry(*offset).or_default().1 += 1;
+    }
+  }
+
+  /// reset forgets every sampled access.
+  pub fn reset(&mut self) {
+    self.accesses = 0;
+    self.leaves.clear();
+  }
+
+  /// leaves returns the sampled (offset, reads, writes) of every accessed leaf.
+  pub fn leaves(&self) -> impl Iterator<Item = (&Offset, usize, usize)> {
+    self
+      .leaves
+      .iter()
+      .map(|(offset, (reads, writes))| (offset, *reads, *writes))
+  }
+
+  fn sample(&mut self) -> bool {
+    if self.rate == 0 {
+      return false;
+    }
+    self.accesses += 1;
+    self.accesses.is_multiple_of(self.rate)
+  }
+}
+
+/// PrefixRate is the estimated number and rate of writes to keys sharing a prefix.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PrefixRate {
+  pub prefix: String,
+  /// Estimated writes since tracking started.
+  pub writes: usize,
+  pub per_second: f64,
+}
+
+/// WriteRates counts one of every sample_rate writes per key prefix, the prefix being
+/// the key up to and including the first delimiter, or the whole key without one.
+/// At most max_prefixes are tracked: a new prefix evicts the least written one and
+/// inherits its count, so that heavy writers are never undercounted.
+#[derive(Clone, Debug)]
+pub struct WriteRates {
+  sample_rate: usize,
+  max_prefixes: usize,
+  delimiter: char,
+  writes: usize,
+  started: Instant,
+  counts: HashMap<String, usize>,
+}
+
+impl WriteRates {
+  pub fn new(sample_rate: usize, max_prefixes: usize, delimiter: char) -> WriteRates {
+    WriteRates {
+      sample_rate: sample_rate.max(1),
+      max_prefixes: max_prefixes.max(1),
+      delimiter,
+      writes: 0,
+      started: Instant::now(),
+      counts: HashMap::new(),
+    }
+  }
+
+  pub fn record_write(&mut self, key: &str) {
+    self.writes += 1;
+    if !self.writes.is_multiple_of(self.sample_rate) {
+      return;
+    }
+    let prefix = match key.find(self.delimiter) {
+      Some(idx) => &key[..idx + self.delimiter.len_utf8()],
+      None => key,
+    };
+    if let Some(count) = self.counts.get_mut(prefix) {
+      *count += 1;
+      return;
+    }
+    let mut count = 1;
+    if self.counts.len() >= self.max_prefixes {
+      let evicted = self
+        .counts
+        .iter()
+        .min_by_key(|(_, count)| **count)
+        .map(|(prefix, count)| (prefix.clone(), *count));
+      if let Some((evicted, evicted_count)) = evicted {
+        self.counts.remove(&evicted);
+        count += evicted_count;
+      }
+    }
+    self.counts.insert(prefix.to_string(), count);
+  }
+
+  /// reset forgets every sampled write and restarts the rate measurement.
+  pub fn reset(&mut self) {
+    self.writes = 0;
+    self.started = Instant::now();
+    self.counts.clear();
+  }
+
+  /// rates returns the estimated writes of every tracked prefix, busiest first.
+  pub fn rates(&self) -> Vec<PrefixRate> {
+    let elapsed = self.started.elapsed().as_secs_f64();
+    let mut rates: Vec<PrefixRate> = self
+      .counts
+      .iter()
+      .map(|(prefix, count)| {
+        let writes = count * self.sample_rate;
+        PrefixRate {
+          prefix: prefix.clone(),
+          writes,
+          per_second: if elapsed > 0.0 {
+            writes as f64 / elapsed
+          } else {
+            0.0
+          },
+        }
+      })
+      .collect();
+    rates.sort_by_key(|rate| (cmp::Reverse(rate.writes), rate.prefix.clone()));
+    rates
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #[test]
+  fn write_rates_bound_tracked_prefixes() {
+    use crate::stats::WriteRates;
+
+    let mut rates = WriteRates::new(1, 2, ':');
+    for _ in 0..10 {
+      rates.record_write("noisy:key");
+    }
+    for _ in 0..3 {
+      rates.record_write("quiet:key");
+    }
+    rates.record_write("new:key");
+    rates.record_write("nodelim");
+
+    let rates = rates.rates();
+    assert_eq!(rates.len(), 2);
+    assert_eq!(rates[0].prefix, "noisy:");
+    assert_eq!(rates[0].writes, 10);
+    // Evicted counts carry over to the prefix replacing them.
+    assert_eq!(rates[1].prefix, "nodelim");
+    assert_eq!(rates[1].writes, 5);
+  }
+}
@@ -0,0 +1,202 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{Key, KeyValuePair};
+use crate::scan::{KeyRange, Scan};
+use std::ops::RangeBounds;
+
+/// TieredTree layers a hot tree receiving all writes over read-only archive trees.
+/// Reads consult the hot tree first and then the archives from newest to oldest,
+/// so a key in a newer tier shadows the same key in older ones.
+/// Archived pairs are never modified, so keys held by an archive cannot be deleted.
+pub struct TieredTree {
+    hot: BTree,
+    /// Archive trees, newest first.
+    archives: Vec<BTree>,
+}
+
+impl TieredTree {
+    pub fn new(hot: BTree) -> TieredTree {
+        TieredTree {
+            hot,
+            archives: Vec::new(),
+        }
+    }
+
+    /// add_archive attaches an existing archive tree, older than the ones already attached.
+    pub fn add_archive(&mut self, archive: BTree) {
+        self.archives.push(archive);
+    }
+
+    pub fn hot(&mut self) -> &mut BTree {
+        &mut self.hot
+    }
+
+    pub fn archives(&mut self) -> &mut [BTree] {
+        &mut self.archives
+    }
+
+    /// insert writes to the hot tree, returning the pair it replaced there.
+    /// Older versions of the pair in the archives are shadowed, not returned.
+    pub fn insert(&mut self, kv: KeyValuePair) -> Result<Option<KeyValuePair>, Error> {
+        self.hot.insert(kv)
+    }
+
+    pub fn search(&mut self, key: String) -> Result<KeyValuePair, Error> {
+        for tree in Some(&mut self.hot)
+            .into_iter()
+            .chain(self.archives.iter_mut())
+        {
+            match tree.search(key.clone()) {
+                Err(Error::KeyNotFound) => continue,
+                res => return res,
+            }
+        }
+        Err(Error::KeyNotFound)
+    }
+
+    /// delete removes key from the hot tree. It fails with Error::Archived if any archive
+    /// holds key, even when the hot tree shadows it, as deleting the hot pair would bring
+    /// the archived one back.
+    pub fn delete(&mut self, key: Key) -> Result<(), Error> {
+        for archive in self.archives.iter_mut() {
+            match archive.search(key.0.clone()) {
+                Ok(_) => return Err(Error::Archived { key: key.0 }),
+                Err(Error::KeyNotFound) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.hot.delete(key)
+    }
+
+    /// scan returns the pairs of all tiers within range in ascending key order,
+    /// taking every key from the newest tier holding it.
+    pub fn scan<R: RangeBounds<String>>(&mut self, range: R) -> TieredScan<'_> {
+        let range: KeyRange = (range.start_bound().cloned(), range.end_bound().cloned());
+        let mut scans = vec![self.hot.scan(range.clone())];
+        for archive in self.archives.iter_mut() {
+            scans.push(archive.scan(range.clone()));
+        }
+        TieredScan::new(scans)
+    }
+
+    /// archive_before moves every pair of the hot tree with a key before key into archive,
+    /// which becomes the newest archive tier. Returns the number of pairs moved.
+    pub fn archive_before(&mut self, key: &str, mut archive: BTree) -> Result<usize, Error> {
+        let range = ..key.to_string();
+        let moved = self.hot.copy_range(range.clone(), &mut archive)?;
+        self.hot.update_range(range, |_, _| None)?;
+        self.archives.insert(0, archive);
+        Ok(moved)
+    }
+}
+
+/// TieredScan merges scans of several tiers ordered from newest to oldest.
+pub struct TieredScan<'a> {
+    scans: Vec<Scan<'a>>,
+    /// Next pair of every scan, in the same order.
+    heads: Vec<Option<KeyValuePair>>,
+}
+
+impl<'a> TieredScan<'a> {
+    fn new(scans: Vec<Scan<'a>>) -> TieredScan<'a> {
+        let heads = scans.iter().map(|_| None).collect();
+        TieredScan { scans, heads }
+    }
+
+    fn next_pair(&mut self) -> Result<Option<KeyValuePair>, Error> {
+        for (scan, head) in self.scans.iter_mut().zip(self.heads.iter_mut()) {
+            if head.is_none() {
+                *head = scan.next().transpose()?;
+            }
+        }
+        let min_key = match self.heads.iter().flatten().map(|kv| &kv.key).min() {
+            Some(key) => key.clone(),
+            None => return Ok(None),
+        };
+        // Consume the key from every tier, keeping the pair of the newest one.
+        let mut next = None;
+        for head in self.heads.iter_mut() {
+            if head.as_ref().is_some_and(|kv| kv.key == min_key) {
+                let kv = head.take();
+                if next.is_none() {
+                    next = kv;
+                }
+            }
+        }
+        Ok(next)
+    }
+}
+
+impl<'a> Iterator for TieredScan<'a> {
+    type Item = Result<KeyValuePair, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pair().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn tiered_tree_reads_across_archives() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::tiered::TieredTree;
+        use std::path::Path;
+
+        let hot = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_tiered_hot"))
+            .b_parameter(2)
+            .build()?;
+        let archive = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_tiered_archive"))
+            .b_parameter(2)
+            .build()?;
+        let mut tiered = TieredTree::new(hot);
+        for i in 0..10 {
+            tiered.insert(KeyValuePair::new(
+                format!("2024-{:02}", i),
+                format!("v{}", i),
+            ))?;
+        }
+
+        assert_eq!(tiered.archive_before("2024-05", archive)?, 5);
+        assert!(tiered.hot().search("2024-02".to_string()).is_err());
+        assert_eq!(tiered.search("2024-02".to_string())?.value, "v2");
+        assert_eq!(tiered.search("2024-07".to_string())?.value, "v7");
+
+        // A newer pair in the hot tree shadows the archived one.
+        tiered.insert(KeyValuePair::new("2024-03".to_string(), "new".to_string()))?;
+        let pairs = tiered
+            .scan("2024-02".to_string().."2024-06".to_string())
+            .map(|kv| kv.map(|kv| (kv.key, kv.value)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let expected: Vec<(String, String)> = vec![
+            ("2024-02", "v2"),
+            ("2024-03", "new"),
+            ("2024-04", "v4"),
+            ("2024-05", "v5"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(pairs, expected);
+
+        // Archived keys cannot be deleted, shadowed or not.
+        for key in ["2024-02", "2024-03"] {
+            assert!(matches!(
+                tiered.delete(Key(key.to_string())),
+                Err(Error::Archived { .. })
+            ));
+        }
+        assert_eq!(tiered.search("2024-03".to_string())?.value, "new");
+        tiered.delete(Key("2024-07".to_string()))?;
+        assert!(matches!(
+            tiered.search("2024-07".to_string()),
+            Err(Error::KeyNotFound)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::KeyValuePair;
+use crate::record_log::{self, RecordLog};
+use std::time::Duration;
+
+/// TrashedPair is a pair deleted from a tree with a Trash attached.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TrashedPair {
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch.
+    pub deleted_at: u64,
+    pub kv: KeyValuePair,
+}
+
+/// Trash keeps the pairs deleted from a tree in a separate tree, see BTree::set_trash,
+/// until they are restored with BTree::restore or purged.
+pub struct Trash {
+    log: RecordLog,
+}
+
+impl Trash {
+    /// new opens a trash stored in tree.
+    pub fn new(tree: BTree) -> Result<Trash, Error> {
+        Ok(Trash {
+            log: RecordLog::new(tree)?,
+        })
+    }
+
+    pub(crate) fn put(&mut self, kv: &KeyValuePair) -> Result<(), Error> {
+        let encoded = format!(
+            "{:x}|{}|{}{}",
+            record_log::now_millis(),
+            kv.key.len(),
+            kv.key,
+            kv.value
+        );
+        self.log.append(&encoded)?;
+        Ok(())
+    }
+
+    /// list returns every trashed pair, oldest first.
+    pub fn list(&mut self) -> Result<Vec<TrashedPair>, Error> {
+        self.log
+            .records()?
+            .into_iter()
+            .map(|(seq, encoded)| decode(seq, &encoded))
+            .collect()
+    }
+
+    /// latest returns the most recently trashed pair stored under key,
+    /// reading the trash from the newest pair back.
+    pub fn latest(&mut self, key: &str) -> Result<TrashedPair, Error> {
+        for record in self.log.iter_rev() {
+            let (seq, encoded) = record?;
+            let trashed = decode(seq, &encoded)?;
+            if trashed.kv.key == key {
+                return Ok(trashed);
+            }
+        }
+        Err(Error::KeyNotFound)
+    }
+
+    /// remove permanently removes the trashed pair with sequence number seq.
+    pub fn remove(&mut self, seq: u64) -> Result<(), Error> {
+        self.log.remove(seq)
+    }
+
+    /// purge_older_than permanently removes the pairs deleted more than age ago,
+    /// returning the number of pairs purged.
+    pub fn purge_older_than(&mut self, age: Duration) -> Result<usize, Error> {
+        let cutoff = record_log::now_millis().saturating_sub(age.as_millis() as u64);
+        // Pairs are trashed in time order, the first one deleted after cutoff ends the search.
+        let mut expired = Vec::new();
+        for record in self.log.iter() {
+            let (seq, encoded) = record?;
+            if decode(seq, &encoded)?.deleted_at >= cutoff {
+                break;
+            }
+            expired.push(seq);
+        }
+        for seq in expired.iter() {
+            self.remove(*seq)?;
+        }
+        Ok(expired.len())
+    }
+
+    pub fn into_inner(self) -> BTree {
+        self.log.into_inner()
+    }
+}
+
+fn decode(seq: u64, encoded: &str) -> Result<TrashedPair, Error> {
+    let mut fields = encoded.splitn(3, '|');
+    let mut next = || fields.next().ok_or(Error::UnexpectedError);
+    let deleted_at = u64::from_str_radix(next()?, 16).map_err(|_| Error::UnexpectedError)?;
+    let key_len: usize = next()?.parse().map_err(|_| Error::UnexpectedError)?;
+    let rest = next()?;
+    let key = rest.get(..key_len).ok_or(Error::UnexpectedError)?;
+    Ok(TrashedPair {
+        seq,
+        deleted_at,
+        kv: KeyValuePair::new(key.to_string(), rest[key_len..].to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn trash_restores_and_purges_deleted_pairs() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::trash::Trash;
+        use std::path::Path;
+        use std::thread;
+        use std::time::Duration;
+
+        let mut btree = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_soft_delete"))
+            .b_parameter(2)
+            .build()?;
+        let trash = BTreeBuilder::new()
+            .path(Path::new("/tmp/db_soft_delete_trash"))
+            .b_parameter(2)
+            .build()?;
+        btree.set_trash(Trash::new(trash)?);
+        for i in 0..5 {
+            btree.insert(KeyValuePair::new(format!("k{}", i), format!("v{}", i)))?;
+        }
+
+        btree.delete(Key("k1".to_string()))?;
+        btree.delete(Key("k2".to_string()))?;
+        assert!(btree.search("k1".to_string()).is_err());
+        assert_eq!(btree.trash().unwrap().list()?.len(), 2);
+
+        btree.restore("k1")?;
+        assert_eq!(btree.search("k1".to_string())?.value, "v1");
+        assert!(matches!(btree.restore("k1"), Err(Error::KeyAlreadyExists)));
+        assert!(matches!(btree.restore("k9"), Err(Error::KeyNotFound)));
+
+        thread::sleep(Duration::from_millis(50));
+        btree.delete(Key("k3".to_string()))?;
+        let trash = btree.trash().unwrap();
+        assert_eq!(trash.purge_older_than(Duration::from_millis(40))?, 1);
+        let remaining: Vec<String> = trash.list()?.into_iter().map(|t| t.kv.key).collect();
+        assert_eq!(remaining, vec!["k3".to_string()]);
+
+        // The newest of several trashed pairs under a key wins.
+        btree.insert(KeyValuePair::new("k3".to_string(), "v3b".to_string()))?;
+        btree.delete(Key("k3".to_string()))?;
+        let latest = btree.trash().unwrap().latest("k3")?;
+        assert_eq!(latest.kv.value, "v3b");
+
+        // Pairs removed through update_range go to the trash as well.
+        assert_eq!(
+            btree.update_range("k4".to_string()..="k4".to_string(), |_, _| None)?,
+            1
+        );
+        assert!(btree.get("k4")?.is_none());
+        btree.restore("k4")?;
+        assert_eq!(btree.search("k4".to_string())?.value, "v4");
+        Ok(())
+    }
+}
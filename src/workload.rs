@@ -0,0 +1,28 @@
+use crate::btree::MAX_BRANCHING_FACTOR;
+
+/// Workload names an access pattern that BTreeBuilder::tuned_for picks defaults for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Workload {
+    /// Mostly single key reads. Every visited node is deserialized in full,
+    /// so small nodes make each level of a descent cheap.
+    PointLookup,
+    /// Mostly ordered scans over many pairs. Large nodes keep the file compact
+    /// and the number of leaves to visit low.
+    RangeScan,
+    /// Mostly inserts and deletes. Mid-sized nodes balance the cost of a descent
+    /// against the bytes written by splits.
+    WriteHeavy,
+}
+
+impl Workload {
+    /// b_parameter returns the b parameter suited to the workload.
+    pub fn b_parameter(&self) -> usize {
+        let b = match self {
+            Workload::PointLookup => 10,
+            Workload::RangeScan => 50,
+            Workload::WriteHeavy => 25,
+        };
+        // Smaller pages cap b.
+        b.min(MAX_BRANCHING_FACTOR / 2)
+    }
+}
@@ -0,0 +1,22 @@
+/// WriteMode decides whether pairs already stored in the tree may change.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum WriteMode {
+    /// Pairs may be overwritten and deleted.
+    #[default]
+    Mutable,
+    /// Inserting an existing key fails with Error::KeyAlreadyExists and updating a value
+    /// fails with Error::Immutable, pairs may still be deleted.
+    WriteOnce,
+    /// Like WriteOnce, and deleting fails with Error::Immutable as well.
+    AppendOnly,
+}
+
+impl WriteMode {
+    pub fn allows_overwrite(&self) -> bool {
+        *self == WriteMode::Mutable
+    }
+
+    pub fn allows_delete(&self) -> bool {
+        *self != WriteMode::AppendOnly
+    }
+}